@@ -8,7 +8,7 @@ pub use operations::*;
 pub use policy::*;
 pub use state::*;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use anyhow::{bail, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
@@ -16,7 +16,7 @@ use serde_json::json;
 use valico::json_schema;
 
 use iop_coeus_proto::*;
-use iop_journal_proto::{BlockHeight, Nonce};
+use iop_journal_proto::{BlockCount, BlockHeight, Nonce};
 use iop_keyvault::multicipher::MPublicKey;
 #[cfg(feature = "did")]
 use iop_morpheus_proto::data::Did;
@@ -8,10 +8,50 @@ pub struct TxnStatus {
     pub success: bool,
 }
 
+/// Describes the change that could not be applied or reverted consistently.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CorruptionDiagnostic {
+    pub height: BlockHeight,
+    pub mutation: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Recovery {
+    /// All changes up to and including this height are kept.
+    pub restored_height: BlockHeight,
+    /// The caller must apply all blocks again starting from this height.
+    pub replay_from_height: BlockHeight,
+    pub diagnostic: CorruptionDiagnostic,
+}
+
+/// Copies of the state taken periodically when a new block is applied, so a corrupted state can be
+/// restored without replaying all blocks.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Checkpoints {
+    interval: BlockCount,
+    max_count: usize,
+    states: VecDeque<State>,
+}
+
+impl Default for Checkpoints {
+    fn default() -> Self {
+        Self {
+            interval: State::DEFAULT_CHECKPOINT_INTERVAL,
+            max_count: State::DEFAULT_MAX_CHECKPOINTS,
+            states: Default::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct State {
-    corrupted: bool,
+    corruption: Option<CorruptionDiagnostic>,
+    #[serde(skip)]
+    checkpoints: Checkpoints,
     root: Domain,
     last_seen_height: BlockHeight,
     version_of_first_undo_operation: Version,
@@ -23,7 +63,8 @@ pub struct State {
 impl Default for State {
     fn default() -> Self {
         Self {
-            corrupted: false,
+            corruption: None,
+            checkpoints: Default::default(),
             root: Domain::new_root(),
             last_seen_height: Default::default(),
             version_of_first_undo_operation: Default::default(),
@@ -35,21 +76,54 @@ impl Default for State {
 }
 
 impl State {
+    pub const DEFAULT_CHECKPOINT_INTERVAL: BlockCount = 100;
+    pub const DEFAULT_MAX_CHECKPOINTS: usize = 10;
+
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// A checkpoint is kept in memory at every `interval`th block, only the last `max_count`
+    /// are kept. An `interval` of 0 disables checkpoints.
+    pub fn with_checkpoints(interval: BlockCount, max_count: usize) -> Self {
+        let checkpoints = Checkpoints { interval, max_count, states: Default::default() };
+        Self { checkpoints, ..Default::default() }
+    }
+
     pub fn is_corrupted(&self) -> bool {
-        self.corrupted
+        self.corruption.is_some()
+    }
+
+    pub fn corruption_diagnostic(&self) -> Option<&CorruptionDiagnostic> {
+        self.corruption.as_ref()
     }
 
     pub fn ensure_not_corrupted(&self) -> Result<()> {
-        if self.corrupted {
+        if self.is_corrupted() {
             bail!("Coeus state is corrupt. All incoming changes will be ignored.");
         }
         Ok(())
     }
 
+    /// Heights of the kept checkpoints in increasing order.
+    pub fn checkpoint_heights(&self) -> Vec<BlockHeight> {
+        self.checkpoints.states.iter().map(|s| s.last_seen_height).collect()
+    }
+
+    /// Restores the latest checkpoint of a corrupted state, or the empty state if there is none.
+    /// The caller must replay all blocks from the returned height.
+    pub fn recover(&mut self) -> Result<Recovery> {
+        let diagnostic = match self.corruption.take() {
+            Some(diagnostic) => diagnostic,
+            None => bail!("Coeus state is not corrupt, there is nothing to recover"),
+        };
+        let checkpoints = std::mem::take(&mut self.checkpoints);
+        let restored = checkpoints.states.back().cloned().unwrap_or_default();
+        *self = Self { checkpoints, ..restored };
+        let restored_height = self.last_seen_height;
+        Ok(Recovery { restored_height, replay_from_height: restored_height + 1, diagnostic })
+    }
+
     pub fn root(&self) -> &Domain {
         &self.root
     }
@@ -68,13 +142,19 @@ impl State {
 
     pub fn block_applying(&mut self, height: BlockHeight) -> Result<()> {
         self.ensure_not_corrupted()?;
+        let interval = self.checkpoints.interval;
+        if interval > 0 && height % interval == 0 {
+            self.take_checkpoint();
+        }
         self.apply_operations(vec![SystemOperation::start_block(height)]).map(|_version| ())
     }
 
     pub fn block_reverted(&mut self, height: BlockHeight) -> Result<()> {
         self.ensure_not_corrupted()?;
         let height_before_revert = self.last_seen_height;
-        self.set_corrupted_on_err(|state| {
+        // Checkpoints containing changes of the reverted block are not valid anymore
+        self.checkpoints.states.retain(|s| s.last_seen_height < height);
+        self.set_corrupted_on_err(format!("Reverting block {}", height), |state| {
             ensure!(
                 height_before_revert == height,
                 "Cannot revert block at height {}, because currently the state is at height {}",
@@ -106,7 +186,8 @@ impl State {
 
     pub fn revert_transaction(&mut self, txid: &str, asset: CoeusAsset) -> Result<()> {
         self.ensure_not_corrupted()?;
-        self.set_corrupted_on_err(|state| match state.txn_statuses.remove(txid) {
+        let mutation = format!("Reverting transaction {}", txid);
+        self.set_corrupted_on_err(mutation, |state| match state.txn_statuses.remove(txid) {
             None => {
                 bail!("Transaction has not been applied previously.");
             }
@@ -165,8 +246,9 @@ impl State {
         });
         match res {
             Err(e) => {
-                // TODO Corrupt state if next line fails
-                undos.drain(..).rev().try_for_each(|op| op.execute(self))?;
+                self.set_corrupted_on_err("Undoing failed operations".to_owned(), |state| {
+                    undos.drain(..).rev().try_for_each(|op| op.execute(state))
+                })?;
                 Err(e)
             }
             Ok(_) => {
@@ -226,16 +308,34 @@ impl State {
         Ok(())
     }
 
-    fn set_corrupted_on_err<R>(&mut self, func: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+    fn set_corrupted_on_err<R>(
+        &mut self, mutation: String, func: impl FnOnce(&mut Self) -> Result<R>,
+    ) -> Result<R> {
+        let height = self.last_seen_height;
         match func(self) {
             Err(e) => {
-                self.corrupted = true;
+                // Nested calls overwrite the diagnostic, so the outermost mutation is reported
+                let error = format!("{:#}", e);
+                self.corruption = Some(CorruptionDiagnostic { height, mutation, error });
                 Err(e)
             }
             Ok(r) => Ok(r),
         }
     }
 
+    fn take_checkpoint(&mut self) {
+        // Checkpoints do not contain further checkpoints
+        let checkpoints = std::mem::take(&mut self.checkpoints);
+        let copy = self.clone();
+        self.checkpoints = checkpoints;
+
+        let states = &mut self.checkpoints.states;
+        states.push_back(copy);
+        while states.len() > self.checkpoints.max_count {
+            states.pop_front();
+        }
+    }
+
     fn undo_operations(&mut self, to_version: Version) -> Result<()> {
         for version in (to_version..self.version()).rev() {
             self.undo_operation(version)?;
@@ -244,7 +344,8 @@ impl State {
     }
 
     fn undo_operation(&mut self, to_version: Version) -> Result<()> {
-        self.set_corrupted_on_err(|state| {
+        let mutation = format!("Undoing operation to version {}", to_version);
+        self.set_corrupted_on_err(mutation, |state| {
             let undo_op = state
                 .undo_operations
                 .pop()
@@ -687,4 +788,42 @@ mod test {
         check_domain_exists(&state, &domain_name(), &data("cool, heh?"), &domain_owner());
         name_resolves_to(&state, &domain_name(), &data("cool, heh?")).unwrap();
     }
+
+    #[test]
+    fn recover_restores_latest_checkpoint() {
+        let mut state = State::with_checkpoints(2, 10);
+        state.block_applying(1).unwrap();
+        state.block_applying(2).unwrap();
+        state.block_applying(3).unwrap();
+        state.block_applying(4).unwrap();
+        assert_eq!(state.checkpoint_heights(), vec![1, 3]);
+
+        // Checkpoint at height 3 does not contain changes of block 4
+        state.block_reverted(4).unwrap();
+        assert_eq!(state.checkpoint_heights(), vec![1, 3]);
+        state.block_reverted(3).unwrap();
+        assert_eq!(state.checkpoint_heights(), vec![1]);
+        state.block_applying(3).unwrap();
+
+        state.block_reverted(5).unwrap_err();
+        assert!(state.is_corrupted());
+        state.block_applying(4).unwrap_err();
+
+        let recovery = state.recover().unwrap();
+        assert_eq!(recovery.restored_height, 1);
+        assert_eq!(recovery.replay_from_height, 2);
+        assert_eq!(recovery.diagnostic.height, 3);
+        assert_eq!(recovery.diagnostic.mutation, "Reverting block 5");
+        assert_eq!(
+            recovery.diagnostic.error,
+            "Cannot revert block at height 5, because currently the state is at height 3"
+        );
+        assert!(!state.is_corrupted());
+        assert_eq!(state.last_seen_height(), 1);
+        assert_eq!(state.version(), 1);
+        assert_eq!(state.checkpoint_heights(), vec![1]);
+
+        state.recover().unwrap_err();
+        state.block_applying(2).unwrap();
+    }
 }
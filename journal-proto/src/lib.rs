@@ -10,5 +10,7 @@ use varint::VarintWrite; // VarintRead
 pub type BlockHeight = u32;
 /// Duration (e.g. a year) expressed as an expected number of blocks on the ledger, approximating the duration.  
 pub type BlockCount = u32;
+/// Seconds elapsed since the Unix epoch, e.g. the time a block was forged at.
+pub type Timestamp = i64;
 /// A unique value attached to requests as protection from replay attacks.
 pub type Nonce = u64;
@@ -0,0 +1,61 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockTime {
+    height: BlockHeight,
+    timestamp: Timestamp,
+}
+
+/// Times of the blocks, so DID documents can be queried by wall-clock time. Both heights and
+/// timestamps are strictly increasing, so lookups in either direction are binary searches.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(super) struct BlockTimesState {
+    blocks: im::Vector<BlockTime>,
+}
+
+impl BlockTimesState {
+    pub fn time_of(&self, height: BlockHeight) -> Option<Timestamp> {
+        let idx = self.blocks.binary_search_by_key(&height, |b| b.height).ok()?;
+        Some(self.blocks[idx].timestamp)
+    }
+
+    /// The last block forged at or before the given time.
+    pub fn height_at(&self, timestamp: Timestamp) -> Option<BlockHeight> {
+        let idx = match self.blocks.binary_search_by_key(&timestamp, |b| b.timestamp) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        Some(self.blocks[idx].height)
+    }
+
+    pub fn check(&self, height: BlockHeight, timestamp: Timestamp) -> Result<()> {
+        if let Some(last) = self.blocks.back() {
+            ensure!(
+                last.height < height && last.timestamp < timestamp,
+                "Block {} at time {} must follow block {} at time {}",
+                height,
+                timestamp,
+                last.height,
+                last.timestamp
+            );
+        }
+        Ok(())
+    }
+
+    pub fn apply(&mut self, height: BlockHeight, timestamp: Timestamp) -> Result<()> {
+        self.check(height, timestamp)?;
+        self.blocks.push_back(BlockTime { height, timestamp });
+        Ok(())
+    }
+
+    /// Forgets the times of all blocks at or above `height`.
+    pub fn revert_from(&mut self, height: BlockHeight) {
+        let idx = match self.blocks.binary_search_by_key(&height, |b| b.height) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        self.blocks.truncate(idx);
+    }
+}
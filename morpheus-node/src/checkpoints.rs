@@ -0,0 +1,55 @@
+use super::*;
+
+/// Copies of the state taken periodically when a new block is applied, so a corrupted state can be
+/// restored without replaying all blocks. Taking a copy is cheap thanks to structural sharing.
+#[derive(Debug, Clone)]
+pub(super) struct Checkpoints {
+    interval: BlockCount,
+    max_count: usize,
+    states: VecDeque<State>,
+}
+
+impl Default for Checkpoints {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_INTERVAL, Self::DEFAULT_MAX_COUNT)
+    }
+}
+
+impl Checkpoints {
+    pub const DEFAULT_INTERVAL: BlockCount = 100;
+    pub const DEFAULT_MAX_COUNT: usize = 10;
+
+    pub fn new(interval: BlockCount, max_count: usize) -> Self {
+        Self { interval, max_count, states: Default::default() }
+    }
+
+    pub fn heights(&self) -> Vec<BlockHeight> {
+        self.states.iter().map(|s| s.last_seen_height()).collect()
+    }
+
+    pub fn latest(&self) -> Option<&State> {
+        self.states.back()
+    }
+
+    /// `state` must contain all changes of blocks below `height`
+    pub fn block_applying(&mut self, height: BlockHeight, state: &State) {
+        if self.interval > 0 && height % self.interval == 0 {
+            self.push(state);
+        }
+    }
+
+    /// Forgets all checkpoints that contain changes of the reverted block
+    pub fn block_reverting(&mut self, height: BlockHeight) {
+        self.states.retain(|s| s.last_seen_height() < height);
+    }
+
+    pub fn push(&mut self, state: &State) {
+        if self.latest().map(|s| s.last_seen_height()) == Some(state.last_seen_height()) {
+            self.states.pop_back();
+        }
+        self.states.push_back(state.to_owned());
+        while self.states.len() > self.max_count {
+            self.states.pop_front();
+        }
+    }
+}
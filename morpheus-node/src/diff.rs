@@ -0,0 +1,70 @@
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum DidChangeKind {
+    KeyAdded {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+    },
+    KeyExpired {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+    },
+    KeyRevoked {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+    },
+    /// The expiry of the key was extended, shortened or removed.
+    KeyExpiryChanged {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+        #[serde(rename = "expiresAtHeight", skip_serializing_if = "Option::is_none", default)]
+        expires_at_height: Option<BlockHeight>,
+    },
+    KeySuspended {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+    },
+    KeyResumed {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+    },
+    RightChanged {
+        #[serde(rename = "keyLink")]
+        key_link: String,
+        auth: Authentication,
+        right: Right,
+        valid: bool,
+    },
+    Tombstoned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChange {
+    pub height: BlockHeight,
+    #[serde(flatten)]
+    pub kind: DidChangeKind,
+    /// Confirmed transactions of the DID in the block of the change. Expiration happens without
+    /// a transaction, so there might be none.
+    pub transaction_ids: Vec<String>,
+}
+
+/// Changes of a DID document after `from_height` up to and including `until_height`, ordered by
+/// their height.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocumentDiff {
+    #[serde(with = "serde_str")]
+    pub did: Did,
+    pub from_height: BlockHeight,
+    pub until_height: BlockHeight,
+    pub changes: Vec<DidChange>,
+}
@@ -1,12 +1,29 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct KeyEntry {
     auth: Authentication,
     added_at_height: Option<BlockHeight>,
     expires_at_height: Option<BlockHeight>,
     revoked_at: Option<BlockHeight>,
     rights: HashMap<Right, TimeSeries<bool>>,
+    // Previous expiries, so changing the expiry can be reverted
+    #[serde(default)]
+    expiry_changes: Vec<ExpiryChange>,
+    #[serde(default = "default_suspended")]
+    suspended: TimeSeries<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExpiryChange {
+    height: BlockHeight,
+    previous_expires_at_height: Option<BlockHeight>,
+}
+
+fn default_suspended() -> TimeSeries<bool> {
+    TimeSeries::new(false)
 }
 
 fn min_of_somes<'a, T: Ord>(i: impl Iterator<Item = &'a Option<T>>) -> Option<&'a T> {
@@ -19,37 +36,139 @@ impl KeyEntry {
             .cloned()
     }
 
-    pub fn is_valid_at(
+    /// Whether the key was added and is not yet expired or revoked, even if it is suspended.
+    pub fn is_active_at(
         &self, tombstoned_at_height: Option<BlockHeight>, height: BlockHeight,
     ) -> bool {
         let valid_until = self.valid_until(tombstoned_at_height);
         is_height_in_range_exc_until(height, self.added_at_height, valid_until)
     }
+
+    pub fn is_valid_at(
+        &self, tombstoned_at_height: Option<BlockHeight>, height: BlockHeight,
+    ) -> bool {
+        self.is_active_at(tombstoned_at_height, height) && !*self.suspended.get(height)
+    }
+
+    fn new(
+        auth: &Authentication, added_at_height: Option<BlockHeight>, rights_valid: bool,
+    ) -> Self {
+        Self {
+            auth: auth.to_owned(),
+            rights: system_rights(rights_valid),
+            added_at_height,
+            expires_at_height: None,
+            revoked_at: None,
+            expiry_changes: Default::default(),
+            suspended: default_suspended(),
+        }
+    }
+
+    /// The expiry set by the last change at or before `height`.
+    fn expiry_at(&self, height: BlockHeight) -> Option<BlockHeight> {
+        match self.expiry_changes.iter().find(|c| c.height > height) {
+            Some(next_change) => next_change.previous_expires_at_height,
+            None => self.expires_at_height,
+        }
+    }
+
+    fn suspensions(&self) -> Vec<KeySuspension> {
+        let mut suspensions: Vec<KeySuspension> = vec![];
+        for (height, suspended) in self.suspended.iter() {
+            let height = match height {
+                Some(height) => height,
+                None => continue,
+            };
+            if *suspended {
+                suspensions.push(KeySuspension { from_height: height, until_height: None });
+            } else if let Some(last) = suspensions.last_mut() {
+                last.until_height = Some(height);
+            }
+        }
+        suspensions
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServiceEntry {
+    service: Service,
+    added_at_height: BlockHeight,
+    removed_at_height: Option<BlockHeight>,
+}
+
+impl ServiceEntry {
+    pub fn is_active_at(&self, height: BlockHeight) -> bool {
+        is_height_in_range_exc_until(height, Some(self.added_at_height), self.removed_at_height)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecoveryEntry {
+    auth: Authentication,
+    started_at_height: BlockHeight,
+    completable_at_height: BlockHeight,
+    cancelled_at_height: Option<BlockHeight>,
+    completed_at_height: Option<BlockHeight>,
+    // What completing the recovery changed, so it can be reverted
+    revoked_key_indices: Vec<usize>,
+    update_policy_reset: bool,
+}
+
+impl RecoveryEntry {
+    pub fn is_pending_at(&self, height: BlockHeight) -> bool {
+        let finished_at_height =
+            min_of_somes([self.cancelled_at_height, self.completed_at_height].iter()).cloned();
+        is_height_in_range_exc_until(height, Some(self.started_at_height), finished_at_height)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.cancelled_at_height.is_none() && self.completed_at_height.is_none()
+    }
 }
 
 fn system_rights(valid: bool) -> HashMap<Right, TimeSeries<bool>> {
-    let state = TimeSeries::new(valid);
-    vec![(Right::Update, state.clone()), (Right::Impersonation, state)].drain(..).collect()
+    Right::system_rights().into_iter().map(|r| (r, TimeSeries::new(valid))).collect()
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub(super) struct DidDocumentState {
     // last entry inserted at the end, because reading performance is unaffected, but writing gets faster
     key_entries: Vec<KeyEntry>,
     tombstoned_at_height: Option<BlockHeight>,
+    #[serde(default)]
+    service_entries: Vec<ServiceEntry>,
+    #[serde(default = "default_update_policy")]
+    update_policy: TimeSeries<UpdatePolicy>,
+    #[serde(default = "default_recovery_policy")]
+    recovery_policy: TimeSeries<RecoveryPolicy>,
+    #[serde(default)]
+    recovery_entries: Vec<RecoveryEntry>,
+}
+
+fn default_update_policy() -> TimeSeries<UpdatePolicy> {
+    TimeSeries::new(UpdatePolicy::Any)
+}
+
+fn default_recovery_policy() -> TimeSeries<RecoveryPolicy> {
+    TimeSeries::new(RecoveryPolicy::Disabled)
 }
 
 impl DidDocumentState {
     pub fn new(did: &Did) -> Self {
         Self {
-            key_entries: vec![KeyEntry {
-                auth: Authentication::KeyId(did.default_key_id()),
-                rights: system_rights(true),
-                added_at_height: None,
-                expires_at_height: None,
-                revoked_at: None,
-            }],
+            key_entries: vec![KeyEntry::new(
+                &Authentication::KeyId(did.default_key_id()),
+                None,
+                true,
+            )],
             tombstoned_at_height: None,
+            service_entries: Default::default(),
+            update_policy: default_update_policy(),
+            recovery_policy: default_recovery_policy(),
+            recovery_entries: Default::default(),
         }
     }
 
@@ -63,19 +182,49 @@ impl DidDocumentState {
         let keys: Vec<KeyData> =
             keys_at_height.iter().map(|k| self.key_entry_to_data(*k, height)).collect();
 
-        let rights: HashMap<Right, Vec<KeyRightHistory>> = Right::map_all(|r| {
-            keys_at_height
-                .iter()
-                .enumerate()
-                .map(|(i, k)| self.key_entry_to_right_history(*k, i, height, r))
-                .collect()
-        });
+        // Custom rights are listed only for keys they were ever granted to
+        let mut all_rights = Right::system_rights();
+        for right in keys_at_height.iter().flat_map(|k| k.rights.keys()) {
+            if !all_rights.contains(right) {
+                all_rights.push(right.to_owned());
+            }
+        }
+        let rights: HashMap<Right, Vec<KeyRightHistory>> = all_rights
+            .into_iter()
+            .map(|r| {
+                let histories = keys_at_height
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, k)| self.key_entry_to_right_history(k, i, height, &r))
+                    .collect();
+                (r, histories)
+            })
+            .collect();
+
+        let services: Vec<Service> = self
+            .service_entries
+            .iter()
+            .filter(|s| s.is_active_at(height))
+            .map(|s| s.service.to_owned())
+            .collect();
+
+        let pending_recovery =
+            self.recovery_entries.iter().rev().find(|r| r.is_pending_at(height)).map(|r| {
+                PendingRecovery {
+                    auth: r.auth.to_owned(),
+                    started_at_height: r.started_at_height,
+                    completable_at_height: r.completable_at_height,
+                }
+            });
 
         let doc = DidDocument {
             did: did.to_owned(),
             keys,
             rights,
-            services: Default::default(),
+            services,
+            update_policy: self.update_policy.get(height).to_owned(),
+            recovery_policy: self.recovery_policy.get(height).to_owned(),
+            pending_recovery,
             queried_at_height: height,
             tombstoned: self.tombstoned_at_height.is_some(),
             tombstoned_at_height: self.tombstoned_at_height,
@@ -84,25 +233,108 @@ impl DidDocumentState {
         Ok(doc)
     }
 
+    /// Squashes history before `height` without changing the document at `height` and above.
+    /// Key entries are kept even if they are no longer valid, because key links are their indices.
+    pub fn prune_below(&mut self, height: BlockHeight) {
+        for key_entry in self.key_entries.iter_mut() {
+            // Right histories and suspensions are listed in full at every height, so they are
+            // not squashed
+            key_entry.expiry_changes.retain(|c| c.height >= height);
+        }
+        self.service_entries
+            .retain(|s| !matches!(s.removed_at_height, Some(removed) if removed <= height));
+        self.recovery_entries.retain(|r| {
+            let finished_at_height =
+                min_of_somes([r.cancelled_at_height, r.completed_at_height].iter()).cloned();
+            !matches!(finished_at_height, Some(finished) if finished <= height)
+        });
+        self.update_policy.prune_below(height);
+        self.recovery_policy.prune_below(height);
+    }
+
+    /// Changes after `from_height` up to and including `until_height` in no particular order.
+    pub fn changes_between(
+        &self, from_height: BlockHeight, until_height: BlockHeight,
+    ) -> Vec<(BlockHeight, DidChangeKind)> {
+        let in_range = |height: BlockHeight| from_height < height && height <= until_height;
+        let mut changes = vec![];
+        for (idx, key_entry) in self.key_entries.iter().enumerate() {
+            let key_link = format!("#{}", idx);
+            let auth = &key_entry.auth;
+            if let Some(added_at_height) = key_entry.added_at_height.filter(|h| in_range(*h)) {
+                let kind =
+                    DidChangeKind::KeyAdded { key_link: key_link.clone(), auth: auth.clone() };
+                changes.push((added_at_height, kind));
+            }
+            // A key is either revoked or expires, whichever happens first
+            let revoked_at = key_entry
+                .revoked_at
+                .filter(|r| !matches!(key_entry.expires_at_height, Some(e) if e <= *r));
+            let expires_at = key_entry.expires_at_height.filter(|_| revoked_at.is_none());
+            if let Some(revoked_at) = revoked_at.filter(|h| in_range(*h)) {
+                let kind =
+                    DidChangeKind::KeyRevoked { key_link: key_link.clone(), auth: auth.clone() };
+                changes.push((revoked_at, kind));
+            }
+            if let Some(expires_at) = expires_at.filter(|h| in_range(*h)) {
+                let kind =
+                    DidChangeKind::KeyExpired { key_link: key_link.clone(), auth: auth.clone() };
+                changes.push((expires_at, kind));
+            }
+            for change in key_entry.expiry_changes.iter().filter(|c| in_range(c.height)) {
+                let kind = DidChangeKind::KeyExpiryChanged {
+                    key_link: key_link.clone(),
+                    auth: auth.clone(),
+                    expires_at_height: key_entry.expiry_at(change.height),
+                };
+                changes.push((change.height, kind));
+            }
+            for (height, suspended) in key_entry.suspended.iter() {
+                if let Some(height) = height.filter(|h| in_range(*h)) {
+                    let key_link = key_link.clone();
+                    let auth = auth.clone();
+                    let kind = if *suspended {
+                        DidChangeKind::KeySuspended { key_link, auth }
+                    } else {
+                        DidChangeKind::KeyResumed { key_link, auth }
+                    };
+                    changes.push((height, kind));
+                }
+            }
+            for (right, history) in &key_entry.rights {
+                for (height, valid) in history.iter() {
+                    if let Some(height) = height.filter(|h| in_range(*h)) {
+                        let kind = DidChangeKind::RightChanged {
+                            key_link: key_link.clone(),
+                            auth: auth.clone(),
+                            right: right.to_owned(),
+                            valid: *valid,
+                        };
+                        changes.push((height, kind));
+                    }
+                }
+            }
+        }
+        if let Some(tombstoned_at_height) = self.tombstoned_at_height.filter(|h| in_range(*h)) {
+            changes.push((tombstoned_at_height, DidChangeKind::Tombstoned));
+        }
+        changes
+    }
+
     fn key_entry_to_right_history(
         &self, key_entry: &KeyEntry, idx: usize, height: BlockHeight, right: &Right,
-    ) -> KeyRightHistory {
-        let (items, valid) = if let Some(history) = key_entry.rights.get(right) {
-            (
-                history
-                    .iter()
-                    .map(|(height, valid)| KeyRightHistoryItem { height, valid: *valid })
-                    .collect(),
-                *history.get(height),
-            )
-        } else {
-            (vec![], false)
-        };
+    ) -> Option<KeyRightHistory> {
+        let history = key_entry.rights.get(right)?;
+        let items = history
+            .iter()
+            .map(|(height, valid)| KeyRightHistoryItem { height, valid: *valid })
+            .collect();
+        let valid = *history.get(height);
 
         let state = KeyRightState { history: items };
         let key_link = format!("#{}", idx);
         let derived = KeyRightDerived { key_link, valid };
-        KeyRightHistory { state, derived }
+        Some(KeyRightHistory { state, derived })
     }
 
     fn key_entry_to_data(&self, key_entry: &KeyEntry, height: BlockHeight) -> KeyData {
@@ -110,65 +342,229 @@ impl DidDocumentState {
             authentication: key_entry.auth.to_owned(),
             valid_from_block: key_entry.added_at_height,
             valid_until_block: key_entry.valid_until(self.tombstoned_at_height),
+            // Filled in by the state that knows the block times
+            valid_from_time: None,
+            valid_until_time: None,
+            suspensions: key_entry.suspensions(),
         };
         let derived =
             KeyDataDerived { valid: key_entry.is_valid_at(self.tombstoned_at_height, height) };
         KeyData { state, derived }
     }
 
+    fn active_service_by_name(&mut self, name: &str) -> Option<&mut ServiceEntry> {
+        self.service_entries
+            .iter_mut()
+            .rev()
+            .find(|s| s.service.name == name && s.removed_at_height.is_none())
+    }
+
     fn last_by_auth(&mut self, auth: &Authentication) -> Option<&mut KeyEntry> {
         self.key_entries.iter_mut().rev().find(|i| &i.auth == auth)
     }
 
-    fn right_history(
-        &mut self, did: &Did, height: BlockHeight, auth: &Authentication, right: &str,
-    ) -> Result<&mut TimeSeries<bool>> {
-        let right: Right = right.parse()?;
+    fn valid_key_by_auth(
+        &mut self, did: &Did, height: BlockHeight, auth: &Authentication,
+    ) -> Result<&mut KeyEntry> {
         let tombstoned_at_height = self.tombstoned_at_height;
         if let Some(entry) = self.last_by_auth(auth) {
             ensure!(
                 entry.is_valid_at(tombstoned_at_height, height),
-                "Key matching {} of DID {} is invalid at height {}",
-                auth,
-                did,
-                height
+                OperationErrorCode::KeyInvalid.error(format!(
+                    "Key matching {} of DID {} is invalid at height {}",
+                    auth, did, height
+                ))
             );
+            Ok(entry)
+        } else {
+            bail!(OperationErrorCode::KeyNotFound
+                .error(format!("DID {} has no key matching {}", did, auth)))
+        }
+    }
 
-            if let Some(history) = entry.rights.get_mut(&right) {
-                Ok(history)
-            } else {
-                bail!(
-                    "Key matching {} of DID {} has no right history of right {}",
-                    auth,
-                    did,
-                    right
-                );
-            }
+    fn active_key_by_auth(
+        &mut self, did: &Did, height: BlockHeight, auth: &Authentication,
+    ) -> Result<&mut KeyEntry> {
+        let tombstoned_at_height = self.tombstoned_at_height;
+        if let Some(entry) = self.last_by_auth(auth) {
+            ensure!(
+                entry.is_active_at(tombstoned_at_height, height),
+                OperationErrorCode::KeyInvalid.error(format!(
+                    "Key matching {} of DID {} is expired or revoked at height {}",
+                    auth, did, height
+                ))
+            );
+            Ok(entry)
         } else {
-            bail!("DID {} has no key matching {}", did, auth)
+            bail!(OperationErrorCode::KeyNotFound
+                .error(format!("DID {} has no key matching {}", did, auth)))
+        }
+    }
+
+    fn right_history(
+        &mut self, did: &Did, height: BlockHeight, auth: &Authentication, right: &Right,
+    ) -> Result<&mut TimeSeries<bool>> {
+        let entry = self.valid_key_by_auth(did, height, auth)?;
+        if let Some(history) = entry.rights.get_mut(right) {
+            Ok(history)
+        } else {
+            bail!(OperationErrorCode::RightNotFound.error(format!(
+                "Key matching {} of DID {} has no right history of right {}",
+                auth, did, right
+            )));
         }
     }
 
     fn ensure_min_height(&self, height: BlockHeight) -> Result<()> {
-        ensure!(height > 1, "Keys cannot be added before height 2");
+        ensure!(
+            height > 1,
+            OperationErrorCode::HeightTooLow.error("Keys cannot be added before height 2")
+        );
         Ok(())
     }
 
     fn ensure_not_tombstoned(&self) -> Result<()> {
+        if let Some(height) = self.tombstoned_at_height {
+            bail!(OperationErrorCode::TombstonedDid.error(format!(
+                "did is tombstoned at height {}, cannot be updated anymore",
+                height
+            )));
+        }
+        Ok(())
+    }
+
+    fn ensure_different_auth(
+        &self, signers: &[Authentication], auth: &Authentication,
+    ) -> Result<()> {
+        for signer in signers {
+            ensure!(
+                signer != auth,
+                OperationErrorCode::OwnAuthorization
+                    .error(format!("{} cannot modify its own authorization (as {})", signer, auth))
+            );
+        }
+        Ok(())
+    }
+
+    fn ensure_not_in_update_policy(&self, did: &Did, auth: &Authentication) -> Result<()> {
+        if let UpdatePolicy::Threshold { keys, .. } = self.update_policy.latest_value() {
+            ensure!(
+                !keys.contains(auth),
+                OperationErrorCode::KeyInUpdatePolicy.error(format!(
+                    "Key matching {} is part of the update policy of DID {}, change the policy first",
+                    auth, did
+                ))
+            );
+        }
+        Ok(())
+    }
+
+    fn pending_recovery(&mut self, did: &Did) -> Result<&mut RecoveryEntry> {
+        match self.recovery_entries.last_mut() {
+            Some(entry) if entry.is_pending() => Ok(entry),
+            _ => bail!(OperationErrorCode::NoPendingRecovery
+                .error(format!("DID {} has no pending recovery", did))),
+        }
+    }
+
+    fn complete_recovery(
+        &mut self, did: &Did, height: BlockHeight, auth: &Authentication,
+    ) -> Result<()> {
+        let entry = self.pending_recovery(did)?;
         ensure!(
-            self.tombstoned_at_height.is_none(),
-            "did is tombstoned at height {}, cannot be updated anymore"
+            &entry.auth == auth,
+            OperationErrorCode::RecoveryKeyMismatch.error(format!(
+                "Pending recovery of DID {} resets keys to {}, not to {}",
+                did, entry.auth, auth
+            ))
         );
+        ensure!(
+            entry.completable_at_height <= height,
+            OperationErrorCode::RecoveryNotCompletable.error(format!(
+                "Recovery of DID {} cannot be completed before height {}",
+                did, entry.completable_at_height
+            ))
+        );
+
+        let tombstoned_at_height = self.tombstoned_at_height;
+        let mut revoked_key_indices = vec![];
+        for (idx, key_entry) in self.key_entries.iter_mut().enumerate() {
+            // Suspended keys are revoked as well, so they cannot be resumed after the reset
+            if key_entry.is_active_at(tombstoned_at_height, height) {
+                key_entry.revoked_at = Some(height);
+                revoked_key_indices.push(idx);
+            }
+        }
+        // Keys of a threshold policy are all revoked, so only the recovered key can update the DID
+        let update_policy_reset = !self.update_policy.latest_value().is_any();
+        if update_policy_reset {
+            self.update_policy
+                .apply(height, UpdatePolicy::Any, || format!("Update policy of DID {}", did))?;
+        }
+
+        let mut key_entry = KeyEntry::new(auth, Some(height), false);
+        for history in key_entry.rights.values_mut() {
+            history.apply(height, true, || format!("Validity of key {} in DID {}", auth, did))?;
+        }
+        self.key_entries.push(key_entry);
+
+        let entry = self.pending_recovery(did)?;
+        entry.completed_at_height = Some(height);
+        entry.revoked_key_indices = revoked_key_indices;
+        entry.update_policy_reset = update_policy_reset;
         Ok(())
     }
 
-    fn ensure_different_auth(&self, signer: &Authentication, auth: &Authentication) -> Result<()> {
-        ensure!(signer != auth, "{} cannot modify its own authorization (as {})", signer, auth);
+    fn revert_complete_recovery(
+        &mut self, did: &Did, height: BlockHeight, auth: &Authentication,
+    ) -> Result<()> {
+        let entry = match self.recovery_entries.last_mut() {
+            Some(entry) if entry.completed_at_height.is_some() => entry,
+            _ => bail!(
+                "Cannot revert completeRecovery in DID {}, because it has no completed recovery",
+                did
+            ),
+        };
+        ensure!(
+            &entry.auth == auth && entry.completed_at_height == Some(height),
+            "Cannot revert completeRecovery in DID {}, because the last recovery was not completed to {} at height {}",
+            did,
+            auth,
+            height
+        );
+        entry.completed_at_height = None;
+        let revoked_key_indices = std::mem::take(&mut entry.revoked_key_indices);
+        let update_policy_reset = std::mem::replace(&mut entry.update_policy_reset, false);
+
+        match self.key_entries.pop() {
+            Some(last_entry) => ensure!(
+                &last_entry.auth == auth && last_entry.added_at_height == Some(height),
+                "Cannot revert completeRecovery in DID {}, because the key does not match the last added one.",
+                did
+            ),
+            None => bail!("Cannot revert completeRecovery in DID {}, because there are no keys", did),
+        }
+        for idx in revoked_key_indices {
+            match self.key_entries.get_mut(idx) {
+                Some(key_entry) if key_entry.revoked_at == Some(height) => {
+                    key_entry.revoked_at = None
+                }
+                _ => bail!(
+                    "Cannot revert completeRecovery in DID {}, because key #{} was not revoked by it",
+                    did,
+                    idx
+                ),
+            }
+        }
+        if update_policy_reset {
+            self.update_policy
+                .revert(height, UpdatePolicy::Any, || format!("Update policy of DID {}", did))?;
+        }
         Ok(())
     }
 
     pub fn apply(
-        &mut self, did: &Did, height: BlockHeight, signer: &Authentication,
+        &mut self, did: &Did, height: BlockHeight, signers: &[Authentication],
         op: &SignableOperationDetails,
     ) -> Result<()> {
         use SignableOperationDetails::*;
@@ -178,53 +574,107 @@ impl DidDocumentState {
                 self.ensure_not_tombstoned()?;
                 if let Some(existing_entry) = self.last_by_auth(auth) {
                     ensure!(
-                        !existing_entry.is_valid_at(None, height),
-                        "DID {} already has a still valid key matching {}",
-                        did,
-                        auth
+                        !existing_entry.is_active_at(None, height),
+                        OperationErrorCode::KeyAlreadyAdded.error(format!(
+                            "DID {} already has a still valid key matching {}",
+                            did, auth
+                        ))
                     )
                 }
-                self.key_entries.push(KeyEntry {
-                    auth: auth.clone(),
-                    rights: system_rights(false),
-                    added_at_height: Some(height),
-                    expires_at_height: expires_at_height.clone(),
-                    revoked_at: None,
-                })
+                let mut key_entry = KeyEntry::new(auth, Some(height), false);
+                key_entry.expires_at_height = *expires_at_height;
+                self.key_entries.push(key_entry)
             }
             RevokeKey { auth } => {
-                self.ensure_different_auth(signer, auth)?;
+                self.ensure_different_auth(signers, auth)?;
                 self.ensure_min_height(height)?;
                 self.ensure_not_tombstoned()?;
+                self.ensure_not_in_update_policy(did, auth)?;
                 if let Some(existing_entry) = self.last_by_auth(auth) {
                     ensure!(
-                        existing_entry.is_valid_at(None, height),
-                        "DID {} has a key matching {}, but it's already invalidated",
-                        did,
-                        auth
+                        existing_entry.revoked_at.is_none(),
+                        OperationErrorCode::KeyAlreadyRevoked.error(format!(
+                            "key matching {} in DID {} was already revoked",
+                            auth, did
+                        ))
                     );
                     ensure!(
-                        existing_entry.revoked_at.is_none(),
-                        "key matching {} in DID {} was already revoked",
-                        auth,
-                        did
+                        existing_entry.is_active_at(None, height),
+                        OperationErrorCode::KeyInvalid.error(format!(
+                            "DID {} has a key matching {}, but it's already invalidated",
+                            did, auth
+                        ))
                     );
                     existing_entry.revoked_at = Some(height);
                 } else {
-                    bail!("DID {} does not have a key matching {}", did, auth)
+                    bail!(OperationErrorCode::KeyNotFound
+                        .error(format!("DID {} does not have a key matching {}", did, auth)))
                 }
             }
+            SetKeyExpiry { auth, expires_at_height } => {
+                self.ensure_different_auth(signers, auth)?;
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                ensure!(
+                    !matches!(expires_at_height, Some(expiry) if *expiry <= height),
+                    OperationErrorCode::InvalidExpiry.error(format!(
+                        "Key matching {} in DID {} cannot expire at height {}, before it is set",
+                        auth, did, height
+                    ))
+                );
+                let entry = self.active_key_by_auth(did, height, auth)?;
+                ensure!(
+                    &entry.expires_at_height != expires_at_height,
+                    OperationErrorCode::AlreadySet.error(format!(
+                        "Key matching {} in DID {} already has the same expiry",
+                        auth, did
+                    ))
+                );
+                let previous_expires_at_height =
+                    std::mem::replace(&mut entry.expires_at_height, *expires_at_height);
+                entry.expiry_changes.push(ExpiryChange { height, previous_expires_at_height });
+            }
+            SuspendKey { auth } => {
+                self.ensure_different_auth(signers, auth)?;
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                self.ensure_not_in_update_policy(did, auth)?;
+                let entry = self.valid_key_by_auth(did, height, auth)?;
+                entry
+                    .suspended
+                    .apply(height, true, || format!("Suspension of key {} in DID {}", auth, did))?;
+            }
+            ResumeKey { auth } => {
+                self.ensure_different_auth(signers, auth)?;
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                let entry = self.active_key_by_auth(did, height, auth)?;
+                ensure!(
+                    *entry.suspended.latest_value(),
+                    OperationErrorCode::KeyNotSuspended
+                        .error(format!("Key matching {} in DID {} is not suspended", auth, did))
+                );
+                entry.suspended.apply(height, false, || {
+                    format!("Suspension of key {} in DID {}", auth, did)
+                })?;
+            }
             AddRight { auth, right } => {
-                self.ensure_different_auth(signer, auth)?;
+                self.ensure_different_auth(signers, auth)?;
                 self.ensure_not_tombstoned()?;
-                let history = self.right_history(did, height, auth, right)?;
+                let right: Right =
+                    right.parse().map_err(|e| OperationErrorCode::InvalidRight.error(e))?;
+                let entry = self.valid_key_by_auth(did, height, auth)?;
+                // System rights have a history for all keys, custom ones are created on first grant
+                let history = entry.rights.entry(right).or_insert_with(|| TimeSeries::new(false));
                 history
                     .apply(height, true, || format!("Validity of key {} in DID {}", auth, did))?;
             }
             RevokeRight { auth, right } => {
-                self.ensure_different_auth(signer, auth)?;
+                self.ensure_different_auth(signers, auth)?;
                 self.ensure_not_tombstoned()?;
-                let history = self.right_history(did, height, auth, right)?;
+                let right: Right =
+                    right.parse().map_err(|e| OperationErrorCode::InvalidRight.error(e))?;
+                let history = self.right_history(did, height, auth, &right)?;
                 history
                     .apply(height, false, || format!("Validity of key {} in DID {}", auth, did))?;
             }
@@ -232,12 +682,87 @@ impl DidDocumentState {
                 self.ensure_not_tombstoned()?;
                 self.tombstoned_at_height = Some(height);
             }
+            AddService { service } => {
+                self.ensure_not_tombstoned()?;
+                ensure!(
+                    self.active_service_by_name(&service.name).is_none(),
+                    OperationErrorCode::ServiceAlreadyAdded
+                        .error(format!("DID {} already has a service named {}", did, service.name))
+                );
+                self.service_entries.push(ServiceEntry {
+                    service: service.to_owned(),
+                    added_at_height: height,
+                    removed_at_height: None,
+                })
+            }
+            RemoveService { name } => {
+                self.ensure_not_tombstoned()?;
+                if let Some(existing_entry) = self.active_service_by_name(name) {
+                    existing_entry.removed_at_height = Some(height);
+                } else {
+                    bail!(OperationErrorCode::ServiceNotFound
+                        .error(format!("DID {} does not have a service named {}", did, name)))
+                }
+            }
+            SetUpdatePolicy { policy } => {
+                self.ensure_not_tombstoned()?;
+                policy.validate().map_err(|e| OperationErrorCode::InvalidPolicy.error(e))?;
+                if let UpdatePolicy::Threshold { keys, .. } = policy {
+                    for key in keys {
+                        self.valid_key_by_auth(did, height, key)?;
+                    }
+                }
+                self.update_policy
+                    .apply(height, policy.to_owned(), || format!("Update policy of DID {}", did))?;
+            }
+            SetRecoveryPolicy { policy } => {
+                self.ensure_not_tombstoned()?;
+                policy.validate().map_err(|e| OperationErrorCode::InvalidPolicy.error(e))?;
+                self.recovery_policy.apply(height, policy.to_owned(), || {
+                    format!("Recovery policy of DID {}", did)
+                })?;
+            }
+            StartRecovery { auth } => {
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                let delay = match self.recovery_policy.get(height) {
+                    RecoveryPolicy::Disabled => bail!(OperationErrorCode::RecoveryDisabled
+                        .error(format!("DID {} has no recovery policy", did))),
+                    RecoveryPolicy::Guardians { delay, .. } => *delay,
+                };
+                ensure!(
+                    self.pending_recovery(did).is_err(),
+                    OperationErrorCode::RecoveryAlreadyPending
+                        .error(format!("DID {} already has a pending recovery", did))
+                );
+                self.recovery_entries.push(RecoveryEntry {
+                    auth: auth.to_owned(),
+                    started_at_height: height,
+                    completable_at_height: height + delay,
+                    cancelled_at_height: None,
+                    completed_at_height: None,
+                    revoked_key_indices: Default::default(),
+                    update_policy_reset: false,
+                })
+            }
+            CancelRecovery {} => {
+                self.ensure_not_tombstoned()?;
+                let entry = self.pending_recovery(did)?;
+                entry.cancelled_at_height = Some(height);
+            }
+            CompleteRecovery { auth } => {
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                self.complete_recovery(did, height, auth)?;
+            }
+            // Revocations are kept by the state, they do not change the document
+            RevokeCredential { .. } => self.ensure_not_tombstoned()?,
         }
         Ok(())
     }
 
     pub fn revert(
-        &mut self, did: &Did, height: BlockHeight, signer: &Authentication,
+        &mut self, did: &Did, height: BlockHeight, signers: &[Authentication],
         op: &SignableOperationDetails,
     ) -> Result<()> {
         use SignableOperationDetails::*;
@@ -266,7 +791,7 @@ impl DidDocumentState {
                 }
             }
             RevokeKey { auth } => {
-                self.ensure_different_auth(signer, auth)?;
+                self.ensure_different_auth(signers, auth)?;
                 self.ensure_min_height(height)?;
                 self.ensure_not_tombstoned()?;
                 if let Some(existing_entry) = self.last_by_auth(auth) {
@@ -278,7 +803,7 @@ impl DidDocumentState {
                     );
                     existing_entry.revoked_at = None;
                     ensure!(
-                        existing_entry.is_valid_at(None, height),
+                        existing_entry.is_active_at(None, height),
                         "Failed to revert revokeKey in DID {} for key matching {}. It's still invalid after reverted revoking.",
                         did,
                         auth
@@ -287,17 +812,70 @@ impl DidDocumentState {
                     bail!("Cannot revert revokeKey in DID {} because it does not have a key matching {}", did, auth)
                 }
             }
+            SetKeyExpiry { auth, expires_at_height } => {
+                self.ensure_different_auth(signers, auth)?;
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                let entry = self.active_key_by_auth(did, height, auth)?;
+                match entry.expiry_changes.pop() {
+                    Some(change)
+                        if change.height == height
+                            && &entry.expires_at_height == expires_at_height =>
+                    {
+                        entry.expires_at_height = change.previous_expires_at_height
+                    }
+                    _ => bail!(
+                        "Cannot revert setKeyExpiry in DID {}, because the expiry of key matching {} was not set at height {}",
+                        did,
+                        auth,
+                        height
+                    ),
+                }
+            }
+            SuspendKey { auth } => {
+                self.ensure_different_auth(signers, auth)?;
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                let entry = self.active_key_by_auth(did, height, auth)?;
+                entry.suspended.revert(height, true, || {
+                    format!("Suspension of key {} in DID {}", auth, did)
+                })?;
+            }
+            ResumeKey { auth } => {
+                self.ensure_different_auth(signers, auth)?;
+                self.ensure_min_height(height)?;
+                self.ensure_not_tombstoned()?;
+                let entry = self.active_key_by_auth(did, height, auth)?;
+                entry.suspended.revert(height, false, || {
+                    format!("Suspension of key {} in DID {}", auth, did)
+                })?;
+            }
             AddRight { auth, right } => {
-                self.ensure_different_auth(signer, auth)?;
+                self.ensure_different_auth(signers, auth)?;
                 self.ensure_not_tombstoned()?;
-                let history = self.right_history(did, height, auth, right)?;
-                history
-                    .revert(height, true, || format!("Validity of key {} in DID {}", auth, did))?;
+                let right: Right = right.parse()?;
+                let entry = self.valid_key_by_auth(did, height, auth)?;
+                if let Some(history) = entry.rights.get_mut(&right) {
+                    history.revert(height, true, || {
+                        format!("Validity of key {} in DID {}", auth, did)
+                    })?;
+                    if !right.is_system() && history.is_empty() {
+                        entry.rights.remove(&right);
+                    }
+                } else {
+                    bail!(
+                        "Key matching {} of DID {} has no right history of right {}",
+                        auth,
+                        did,
+                        right
+                    );
+                }
             }
             RevokeRight { auth, right } => {
-                self.ensure_different_auth(signer, auth)?;
+                self.ensure_different_auth(signers, auth)?;
                 self.ensure_not_tombstoned()?;
-                let history = self.right_history(did, height, auth, right)?;
+                let right: Right = right.parse()?;
+                let history = self.right_history(did, height, auth, &right)?;
                 history
                     .revert(height, false, || format!("Validity of key {} in DID {}", auth, did))?;
             }
@@ -309,6 +887,87 @@ impl DidDocumentState {
                 );
                 self.tombstoned_at_height = None;
             }
+            AddService { service } => {
+                self.ensure_not_tombstoned()?;
+                if let Some(last_entry) = self.service_entries.pop() {
+                    ensure!(
+                        &last_entry.service == service,
+                        "Cannot revert addService in DID {}, because the service does not match the last added one.",
+                        did
+                    );
+                    ensure!(
+                        last_entry.added_at_height == height,
+                        "Cannot revert addService in DID {}, because it was not added at the specified height.",
+                        did
+                    );
+                    ensure!(
+                        last_entry.removed_at_height.is_none(),
+                        "Cannot revert addService in DID {}, because the service was already removed.",
+                        did
+                    );
+                } else {
+                    bail!("Cannot revert addService in DID {}, because there are no services", did);
+                }
+            }
+            RemoveService { name } => {
+                self.ensure_not_tombstoned()?;
+                let removed_entry = self
+                    .service_entries
+                    .iter_mut()
+                    .rev()
+                    .find(|s| &s.service.name == name && s.removed_at_height.is_some());
+                if let Some(existing_entry) = removed_entry {
+                    ensure!(
+                        existing_entry.removed_at_height == Some(height),
+                        "Cannot revert removeService in DID {}, because service {} was not removed at height {}",
+                        did,
+                        name,
+                        height
+                    );
+                    existing_entry.removed_at_height = None;
+                } else {
+                    bail!("Cannot revert removeService in DID {} because it has no removed service named {}", did, name)
+                }
+            }
+            SetUpdatePolicy { policy } => {
+                self.ensure_not_tombstoned()?;
+                self.update_policy.revert(height, policy.to_owned(), || {
+                    format!("Update policy of DID {}", did)
+                })?;
+            }
+            SetRecoveryPolicy { policy } => {
+                self.ensure_not_tombstoned()?;
+                self.recovery_policy.revert(height, policy.to_owned(), || {
+                    format!("Recovery policy of DID {}", did)
+                })?;
+            }
+            StartRecovery { auth } => {
+                self.ensure_not_tombstoned()?;
+                match self.recovery_entries.pop() {
+                    Some(last_entry) => ensure!(
+                        &last_entry.auth == auth
+                            && last_entry.started_at_height == height
+                            && last_entry.is_pending(),
+                        "Cannot revert startRecovery in DID {}, because it does not match the last pending recovery.",
+                        did
+                    ),
+                    None => bail!("Cannot revert startRecovery in DID {}, because there are no recoveries", did),
+                }
+            }
+            CancelRecovery {} => {
+                self.ensure_not_tombstoned()?;
+                match self.recovery_entries.last_mut() {
+                    Some(last_entry) if last_entry.cancelled_at_height == Some(height) => {
+                        last_entry.cancelled_at_height = None
+                    }
+                    _ => bail!("Cannot revert cancelRecovery in DID {}, because no recovery was cancelled at height {}", did, height),
+                }
+            }
+            CompleteRecovery { auth } => {
+                self.ensure_not_tombstoned()?;
+                self.revert_complete_recovery(did, height, auth)?;
+            }
+            RevokeCredential { .. } => self.ensure_not_tombstoned()?,
         }
         Ok(())
     }
@@ -0,0 +1,207 @@
+use super::*;
+
+/// A change of the Morpheus state caused by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum StateEvent {
+    KeyAdded {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+    },
+    KeyRevoked {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+    },
+    KeyExpiryChanged {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+        #[serde(rename = "expiresAtHeight", skip_serializing_if = "Option::is_none", default)]
+        expires_at_height: Option<BlockHeight>,
+    },
+    KeySuspended {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+    },
+    KeyResumed {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+    },
+    RightGranted {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+        right: String,
+    },
+    RightRevoked {
+        #[serde(with = "serde_str")]
+        did: Did,
+        auth: Authentication,
+        right: String,
+    },
+    CredentialRevoked {
+        #[serde(with = "serde_str")]
+        did: Did,
+        #[serde(rename = "contentId")]
+        content_id: String,
+    },
+    DidTombstoned {
+        #[serde(with = "serde_str")]
+        did: Did,
+    },
+    BeforeProofRegistered {
+        #[serde(rename = "contentId")]
+        content_id: String,
+    },
+    BeforeProofBatchRegistered {
+        root: String,
+    },
+    /// The error is only known when the transaction is applied, not when it is reverted.
+    TransactionRejected {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        error: Option<String>,
+    },
+    /// `StateHolder::recover` restored the checkpoint at `height`. Changes emitted for later heights
+    /// were discarded without reversals, they are emitted again while the blocks are replayed. Not
+    /// caused by a transaction, so `txid` is empty.
+    StateRecovered,
+}
+
+/// An event emitted after the change was committed. Reverting a transaction emits the events of
+/// applying it in reverse order with `reverted` set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateChange {
+    pub height: BlockHeight,
+    pub txid: String,
+    pub reverted: bool,
+    #[serde(flatten)]
+    pub event: StateEvent,
+}
+
+/// Subscribers of `StateHolder` are notified of every committed change in order.
+pub trait StateObserver {
+    fn on_change(&mut self, change: &StateChange);
+}
+
+impl<F: FnMut(&StateChange)> StateObserver for F {
+    fn on_change(&mut self, change: &StateChange) {
+        self(change)
+    }
+}
+
+/// How a transaction was processed, which decides the events it emits.
+pub(super) enum TxnOutcome {
+    Confirmed,
+    Rejected { error: Option<String> },
+}
+
+/// Collects the changes of a transaction. `state_without_txn` is the state right before applying
+/// or right after reverting the transaction.
+pub(super) fn txn_changes(
+    state_without_txn: &State, txid: &str, asset: &MorpheusAsset, outcome: TxnOutcome,
+    reverted: bool,
+) -> Result<Vec<StateChange>> {
+    let events = match outcome {
+        TxnOutcome::Confirmed => confirmed_events(state_without_txn, asset)?,
+        TxnOutcome::Rejected { error } => vec![StateEvent::TransactionRejected { error }],
+    };
+    let height = state_without_txn.last_seen_height();
+    let mut changes: Vec<_> = events
+        .into_iter()
+        .map(|event| StateChange { height, txid: txid.to_owned(), reverted, event })
+        .collect();
+    if reverted {
+        changes.reverse();
+    }
+    Ok(changes)
+}
+
+fn confirmed_events(state_without_txn: &State, asset: &MorpheusAsset) -> Result<Vec<StateEvent>> {
+    use SignableOperationDetails::*;
+    let mut events = vec![];
+    for op in &asset.operation_attempts {
+        let attempts = match attempts_unsafe_without_signature_checking(op) {
+            Some(attempts) => attempts,
+            None => {
+                match op {
+                    OperationAttempt::RegisterBeforeProof { content_id } => {
+                        let content_id = content_id.to_owned();
+                        events.push(StateEvent::BeforeProofRegistered { content_id });
+                    }
+                    OperationAttempt::RegisterBeforeProofBatch { root } => {
+                        let root = root.to_owned();
+                        events.push(StateEvent::BeforeProofBatchRegistered { root });
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        };
+        // Signatures were checked when the transaction was confirmed
+        for attempt in attempts {
+            let did = attempt.did.to_owned();
+            match &attempt.operation {
+                AddKey { auth, .. } => {
+                    events.push(StateEvent::KeyAdded { did, auth: auth.clone() })
+                }
+                RevokeKey { auth } => {
+                    events.push(StateEvent::KeyRevoked { did, auth: auth.clone() })
+                }
+                SetKeyExpiry { auth, expires_at_height } => {
+                    events.push(StateEvent::KeyExpiryChanged {
+                        did,
+                        auth: auth.clone(),
+                        expires_at_height: *expires_at_height,
+                    })
+                }
+                SuspendKey { auth } => {
+                    events.push(StateEvent::KeySuspended { did, auth: auth.clone() })
+                }
+                ResumeKey { auth } => {
+                    events.push(StateEvent::KeyResumed { did, auth: auth.clone() })
+                }
+                AddRight { auth, right } => events.push(StateEvent::RightGranted {
+                    did,
+                    auth: auth.clone(),
+                    right: right.to_owned(),
+                }),
+                RevokeRight { auth, right } => events.push(StateEvent::RightRevoked {
+                    did,
+                    auth: auth.clone(),
+                    right: right.to_owned(),
+                }),
+                TombstoneDid {} => events.push(StateEvent::DidTombstoned { did }),
+                CompleteRecovery { auth } => {
+                    // Completing a recovery revokes all keys valid or suspended before it
+                    let doc = state_without_txn.get_doc_at(&did.to_string(), None)?;
+                    let height = doc.queried_at_height;
+                    let revoked = |k: &&KeyData| {
+                        let (from, until) = (k.state.valid_from_block, k.state.valid_until_block);
+                        is_height_in_range_exc_until(height, from, until)
+                    };
+                    for key in doc.keys.iter().filter(revoked) {
+                        let auth = key.state.authentication.to_owned();
+                        events.push(StateEvent::KeyRevoked { did: did.clone(), auth });
+                    }
+                    events.push(StateEvent::KeyAdded { did, auth: auth.clone() });
+                }
+                RevokeCredential { content_id } => {
+                    let content_id = content_id.to_owned();
+                    events.push(StateEvent::CredentialRevoked { did, content_id })
+                }
+                AddService { .. }
+                | RemoveService { .. }
+                | SetUpdatePolicy { .. }
+                | SetRecoveryPolicy { .. }
+                | StartRecovery { .. }
+                | CancelRecovery {} => {}
+            }
+        }
+    }
+    Ok(events)
+}
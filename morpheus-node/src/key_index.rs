@@ -0,0 +1,89 @@
+use super::*;
+
+/// Lists the DIDs each key or controller DID was ever added to, so the DIDs affected by a compromised key can be
+/// found without scanning all documents. Entries are kept in the order of their addition, a DID
+/// appears multiple times if the key was added to it again after it became invalid.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(super) struct KeyIndexState {
+    map: im::HashMap<String, im::Vector<String>>,
+}
+
+impl KeyIndexState {
+    fn index_key(auth: &Authentication) -> String {
+        // Controller DIDs are indexed by the DID itself, so they can be told apart from key ids
+        auth.key_id().map(|id| id.to_string()).unwrap_or_else(|| auth.to_string())
+    }
+
+    pub fn dids_of(&self, auth: &Authentication) -> Vec<String> {
+        let mut dids = Vec::new();
+        if let Some(entries) = self.map.get(&Self::index_key(auth)) {
+            for did in entries.iter() {
+                if !dids.contains(did) {
+                    dids.push(did.to_owned());
+                }
+            }
+        }
+        dids
+    }
+
+    pub fn apply(&mut self, auth: &Authentication, did: &Did) {
+        self.map.entry(Self::index_key(auth)).or_default().push_back(did.to_string());
+    }
+
+    pub fn revert(&mut self, auth: &Authentication, did: &Did) -> Result<()> {
+        let index_key = Self::index_key(auth);
+        let entries = match self.map.get_mut(&index_key) {
+            Some(entries) => entries,
+            None => bail!("Key {} was never added to any DID", auth),
+        };
+        let last = entries.pop_back();
+        ensure!(
+            last.as_ref() == Some(&did.to_string()),
+            "Key {} was last added to {:?}, cannot revert adding it to {}",
+            auth,
+            last,
+            did
+        );
+        if entries.is_empty() {
+            self.map.remove(&index_key);
+        }
+        Ok(())
+    }
+}
+
+/// A key entry in a DID document that matches a queried key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyUsage {
+    #[serde(with = "serde_str")]
+    pub did: Did,
+    pub key_link: String,
+    #[serde(flatten)]
+    pub key: KeyData,
+    pub rights: HashMap<Right, KeyRightState>,
+}
+
+impl KeyUsage {
+    pub(super) fn collect_from(doc: &DidDocument, auth: &Authentication) -> Vec<Self> {
+        doc.keys
+            .iter()
+            .enumerate()
+            .filter(|(_idx, key)| key.state.authentication == *auth)
+            .map(|(idx, key)| {
+                let key_link = format!("#{}", idx);
+                let rights = doc
+                    .rights
+                    .iter()
+                    .filter_map(|(right, histories)| {
+                        histories
+                            .iter()
+                            .find(|h| h.derived.key_link == key_link)
+                            .map(|h| (right.to_owned(), h.state.to_owned()))
+                    })
+                    .collect();
+                Self { did: doc.did.to_owned(), key_link, key: key.to_owned(), rights }
+            })
+            .collect()
+    }
+}
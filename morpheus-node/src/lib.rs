@@ -1,37 +1,66 @@
+mod block_times;
+mod checkpoints;
+mod diff;
 mod docs;
+mod events;
+mod key_index;
+mod pending;
+mod query;
+mod snapshot;
 mod state;
 mod state_holder;
+mod state_tree;
 #[cfg(test)]
 mod test;
 mod time_series;
 mod txns;
 mod util;
 
+use block_times::*;
+use checkpoints::*;
 use docs::*;
+use events::*;
+use key_index::*;
+use pending::*;
+use state_tree::*;
 use txns::*;
 use util::*;
 
+pub use diff::*;
+pub use events::{StateChange, StateEvent, StateObserver};
+pub use key_index::KeyUsage;
+pub use pending::PendingTransactionReport;
+pub use query::*;
 pub use state::*;
 pub use state_holder::*;
 pub use time_series::*;
 
 // imports from standard library
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 
 // imports from 3rd party crates
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use once_cell::unsync::OnceCell;
 use serde::{Deserialize, Serialize};
 
 // imports from own crates
 
-use iop_journal_proto::BlockHeight;
+use iop_journal_proto::{BlockCount, BlockHeight, Timestamp};
 use iop_morpheus_proto::{
     data::{
-        Authentication, Did, DidDocument, KeyData, KeyDataDerived, KeyRightDerived,
-        KeyRightHistory, KeyRightHistoryItem, KeyRightState, KeyState, OperationError, Right,
+        hash_merkle_node, Authentication, BeforeProofHistory, BeforeProofInclusionProof, Did,
+        DidDocument, DidDocumentMetadata, DidResolutionResult, DidResolver, KeyData,
+        KeyDataDerived, KeyRightDerived, KeyRightHistory, KeyRightHistoryItem, KeyRightState,
+        KeyState, KeySuspension, MerkleSide, MerkleStep, MerkleTree, OperationError,
+        OperationErrorCode, PendingRecovery, RecoveryPolicy, Right, Service, StateInclusionProof,
+        StateLeaf, UpdatePolicy, ValidationResult,
+    },
+    txtype::{
+        MorpheusAsset, MultiSignedOperation, OperationAttempt, SignableOperationAttempt,
+        SignableOperationDetails,
     },
-    txtype::{MorpheusAsset, OperationAttempt, SignableOperationDetails, SignedOperation},
 };
@@ -0,0 +1,113 @@
+use super::*;
+
+/// Tells whether an unconfirmed transaction would be accepted if it was included in the next block
+/// after all pending transactions before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransactionReport {
+    pub txid: String,
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rejection: Option<String>,
+}
+
+/// Unconfirmed transactions from the mempool applied on top of a copy of the confirmed state, so
+/// wallets can show DID documents as they will look after the next block. The projection is only
+/// built when it is queried after the confirmed state changed, so applying a block does not apply
+/// all pending transactions again after each of its transactions.
+#[derive(Debug, Clone, Default)]
+pub(super) struct PendingOverlay {
+    transactions: Vec<BlockTransaction>,
+    projection: OnceCell<Projection>,
+}
+
+#[derive(Debug, Clone)]
+struct Projection {
+    reports: Vec<PendingTransactionReport>,
+    projected: Box<State>,
+}
+
+impl PendingOverlay {
+    pub fn reports(&self, confirmed: &State) -> Result<&[PendingTransactionReport]> {
+        Ok(&self.projection(confirmed)?.reports)
+    }
+
+    pub fn projected(&self, confirmed: &State) -> Result<&State> {
+        Ok(&self.projection(confirmed)?.projected)
+    }
+
+    pub fn add(&mut self, txid: &str, asset: &MorpheusAsset, confirmed: &State) -> Result<()> {
+        ensure!(
+            confirmed.is_confirmed(txid).is_none(),
+            "Transaction {} is already included in a block",
+            txid
+        );
+        ensure!(
+            self.transactions.iter().all(|tx| tx.txid != txid),
+            "Transaction {} is already pending",
+            txid
+        );
+        let transaction = BlockTransaction { txid: txid.to_owned(), asset: asset.to_owned() };
+        self.transactions.push(transaction);
+        self.outdate();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, txid: &str) -> Result<()> {
+        let count = self.transactions.len();
+        self.transactions.retain(|tx| tx.txid != txid);
+        ensure!(self.transactions.len() < count, "Transaction {} is not pending", txid);
+        self.outdate();
+        Ok(())
+    }
+
+    /// Must be called whenever the confirmed state changed, the projection is built again when it
+    /// is queried next time.
+    pub fn outdate(&mut self) {
+        self.projection = Default::default();
+    }
+
+    /// Forgets the transaction once it was included in a block.
+    pub fn forget(&mut self, txid: &str) {
+        self.transactions.retain(|tx| tx.txid != txid);
+        self.outdate();
+    }
+
+    /// Forgets all transactions already included in a block, e.g. after a reorganization.
+    pub fn forget_confirmed(&mut self, confirmed: &State) {
+        self.transactions.retain(|tx| confirmed.is_confirmed(&tx.txid).is_none());
+        self.outdate();
+    }
+
+    fn projection(&self, confirmed: &State) -> Result<&Projection> {
+        self.projection.get_or_try_init(|| self.project(confirmed))
+    }
+
+    /// Applies the transactions not included in a block yet on top of the `confirmed` state at the
+    /// next height. Invalid transactions are reported as rejected.
+    fn project(&self, confirmed: &State) -> Result<Projection> {
+        let mut projected = Box::new(confirmed.to_owned());
+        let height = projected.last_seen_height() + 1;
+        projected.apply(Mutation::SetBlockHeight { height })?;
+        let mut reports = Vec::with_capacity(self.transactions.len());
+        for tx in &self.transactions {
+            // Cloning is cheap and keeps the projection consistent even if the transaction is invalid
+            let mut applied = projected.clone();
+            let rejection = match StateHolder::apply_txn(&mut applied, &tx.txid, &tx.asset) {
+                Ok(None) => {
+                    projected = applied;
+                    None
+                }
+                Ok(Some(rejection)) => Some(rejection.to_string()),
+                Err(e) => Some(format!("{:#}", e)),
+            };
+            let accepted = rejection.is_none();
+            reports.push(PendingTransactionReport {
+                txid: tx.txid.to_owned(),
+                accepted,
+                rejection,
+            });
+        }
+        Ok(Projection { reports, projected })
+    }
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+/// A slice of a result set in a stable order. The next page starts after `next_cursor`, which is
+/// missing on the last page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub(super) fn take(
+        mut items: impl Iterator<Item = T>, limit: usize, cursor_of: impl Fn(&T) -> String,
+    ) -> Result<Self> {
+        ensure!(limit > 0, "Page limit must be positive");
+        let page: Vec<T> = items.by_ref().take(limit).collect();
+        let next_cursor = match page.last() {
+            Some(last) if items.next().is_some() => Some(cursor_of(last)),
+            _ => None,
+        };
+        Ok(Self { items: page, next_cursor })
+    }
+}
+
+/// Conditions for listing DIDs at the last seen height. Only DIDs with confirmed transactions are
+/// listed, all conditions given have to be met.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidFilter {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tombstoned: Option<bool>,
+    /// The DID had a confirmed transaction at or above this height.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub changed_from_height: Option<BlockHeight>,
+    /// The DID had a confirmed transaction at or below this height.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub changed_until_height: Option<BlockHeight>,
+    /// The key or controller DID is valid in the document.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub key: Option<Authentication>,
+}
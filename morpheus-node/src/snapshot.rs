@@ -0,0 +1,38 @@
+use super::*;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotRef<'a> {
+    format_version: u32,
+    state: &'a State,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotData {
+    format_version: u32,
+    // Kept as raw JSON, so a snapshot with an unknown format version is rejected before parsing its state
+    state: serde_json::Value,
+}
+
+impl State {
+    /// Increase this whenever the serialized format of `State` changes in an incompatible way.
+    pub const SNAPSHOT_FORMAT_VERSION: u32 = 4;
+
+    pub(super) fn to_snapshot(&self) -> Result<Vec<u8>> {
+        let snapshot = SnapshotRef { format_version: Self::SNAPSHOT_FORMAT_VERSION, state: self };
+        Ok(serde_json::to_vec(&snapshot)?)
+    }
+
+    pub(super) fn from_snapshot(bytes: &[u8]) -> Result<Self> {
+        let snapshot: SnapshotData = serde_json::from_slice(bytes)?;
+        ensure!(
+            snapshot.format_version == Self::SNAPSHOT_FORMAT_VERSION,
+            "Snapshot format version {} is not supported, expected {}",
+            snapshot.format_version,
+            Self::SNAPSHOT_FORMAT_VERSION
+        );
+        let state = serde_json::from_value(snapshot.state)?;
+        Ok(state)
+    }
+}
@@ -1,22 +1,89 @@
-use iop_morpheus_proto::data::BeforeProofHistory;
-
 use super::*;
 
+type Attempts<'a> = std::slice::Iter<'a, SignableOperationAttempt>;
+
+pub(super) fn attempts_unsafe_without_signature_checking(
+    op: &OperationAttempt,
+) -> Option<Attempts<'_>> {
+    match op {
+        OperationAttempt::RegisterBeforeProof { .. } => None,
+        OperationAttempt::RegisterBeforeProofBatch { .. } => None,
+        OperationAttempt::Signed(op) => Some(op.attempts_unsafe_without_signature_checking()),
+        OperationAttempt::MultiSigned(op) => Some(op.attempts_unsafe_without_signature_checking()),
+    }
+}
+
+/// The key an operation adds to a DID, which has to be kept in the key index.
+fn added_key(op: &SignableOperationDetails) -> Option<&Authentication> {
+    match op {
+        SignableOperationDetails::AddKey { auth, .. } => Some(auth),
+        SignableOperationDetails::CompleteRecovery { auth } => Some(auth),
+        _ => None,
+    }
+}
+
+/// Leaves of the state root committed by confirming a transaction at the given height, DID leaves
+/// with the documents in `state` after the transaction.
+fn committed_leaves(
+    state: &State, txid: &str, height: BlockHeight, ops: &[OperationAttempt],
+) -> Result<Vec<StateLeaf>> {
+    let mut leaves = vec![];
+    let mut dids = vec![];
+    for op in ops {
+        match op {
+            OperationAttempt::RegisterBeforeProof { content_id } => {
+                let content_id = content_id.to_owned();
+                leaves.push(StateLeaf::BeforeProof { content_id, registered_at_height: height });
+            }
+            OperationAttempt::RegisterBeforeProofBatch { root } => {
+                let root = root.to_owned();
+                leaves.push(StateLeaf::BeforeProofBatch { root, registered_at_height: height });
+            }
+            _ => {}
+        }
+        for attempt in attempts_unsafe_without_signature_checking(op).into_iter().flatten() {
+            if !dids.contains(&attempt.did) {
+                let doc = state.get_doc_at(&attempt.did.to_string(), Some(height))?;
+                leaves.push(StateLeaf::did(&doc, txid, height)?);
+                dids.push(attempt.did.to_owned());
+            }
+        }
+    }
+    Ok(leaves)
+}
+
 pub(super) enum Mutation<'a> {
     SetBlockHeight { height: BlockHeight },
     RegisterAttempt { txid: &'a str, op: &'a OperationAttempt },
     DoAttempt { op: &'a OperationAttempt },
-    ConfirmTxn { txid: &'a str },
+    ConfirmTxn { txid: &'a str, ops: &'a [OperationAttempt] },
     RejectTxn { txid: &'a str },
 }
 
-#[derive(Debug, Default, Clone)]
+// All collections are persistent (structurally shared), so cloning the state for a transaction is cheap
+// and applying the transaction copies only the entries it touches.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct State {
     last_seen_height: BlockHeight,
-    txn_status: HashMap<String, bool>,
-    before_proofs: HashMap<String, BlockHeight>,
-    did_states: HashMap<String, DidDocumentState>,
+    txn_status: im::HashMap<String, bool>,
+    before_proofs: im::HashMap<String, BlockHeight>,
+    #[serde(default)]
+    before_proof_batches: im::HashMap<String, BlockHeight>,
+    did_states: im::HashMap<String, DidDocumentState>,
     did_txns: DidTransactionsState,
+    key_index: KeyIndexState,
+    #[serde(default)]
+    block_times: BlockTimesState,
+    state_tree: StateTree,
+    // Transactions by the height they were confirmed or rejected at, so they can be pruned
+    #[serde(default)]
+    txn_heights: im::OrdMap<BlockHeight, im::Vector<String>>,
+    #[serde(default)]
+    pruned_below_height: BlockHeight,
+    // Heights of credential revocations by content id for each issuer DID
+    #[serde(default)]
+    credential_revocations: im::HashMap<String, im::HashMap<String, BlockHeight>>,
 }
 
 impl State {
@@ -24,29 +91,207 @@ impl State {
         self.last_seen_height
     }
 
+    /// History below this height was squashed by `prune_below`, so it cannot be queried.
+    pub fn pruned_below_height(&self) -> BlockHeight {
+        self.pruned_below_height
+    }
+
+    fn ensure_not_pruned(&self, height: BlockHeight) -> Result<()> {
+        ensure!(
+            height >= self.pruned_below_height,
+            "Queried height {} is pruned, history is kept from height {}",
+            height,
+            self.pruned_below_height
+        );
+        Ok(())
+    }
+
+    /// Squashes all history below `height` to free memory. Queries at `height` and above are
+    /// answered the same as before, queries below it fail. Transactions confirmed or rejected
+    /// below `height` are forgotten, except the last confirmed one of each DID.
+    pub(super) fn prune_below(&mut self, height: BlockHeight) -> Result<()> {
+        ensure!(
+            height <= self.last_seen_height,
+            "Cannot prune below height {}, because the state is only at height {}",
+            height,
+            self.last_seen_height
+        );
+        if height <= self.pruned_below_height {
+            return Ok(());
+        }
+
+        let mut pruned_states = vec![];
+        for (did_data, did_state) in self.did_states.iter() {
+            let mut pruned = did_state.to_owned();
+            pruned.prune_below(height);
+            // Only changed documents are copied
+            if pruned != *did_state {
+                pruned_states.push((did_data.to_owned(), pruned));
+            }
+        }
+        self.did_states.extend(pruned_states);
+
+        let txn_status = &self.txn_status;
+        let is_confirmed = |txid: &str| txn_status.get(txid).cloned().unwrap_or_default();
+        let (mut forgotten, kept) = self.did_txns.prune_below(height, is_confirmed);
+        let pruned_heights: Vec<_> = self.txn_heights.range(..height).map(|(h, _)| *h).collect();
+        for pruned_height in pruned_heights {
+            if let Some(txids) = self.txn_heights.remove(&pruned_height) {
+                forgotten.extend(txids);
+            }
+        }
+        for txid in forgotten.difference(&kept) {
+            self.txn_status.remove(txid);
+        }
+
+        self.state_tree.prune_below(height);
+        self.pruned_below_height = height;
+        Ok(())
+    }
+
+    /// Transactions confirmed or rejected below `pruned_below_height` are forgotten, except the
+    /// last confirmed one of each DID, so they are unknown like ones never seen in a block.
     pub fn is_confirmed(&self, txid: &str) -> Option<bool> {
         self.txn_status.get(txid).cloned()
     }
 
-    pub fn before_proof_exists_at(&self, content_id: &str, height: Option<BlockHeight>) -> bool {
-        self.before_proofs
-            .get(content_id)
-            .map(|registered_at| height.map(|h| h >= *registered_at).unwrap_or(true))
-            .unwrap_or(false)
+    pub fn before_proof_exists_at(
+        &self, content_id: &str, height: Option<BlockHeight>,
+    ) -> Result<bool> {
+        let history = self.before_proof_history(content_id, height)?;
+        Ok(history.exists_from_height.is_some())
     }
 
-    pub fn before_proof_history(&self, content_id: &str) -> BeforeProofHistory {
-        let exists_from_height = self.before_proofs.get(content_id).cloned();
-        BeforeProofHistory {
+    /// Registration of the content id as known at the given height, by default at the last seen
+    /// height.
+    pub fn before_proof_history(
+        &self, content_id: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<BeforeProofHistory> {
+        let height = height_opt.unwrap_or(self.last_seen_height);
+        self.ensure_not_pruned(height)?;
+        let exists_from_height = self
+            .before_proofs
+            .get(content_id)
+            .cloned()
+            .filter(|registered_at| *registered_at <= height);
+        Ok(BeforeProofHistory {
             content_id: content_id.to_owned(),
             exists_from_height,
-            queried_at_height: self.last_seen_height,
+            queried_at_height: height,
+            batch_root: None,
+        })
+    }
+
+    /// Like `before_proof_exists_at` for a content id included in a registered batch.
+    pub fn before_proof_exists_in_batch_at(
+        &self, proof: &BeforeProofInclusionProof, height: Option<BlockHeight>,
+    ) -> Result<bool> {
+        if let Some(height) = height {
+            self.ensure_not_pruned(height)?;
+        }
+        let history = self.before_proof_history_in_batch(proof)?;
+        let exists = history
+            .exists_from_height
+            .map(|registered_at| height.map(|h| h >= registered_at).unwrap_or(true))
+            .unwrap_or(false);
+        Ok(exists)
+    }
+
+    /// Like `before_proof_history` for a content id included in a registered batch. If the content
+    /// id was also registered on its own earlier, that registration is returned.
+    pub fn before_proof_history_in_batch(
+        &self, proof: &BeforeProofInclusionProof,
+    ) -> Result<BeforeProofHistory> {
+        proof.validate()?;
+        let mut history = self.before_proof_history(&proof.content_id, None)?;
+        if let Some(batch_height) = self.before_proof_batches.get(&proof.root) {
+            if !matches!(history.exists_from_height, Some(h) if h <= *batch_height) {
+                history.exists_from_height = Some(*batch_height);
+                history.batch_root = Some(proof.root.to_owned());
+            }
+        }
+        Ok(history)
+    }
+
+    /// Whether the issuer DID revoked the statement with the given content id at or before the
+    /// height. Any DID can revoke any content id, so revocations are kept per DID and only the one
+    /// by the issuer of the statement counts. Otherwise anyone knowing the content id of a
+    /// statement could revoke it for its issuer.
+    pub fn is_revoked_at(
+        &self, did_data: &str, content_id: &str, height: Option<BlockHeight>,
+    ) -> Result<bool> {
+        let did: Did = did_data.parse()?;
+        let revoked_at = self.credential_revocation_height(&did, content_id);
+        let revoked = match (revoked_at, height) {
+            (Some(revoked_at), Some(height)) => revoked_at <= height,
+            (revoked_at, None) => revoked_at.is_some(),
+            (None, _) => false,
+        };
+        Ok(revoked)
+    }
+
+    fn credential_revocation_height(&self, did: &Did, content_id: &str) -> Option<BlockHeight> {
+        self.credential_revocations
+            .get(&did.to_string())
+            .and_then(|revocations| revocations.get(content_id))
+            .cloned()
+    }
+
+    fn revoke_credential(&mut self, did: &Did, content_id: &str) -> Result<()> {
+        let height = self.last_seen_height;
+        let revocations = self.credential_revocations.entry(did.to_string()).or_default();
+        if let Some(old_height) = revocations.insert(content_id.to_owned(), height) {
+            bail!(OperationErrorCode::CredentialAlreadyRevoked.error(format!(
+                "Credential {} of {} is already revoked at {}",
+                content_id, did, old_height
+            )))
+        }
+        Ok(())
+    }
+
+    fn revert_credential_revocation(&mut self, did: &Did, content_id: &str) -> Result<()> {
+        let height = self.last_seen_height;
+        let did_data = did.to_string();
+        let revocations = match self.credential_revocations.get_mut(&did_data) {
+            Some(revocations) => revocations,
+            None => {
+                bail!("{} has no revoked credentials, cannot revert revoking {}", did, content_id)
+            }
+        };
+        match revocations.remove(content_id) {
+            Some(old_height) => ensure!(
+                height == old_height,
+                "Credential {} of {} was revoked at {}, cannot be reverted at {}",
+                content_id,
+                did,
+                old_height,
+                height
+            ),
+            None => bail!(
+                "Credential {} of {} was not revoked, therefore cannot be reverted",
+                content_id,
+                did
+            ),
         }
+        if revocations.is_empty() {
+            self.credential_revocations.remove(&did_data);
+        }
+        Ok(())
     }
 
+    /// Fails for ranges starting below the pruning height, where transactions were forgotten.
     pub fn get_tx_ids(
         &self, did: &str, include_attempts: bool, from_height_inc: BlockHeight,
         until_height_inc: Option<BlockHeight>,
+    ) -> Result<Option<impl Iterator<Item = &TransactionIdWithHeight>>> {
+        self.ensure_not_pruned(from_height_inc)?;
+        Ok(self.known_tx_ids(did, include_attempts, from_height_inc, until_height_inc))
+    }
+
+    // Like `get_tx_ids`, but only lists what was kept below the pruning height
+    fn known_tx_ids(
+        &self, did: &str, include_attempts: bool, from_height_inc: BlockHeight,
+        until_height_inc: Option<BlockHeight>,
     ) -> Option<impl Iterator<Item = &TransactionIdWithHeight>> {
         self.did_txns.get_between(did, from_height_inc, until_height_inc).map(move |i| {
             i.filter(move |t| {
@@ -55,24 +300,366 @@ impl State {
         })
     }
 
+    /// Like `get_tx_ids`, newest first, at most `limit` of them after the transaction given as
+    /// cursor.
+    pub fn get_tx_ids_page(
+        &self, did: &str, include_attempts: bool, from_height_inc: BlockHeight,
+        until_height_inc: Option<BlockHeight>, cursor: Option<&str>, limit: usize,
+    ) -> Result<Page<TransactionIdWithHeight>> {
+        let txns: Vec<_> = self
+            .get_tx_ids(did, include_attempts, from_height_inc, until_height_inc)?
+            .map(|txns| txns.collect())
+            .unwrap_or_default();
+        let start = match cursor {
+            Some(cursor) => match txns.iter().position(|t| t.transaction_id == cursor) {
+                Some(idx) => idx + 1,
+                None => bail!("Transaction {} of cursor is not in the history of {}", cursor, did),
+            },
+            None => 0,
+        };
+        let items = txns.into_iter().skip(start).cloned();
+        Page::take(items, limit, |t| t.transaction_id.to_owned())
+    }
+
+    /// DIDs meeting the filter in alphabetical order, at most `limit` of them after the DID given
+    /// as cursor.
+    pub fn list_dids(
+        &self, filter: &DidFilter, cursor: Option<&str>, limit: usize,
+    ) -> Result<Page<String>> {
+        let (from_height, until_height) = (filter.changed_from_height, filter.changed_until_height);
+        if let Some(from_height) = from_height {
+            self.ensure_not_pruned(from_height)?;
+            if let Some(until_height) = until_height {
+                ensure!(
+                    from_height <= until_height,
+                    "Invalid height range {}-{}",
+                    from_height,
+                    until_height
+                );
+            }
+        }
+
+        let mut dids: Vec<String> = match &filter.key {
+            Some(auth) => self.key_index.dids_of(auth),
+            None => self.did_states.keys().cloned().collect(),
+        };
+        dids.sort();
+        let after_cursor =
+            dids.into_iter().filter(|did| !matches!(cursor, Some(c) if did.as_str() <= c));
+
+        let mut matching = vec![];
+        for did in after_cursor {
+            if self.did_matches(&did, filter)? {
+                matching.push(did);
+                // One more than the limit tells whether there is a next page
+                if matching.len() > limit {
+                    break;
+                }
+            }
+        }
+        Page::take(matching.into_iter(), limit, |did| did.to_owned())
+    }
+
+    fn did_matches(&self, did: &str, filter: &DidFilter) -> Result<bool> {
+        let from_height = filter.changed_from_height.unwrap_or_default();
+        let changed = self
+            .known_tx_ids(did, false, from_height, filter.changed_until_height)
+            .map(|mut txns| txns.next().is_some())
+            .unwrap_or_default();
+        if !changed {
+            return Ok(false);
+        }
+        if filter.tombstoned.is_none() && filter.key.is_none() {
+            return Ok(true);
+        }
+        let doc = self.get_doc_at(did, None)?;
+        if matches!(filter.tombstoned, Some(tombstoned) if tombstoned != doc.tombstoned) {
+            return Ok(false);
+        }
+        let holds_key = |auth: &Authentication| {
+            KeyUsage::collect_from(&doc, auth).iter().any(|usage| usage.key.derived.valid)
+        };
+        Ok(filter.key.as_ref().map(holds_key).unwrap_or(true))
+    }
+
     pub fn last_tx_id(&self, did: &str) -> Option<&TransactionIdWithHeight> {
-        self.get_tx_ids(did, false, 0, None).and_then(|mut i| i.next())
+        self.known_tx_ids(did, false, 0, None).and_then(|mut i| i.next())
     }
 
     pub fn get_doc_at(
         &self, did_data: &str, height_opt: Option<BlockHeight>,
     ) -> Result<DidDocument> {
         let height = height_opt.unwrap_or(self.last_seen_height);
+        self.ensure_not_pruned(height)?;
         let did: Did = did_data.parse()?;
         let default_state = DidDocumentState::new(&did);
         let state = self.did_states.get(did_data).unwrap_or(&default_state);
-        let doc = state.at_height(&did, height)?;
+        let mut doc = state.at_height(&did, height)?;
+        for key in doc.keys.iter_mut() {
+            let key_state = &mut key.state;
+            key_state.valid_from_time =
+                key_state.valid_from_block.and_then(|h| self.block_times.time_of(h));
+            key_state.valid_until_time =
+                key_state.valid_until_block.and_then(|h| self.block_times.time_of(h));
+        }
         Ok(doc)
     }
 
+    /// Time of the block at the given height, if it was recorded when the block was applied.
+    pub fn block_time(&self, height: BlockHeight) -> Option<Timestamp> {
+        self.block_times.time_of(height)
+    }
+
+    /// Height of the last block with a recorded time at or before the given time.
+    pub fn height_at_time(&self, timestamp: Timestamp) -> Option<BlockHeight> {
+        self.block_times.height_at(timestamp)
+    }
+
+    fn ensure_height_at_time(&self, timestamp: Timestamp) -> Result<BlockHeight> {
+        match self.height_at_time(timestamp) {
+            Some(height) => Ok(height),
+            None => bail!("No block with a known time was forged at or before {}", timestamp),
+        }
+    }
+
+    /// Like `get_doc_at` with the height of the last block forged at or before the given time.
+    pub fn get_doc_at_time(&self, did_data: &str, timestamp: Timestamp) -> Result<DidDocument> {
+        let height = self.ensure_height_at_time(timestamp)?;
+        self.get_doc_at(did_data, Some(height))
+    }
+
+    /// Like `DidDocument::validate_right` with a range of times instead of heights.
+    pub fn validate_right_between_times(
+        &self, did_data: &str, auth: &Authentication, right: Right, from_time: Timestamp,
+        until_time: Timestamp,
+    ) -> Result<ValidationResult> {
+        ensure!(from_time < until_time, "Invalid time range {}-{}", from_time, until_time);
+        let from_height = self.ensure_height_at_time(from_time)?;
+        self.ensure_not_pruned(from_height)?;
+        let until_height = self.ensure_height_at_time(until_time)?;
+        let doc = self.get_doc_at(did_data, Some(until_height))?;
+        doc.validate_right(auth, right, from_height, until_height)
+    }
+
+    /// Resolves a DID in the format of the W3C DID Resolution specification. The metadata is
+    /// derived from the confirmed transactions of the DID until the queried height.
+    pub fn resolve_w3c(
+        &self, did_data: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<DidResolutionResult> {
+        let doc = self.get_doc_at(did_data, height_opt)?;
+        let height = doc.queried_at_height;
+        let txns: Vec<_> = self
+            .known_tx_ids(did_data, false, 0, Some(height))
+            .map(|txns| txns.collect())
+            .unwrap_or_default();
+        // The first transactions of the DID might have been pruned
+        let created_at_height =
+            txns.last().map(|t| t.height).filter(|h| *h >= self.pruned_below_height);
+        let updated_at_height = txns.first().map(|t| t.height);
+        let time_of = |height: Option<BlockHeight>| {
+            let timestamp = height.and_then(|h| self.block_times.time_of(h))?;
+            let time = Utc.timestamp_opt(timestamp, 0).single()?;
+            Some(time.to_rfc3339_opts(SecondsFormat::Secs, true))
+        };
+        let metadata = DidDocumentMetadata {
+            created: time_of(created_at_height),
+            updated: time_of(updated_at_height),
+            deactivated: doc.is_tombstoned_at(height)?,
+            version_id: txns.first().map(|t| t.transaction_id.to_owned()),
+            created_at_height,
+            updated_at_height,
+        };
+        Ok(DidResolutionResult::new(&doc, metadata))
+    }
+
+    pub(super) fn check_block_time(&self, height: BlockHeight, timestamp: Timestamp) -> Result<()> {
+        self.block_times.check(height, timestamp)
+    }
+
+    pub(super) fn record_block_time(
+        &mut self, height: BlockHeight, timestamp: Timestamp,
+    ) -> Result<()> {
+        ensure!(
+            height == self.last_seen_height,
+            "Time of block {} can be recorded only when it is applied, the last seen height is {}",
+            height,
+            self.last_seen_height
+        );
+        self.block_times.apply(height, timestamp)
+    }
+
+    pub(super) fn forget_block_times_from(&mut self, height: BlockHeight) {
+        self.block_times.revert_from(height)
+    }
+
+    /// Lists what changed in a DID document after `from_height` up to and including `until_height`.
+    pub fn get_doc_diff(
+        &self, did_data: &str, from_height: BlockHeight, until_height: BlockHeight,
+    ) -> Result<DidDocumentDiff> {
+        ensure!(from_height < until_height, "Invalid block range {}-{}", from_height, until_height);
+        ensure!(
+            until_height <= self.last_seen_height,
+            "Queried future height {}, present is {}",
+            until_height,
+            self.last_seen_height
+        );
+        self.ensure_not_pruned(from_height)?;
+        let did: Did = did_data.parse()?;
+        let default_state = DidDocumentState::new(&did);
+        let state = self.did_states.get(did_data).unwrap_or(&default_state);
+
+        let mut changes: Vec<DidChange> = state
+            .changes_between(from_height, until_height)
+            .into_iter()
+            .map(|(height, kind)| {
+                let transaction_ids = self
+                    .known_tx_ids(did_data, false, height, Some(height))
+                    .map(|txns| txns.map(|t| t.transaction_id.to_owned()).collect())
+                    .unwrap_or_default();
+                DidChange { height, kind, transaction_ids }
+            })
+            .collect();
+        changes.sort_by_key(|c| c.height);
+        Ok(DidDocumentDiff { did, from_height, until_height, changes })
+    }
+
+    fn ensure_state_root_height(&self, height: BlockHeight) -> Result<()> {
+        ensure!(
+            height <= self.last_seen_height,
+            "Queried future height {}, present is {}",
+            height,
+            self.last_seen_height
+        );
+        self.ensure_not_pruned(height)
+    }
+
+    /// Entries committed in the state root at the given height in the order of the Merkle tree:
+    /// the last confirmed transaction and document of each DID and the before proofs registered
+    /// until that height.
+    pub fn state_leaves_at(&self, height: BlockHeight) -> Result<Vec<StateLeaf>> {
+        self.ensure_state_root_height(height)?;
+        let tree = self.state_tree.at_height(height)?;
+        Ok(tree.leaves().cloned().collect())
+    }
+
+    /// Merkle root committing to the last confirmed transaction and document of all DIDs and to all
+    /// before proofs at the given height. Roots are recorded as blocks are applied.
+    pub fn state_root_at(&self, height_opt: Option<BlockHeight>) -> Result<String> {
+        let height = height_opt.unwrap_or(self.last_seen_height);
+        self.ensure_state_root_height(height)?;
+        Ok(self.state_tree.root_at(height))
+    }
+
+    fn state_proof(
+        &self, height_opt: Option<BlockHeight>, key: &str,
+    ) -> Result<Option<StateInclusionProof>> {
+        let height = height_opt.unwrap_or(self.last_seen_height);
+        self.ensure_state_root_height(height)?;
+        self.state_tree.proof(height, key)
+    }
+
+    /// Proves the last confirmed transaction of the DID at the given height, see `last_tx_id`, and
+    /// its document at that height, see `StateInclusionProof::verify_did`.
+    pub fn did_state_proof(
+        &self, did_data: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<StateInclusionProof> {
+        let did: Did = did_data.parse()?;
+        match self.state_proof(height_opt, &StateLeaf::did_key(&did))? {
+            Some(proof) => Ok(proof),
+            None => bail!("DID {} has no confirmed transactions at that height", did_data),
+        }
+    }
+
+    pub fn before_proof_state_proof(
+        &self, content_id: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<StateInclusionProof> {
+        match self.state_proof(height_opt, &StateLeaf::before_proof_key(content_id))? {
+            Some(proof) => Ok(proof),
+            None => bail!("Before proof {} is not registered at that height", content_id),
+        }
+    }
+
+    pub fn before_proof_batch_state_proof(
+        &self, root: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<StateInclusionProof> {
+        match self.state_proof(height_opt, &StateLeaf::before_proof_batch_key(root))? {
+            Some(proof) => Ok(proof),
+            None => bail!("Before proof batch {} is not registered at that height", root),
+        }
+    }
+
+    /// Lists every usage of the key in all DIDs it was ever added to, including the implicit DID
+    /// derived from the key itself. Key validity and rights are evaluated at the given height.
+    /// For a controller DID the DIDs it was added to are listed.
+    pub fn get_key_usages_at(
+        &self, auth: &Authentication, height_opt: Option<BlockHeight>,
+    ) -> Result<Vec<KeyUsage>> {
+        let mut dids: Vec<String> =
+            auth.key_id().into_iter().map(|id| Did::from(id).to_string()).collect();
+        for did in self.key_index.dids_of(auth) {
+            if !dids.contains(&did) {
+                dids.push(did);
+            }
+        }
+
+        let mut usages = Vec::new();
+        for did in dids {
+            let doc = self.get_doc_at(&did, height_opt)?;
+            usages.extend(KeyUsage::collect_from(&doc, auth));
+        }
+        Ok(usages)
+    }
+
+    /// Detects changes left behind by unwinding blocks with some of their transactions missing.
+    pub(super) fn ensure_nothing_above(&self, height: BlockHeight) -> Result<()> {
+        if let Some(txn) = self.did_txns.find_above(height) {
+            bail!("Transaction {} at height {} was not reverted", txn.transaction_id, txn.height);
+        }
+        if let Some(changed_at) = self.state_tree.changed_above(height) {
+            bail!("State root at height {} was not reverted", changed_at);
+        }
+        if let Some((content_id, registered_at)) =
+            self.before_proofs.iter().find(|(_, registered_at)| **registered_at > height)
+        {
+            bail!("Before proof {} at height {} was not reverted", content_id, registered_at);
+        }
+        if let Some((root, registered_at)) =
+            self.before_proof_batches.iter().find(|(_, registered_at)| **registered_at > height)
+        {
+            bail!("Before proof batch {} at height {} was not reverted", root, registered_at);
+        }
+        for (did, revocations) in self.credential_revocations.iter() {
+            if let Some((content_id, revoked_at)) =
+                revocations.iter().find(|(_, revoked_at)| **revoked_at > height)
+            {
+                bail!(
+                    "Revoking credential {} of {} at height {} was not reverted",
+                    content_id,
+                    did,
+                    revoked_at
+                );
+            }
+        }
+        Ok(())
+    }
+
     fn did_state_mut(
         &mut self, did: &Did, last_tx_id: &Option<String>,
     ) -> Result<&mut DidDocumentState> {
+        self.ensure_last_tx_id(did, last_tx_id)?;
+        Ok(self.did_state_entry(did))
+    }
+
+    #[cfg(test)]
+    pub(super) fn did_state(&self, did_data: &str) -> Option<&DidDocumentState> {
+        self.did_states.get(did_data)
+    }
+
+    fn did_state_entry(&mut self, did: &Did) -> &mut DidDocumentState {
+        self.did_states.entry(did.to_string()).or_insert_with(|| DidDocumentState::new(did))
+    }
+
+    fn ensure_last_tx_id(&self, did: &Did, last_tx_id: &Option<String>) -> Result<()> {
         let height = self.last_seen_height;
         let did_data = did.to_string();
 
@@ -91,19 +678,17 @@ impl State {
             } else {
                 "but it never changed yet".to_owned()
             };
-            bail!(
+            bail!(OperationErrorCode::StaleLastTxId.error(format!(
                 "Operation on {} at height {} was attempted {}, {}",
-                &did_data,
-                height,
-                op_state,
-                chain_state
-            )
+                &did_data, height, op_state, chain_state
+            )))
         }
+        Ok(())
+    }
 
-        let state =
-            self.did_states.entry(did_data.clone()).or_insert_with(|| DidDocumentState::new(&did));
-
-        Ok(state)
+    fn multi_signers(op: &MultiSignedOperation) -> Result<Vec<Authentication>> {
+        let signers = op.signers().map_err(|e| OperationErrorCode::InvalidSignature.error(e))?;
+        Ok(signers.into_iter().map(Authentication::PublicKey).collect())
     }
 
     pub(super) fn apply(&mut self, mutation: Mutation) -> Result<()> {
@@ -114,11 +699,12 @@ impl State {
                 "Transaction {} was already confirmed",
                 txid
             );
+            this.txn_heights.entry(this.last_seen_height).or_default().push_back(txid.to_owned());
             Ok(())
         }
 
-        fn insert_did_txns(this: &mut State, txid: &str, signed_op: &SignedOperation) {
-            signed_op.attempts_unsafe_without_signature_checking().for_each(|op| {
+        fn insert_did_txns(this: &mut State, txid: &str, attempts: Attempts) {
+            attempts.for_each(|op| {
                 let item = DidTransactionItem {
                     did: op.did.to_string(),
                     txid,
@@ -133,46 +719,83 @@ impl State {
         ) -> Result<()> {
             // We can change the state fearlessly even if we Err, because the caller will throw away changed state on error
             if let Some(old_height) = this.before_proofs.insert(content_id.to_owned(), height) {
-                bail!("Before proof {} is already registered at {}", content_id, old_height)
+                bail!(OperationErrorCode::BeforeProofAlreadyRegistered.error(format!(
+                    "Before proof {} is already registered at {}",
+                    content_id, old_height
+                )))
             }
             Ok(())
         }
 
         fn check_state(
-            state: &mut DidDocumentState, did: &Did, height: u32, signer: &Authentication,
+            this: &State, did: &Did, height: u32, signers: &[Authentication],
+            op: &SignableOperationDetails,
         ) -> Result<()> {
             let did_data = did.to_string();
+            let signers_str = signers.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ");
 
-            let doc = state.at_height(did, height)?;
+            let doc = this.get_doc_at(&did_data, Some(height))?;
             let tombstoned = doc.is_tombstoned_at(height)?;
-            let can_update = doc.has_right_at(signer, Right::Update, height)?;
+            // Controller and guardian DIDs are resolved from the same state
+            let can_update = match op {
+                SignableOperationDetails::StartRecovery { .. } => {
+                    doc.is_recovery_authorized_at(signers, height, this)?
+                }
+                SignableOperationDetails::CompleteRecovery { auth } => signers.contains(auth),
+                // Statements are signed by keys with the impersonation right, so they can revoke them
+                SignableOperationDetails::RevokeCredential { .. } => {
+                    let mut can_revoke = false;
+                    for signer in signers {
+                        if doc.has_right_with_resolver_at(
+                            signer,
+                            Right::Impersonation,
+                            height,
+                            this,
+                        )? {
+                            can_revoke = true;
+                            break;
+                        }
+                    }
+                    can_revoke
+                }
+                _ => doc.is_update_authorized_at(signers, height, this)?,
+            };
 
             ensure!(
                 !tombstoned,
-                "{} cannot update {} at height {}. The DID is tombstoned",
-                signer,
-                &did_data,
-                height
+                OperationErrorCode::TombstonedDid.error(format!(
+                    "{} cannot update {} at height {}. The DID is tombstoned",
+                    signers_str, &did_data, height
+                ))
             );
 
             ensure!(
                 can_update,
-                "{} has no right to update {} at height {}",
-                signer,
-                &did_data,
-                height
+                OperationErrorCode::NotAuthorized.error(format!(
+                    "{} has no right to update {} at height {}",
+                    signers_str, &did_data, height
+                ))
             );
 
             Ok(())
         }
 
-        fn apply_signed_op(this: &mut State, op: &SignedOperation) -> Result<()> {
-            op.attempts()?.try_for_each(|a| -> Result<()> {
-                let signer = Authentication::PublicKey(op.signer_public_key.parse()?);
+        fn apply_signed_op(
+            this: &mut State, signers: &[Authentication], mut attempts: Attempts,
+        ) -> Result<()> {
+            attempts.try_for_each(|a| -> Result<()> {
                 let height = this.last_seen_height;
-                let state = this.did_state_mut(&a.did, &a.last_tx_id)?;
-                check_state(state, &a.did, height, &signer)?;
-                state.apply(&a.did, height, &signer, &a.operation)
+                this.ensure_last_tx_id(&a.did, &a.last_tx_id)?;
+                check_state(this, &a.did, height, signers, &a.operation)?;
+                let state = this.did_state_entry(&a.did);
+                state.apply(&a.did, height, signers, &a.operation)?;
+                if let Some(auth) = added_key(&a.operation) {
+                    this.key_index.apply(auth, &a.did);
+                }
+                if let SignableOperationDetails::RevokeCredential { content_id } = &a.operation {
+                    this.revoke_credential(&a.did, content_id)?;
+                }
+                Ok(())
             })
         }
 
@@ -188,17 +811,48 @@ impl State {
                 self.last_seen_height = height;
             }
             RegisterAttempt { txid, op } => {
-                if let OperationAttempt::Signed(signed_op) = op {
-                    insert_did_txns(self, txid, signed_op);
+                if let Some(attempts) = attempts_unsafe_without_signature_checking(op) {
+                    insert_did_txns(self, txid, attempts);
                 }
             }
             DoAttempt { op } => match op {
                 OperationAttempt::RegisterBeforeProof { content_id } => {
                     insert_before_proof(self, content_id, self.last_seen_height)?
                 }
-                OperationAttempt::Signed(op) => apply_signed_op(self, op)?,
+                OperationAttempt::RegisterBeforeProofBatch { root } => {
+                    let height = self.last_seen_height;
+                    if let Some(old_height) =
+                        self.before_proof_batches.insert(root.to_owned(), height)
+                    {
+                        bail!(OperationErrorCode::BeforeProofBatchAlreadyRegistered.error(format!(
+                            "Before proof batch {} is already registered at {}",
+                            root, old_height
+                        )))
+                    }
+                }
+                OperationAttempt::Signed(op) => {
+                    let invalid_signature = |e| OperationErrorCode::InvalidSignature.error(e);
+                    let signer = op.signer_public_key.parse().map_err(invalid_signature)?;
+                    let attempts = op.attempts().map_err(invalid_signature)?;
+                    apply_signed_op(self, &[Authentication::PublicKey(signer)], attempts)?
+                }
+                OperationAttempt::MultiSigned(op) => {
+                    let signers = Self::multi_signers(op)?;
+                    // Signatures were checked when collecting the signers
+                    apply_signed_op(
+                        self,
+                        &signers,
+                        op.attempts_unsafe_without_signature_checking(),
+                    )?
+                }
             },
-            ConfirmTxn { txid } => insert_txn_status(self, txid, true)?,
+            ConfirmTxn { txid, ops } => {
+                insert_txn_status(self, txid, true)?;
+                let height = self.last_seen_height;
+                for leaf in committed_leaves(self, txid, height, ops)? {
+                    self.state_tree.apply(height, leaf)?;
+                }
+            }
             RejectTxn { txid } => insert_txn_status(self, txid, false)?,
         }
         Ok(())
@@ -208,6 +862,13 @@ impl State {
         fn remove_txn_status(this: &mut State, txid: &str, status: bool) -> Result<()> {
             let confirmed_opt = this.txn_status.remove(txid);
             ensure!(confirmed_opt.is_some(), "Transaction {} was not seen", txid);
+            let height = this.last_seen_height;
+            if let Some(txids) = this.txn_heights.get_mut(&height) {
+                txids.retain(|t| t != txid);
+                if txids.is_empty() {
+                    this.txn_heights.remove(&height);
+                }
+            }
             if confirmed_opt.unwrap() {
                 ensure!(
                     status,
@@ -224,8 +885,8 @@ impl State {
             Ok(())
         }
 
-        fn remove_did_txns(this: &mut State, txid: &str, signed_op: &SignedOperation) {
-            signed_op.attempts_unsafe_without_signature_checking().rev().for_each(|op| {
+        fn remove_did_txns(this: &mut State, txid: &str, attempts: Attempts) {
+            attempts.rev().for_each(|op| {
                 let item = DidTransactionItem {
                     did: op.did.to_string(),
                     txid,
@@ -254,12 +915,20 @@ impl State {
             }
         }
 
-        fn revert_signed_op(this: &mut State, op: &SignedOperation) -> Result<()> {
-            op.attempts()?.rev().try_for_each(|a| -> Result<()> {
-                let signer = Authentication::PublicKey(op.signer_public_key.parse()?);
+        fn revert_signed_op(
+            this: &mut State, signers: &[Authentication], attempts: Attempts,
+        ) -> Result<()> {
+            attempts.rev().try_for_each(|a| -> Result<()> {
                 let height = this.last_seen_height;
                 let state = this.did_state_mut(&a.did, &a.last_tx_id)?;
-                state.revert(&a.did, height, &signer, &a.operation)
+                state.revert(&a.did, height, signers, &a.operation)?;
+                if let Some(auth) = added_key(&a.operation) {
+                    this.key_index.revert(auth, &a.did)?;
+                }
+                if let SignableOperationDetails::RevokeCredential { content_id } = &a.operation {
+                    this.revert_credential_revocation(&a.did, content_id)?;
+                }
+                Ok(())
             })
         }
 
@@ -275,19 +944,59 @@ impl State {
                 self.last_seen_height = height;
             }
             RegisterAttempt { txid, op } => {
-                if let OperationAttempt::Signed(signed_op) = op {
-                    remove_did_txns(self, txid, signed_op);
+                if let Some(attempts) = attempts_unsafe_without_signature_checking(op) {
+                    remove_did_txns(self, txid, attempts);
                 }
             }
             DoAttempt { op } => match op {
                 OperationAttempt::RegisterBeforeProof { content_id } => {
                     remove_before_proof(self, content_id)?
                 }
-                OperationAttempt::Signed(op) => revert_signed_op(self, op)?,
+                OperationAttempt::RegisterBeforeProofBatch { root } => {
+                    let height = self.last_seen_height;
+                    match self.before_proof_batches.remove(root) {
+                        Some(old_height) => ensure!(
+                            height == old_height,
+                            "Before proof batch {} was registered at {}, cannot be reverted at {}",
+                            root,
+                            old_height,
+                            height
+                        ),
+                        None => bail!(
+                            "Before proof batch {} was not registered, therefore cannot be reverted",
+                            root
+                        ),
+                    }
+                }
+                OperationAttempt::Signed(op) => {
+                    let signers = [Authentication::PublicKey(op.signer_public_key.parse()?)];
+                    revert_signed_op(self, &signers, op.attempts()?)?
+                }
+                OperationAttempt::MultiSigned(op) => {
+                    let signers = Self::multi_signers(op)?;
+                    // Signatures were checked when collecting the signers
+                    revert_signed_op(
+                        self,
+                        &signers,
+                        op.attempts_unsafe_without_signature_checking(),
+                    )?
+                }
             },
-            ConfirmTxn { txid } => remove_txn_status(self, txid, true)?,
+            ConfirmTxn { txid, ops } => {
+                let height = self.last_seen_height;
+                for leaf in committed_leaves(self, txid, height, ops)?.iter().rev() {
+                    self.state_tree.revert(height, &leaf.key())?;
+                }
+                remove_txn_status(self, txid, true)?
+            }
             RejectTxn { txid } => remove_txn_status(self, txid, false)?,
         }
         Ok(())
     }
 }
+
+impl DidResolver for State {
+    fn resolve_at(&self, did: &Did, height: BlockHeight) -> Result<DidDocument> {
+        self.get_doc_at(&did.to_string(), Some(height))
+    }
+}
@@ -7,9 +7,49 @@ pub struct TransactionIdWithHeight {
     pub height: BlockHeight,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockTransaction {
+    pub txid: String,
+    pub asset: MorpheusAsset,
+}
+
+/// Morpheus transactions of a block in the order they were included in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    pub height: BlockHeight,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timestamp: Option<Timestamp>,
+    pub transactions: Vec<BlockTransaction>,
+}
+
+/// Describes the change that could not be applied or reverted consistently.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CorruptionDiagnostic {
+    pub height: BlockHeight,
+    pub mutation: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recovery {
+    /// All changes up to and including this height are kept.
+    pub restored_height: BlockHeight,
+    /// The caller must apply all blocks again starting from this height.
+    pub replay_from_height: BlockHeight,
+    pub diagnostic: CorruptionDiagnostic,
+}
+
 pub struct StateHolder {
-    corrupted: bool,
+    corruption: Option<CorruptionDiagnostic>,
     inner: Box<State>,
+    checkpoints: Checkpoints,
+    finality_depth: BlockCount,
+    pending: PendingOverlay,
+    observers: Vec<Box<dyn StateObserver>>,
 }
 
 impl StateHolder {
@@ -17,117 +57,487 @@ impl StateHolder {
         "Morpheus state is corrupt. All incoming changes will be ignored.";
 
     pub fn new() -> Self {
-        Self { corrupted: false, inner: Default::default() }
+        Self::with_checkpoints(Checkpoints::DEFAULT_INTERVAL, Checkpoints::DEFAULT_MAX_COUNT)
+    }
+
+    /// A checkpoint is kept in memory at every `interval`th block, only the last `max_count`
+    /// are kept. An `interval` of 0 disables checkpoints.
+    pub fn with_checkpoints(interval: BlockCount, max_count: usize) -> Self {
+        let checkpoints = Checkpoints::new(interval, max_count);
+        Self {
+            corruption: None,
+            inner: Default::default(),
+            checkpoints,
+            finality_depth: 0,
+            pending: Default::default(),
+            observers: Default::default(),
+        }
     }
 
     pub fn is_corrupted(&self) -> bool {
-        self.corrupted
+        self.corruption.is_some()
+    }
+
+    pub fn corruption_diagnostic(&self) -> Option<&CorruptionDiagnostic> {
+        self.corruption.as_ref()
     }
 
     pub fn ensure_not_corrupted(&self) -> Result<()> {
-        ensure!(!self.corrupted, StateHolder::CORRUPTED_ERR_MSG);
+        ensure!(!self.is_corrupted(), StateHolder::CORRUPTED_ERR_MSG);
         Ok(())
     }
 
+    /// Heights of the kept checkpoints in increasing order.
+    pub fn checkpoint_heights(&self) -> Vec<BlockHeight> {
+        self.checkpoints.heights()
+    }
+
+    /// Restores the latest checkpoint of a corrupted state, or the empty state if there is none.
+    /// The caller must replay all blocks from the returned height, e.g. using `block_applying`
+    /// and `apply_transaction`.
+    pub fn recover(&mut self) -> Result<Recovery> {
+        let diagnostic = match self.corruption.take() {
+            Some(diagnostic) => diagnostic,
+            None => bail!("Morpheus state is not corrupt, there is nothing to recover"),
+        };
+        let state = self.checkpoints.latest().cloned().unwrap_or_default();
+        let restored_height = state.last_seen_height();
+        *self.inner = state;
+        self.pending.outdate();
+        let change = StateChange {
+            height: restored_height,
+            txid: String::new(),
+            reverted: false,
+            event: StateEvent::StateRecovered,
+        };
+        self.notify(&[change]);
+        Ok(Recovery { restored_height, replay_from_height: restored_height + 1, diagnostic })
+    }
+
     pub fn state(&self) -> Result<&State> {
         self.ensure_not_corrupted()?;
         Ok(&self.inner)
     }
 
+    /// Serializes the whole Morpheus state, so a node can be restarted without replaying all blocks.
+    pub fn save_snapshot(&self) -> Result<Vec<u8>> {
+        self.state()?.to_snapshot()
+    }
+
+    /// Restores a state saved by `save_snapshot`. Snapshots of other format versions are rejected.
+    /// The restored state is kept as the first checkpoint.
+    pub fn load_snapshot(bytes: &[u8]) -> Result<Self> {
+        let state = State::from_snapshot(bytes)?;
+        let mut checkpoints = Checkpoints::default();
+        checkpoints.push(&state);
+        Ok(Self {
+            corruption: None,
+            inner: Box::new(state),
+            checkpoints,
+            finality_depth: 0,
+            pending: Default::default(),
+            observers: Default::default(),
+        })
+    }
+
+    /// Blocks deeper than `depth` below the last seen block are considered final: they are never
+    /// reverted, so their history is pruned automatically from time to time while applying blocks.
+    /// A `depth` of 0 disables automatic pruning.
+    pub fn set_finality_depth(&mut self, depth: BlockCount) {
+        self.finality_depth = depth;
+    }
+
+    pub fn finality_depth(&self) -> BlockCount {
+        self.finality_depth
+    }
+
+    /// Squashes all history below `height`, see `State::prune_below`. Blocks at or below `height`
+    /// cannot be reverted afterwards.
+    pub fn prune_below(&mut self, height: BlockHeight) -> Result<()> {
+        self.ensure_not_corrupted()?;
+        // Pruning either fails before changing anything or succeeds
+        self.inner.prune_below(height)?;
+        self.pending.outdate();
+        Ok(())
+    }
+
+    /// The observer is notified of all changes committed from now on, including the reversals.
+    pub fn subscribe(&mut self, observer: Box<dyn StateObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Adds an unconfirmed transaction to the end of the pending ones and returns how all pending
+    /// transactions would fare if they were included in the next block.
+    pub fn add_pending_transaction(
+        &mut self, txid: &str, asset: &MorpheusAsset,
+    ) -> Result<&[PendingTransactionReport]> {
+        self.ensure_not_corrupted()?;
+        self.pending.add(txid, asset, &self.inner)?;
+        self.pending.reports(&self.inner)
+    }
+
+    /// Drops a transaction from the pending ones, e.g. when it was evicted from the mempool.
+    /// Transactions included in a block are dropped automatically.
+    pub fn remove_pending_transaction(
+        &mut self, txid: &str,
+    ) -> Result<&[PendingTransactionReport]> {
+        self.ensure_not_corrupted()?;
+        self.pending.remove(txid)?;
+        self.pending.reports(&self.inner)
+    }
+
+    pub fn pending_transactions(&self) -> Result<&[PendingTransactionReport]> {
+        self.ensure_not_corrupted()?;
+        self.pending.reports(&self.inner)
+    }
+
+    /// The state after applying all pending transactions at the height after the last seen one.
+    /// It is rebuilt automatically when queried after the confirmed state changed.
+    pub fn projected_state(&self) -> Result<&State> {
+        self.ensure_not_corrupted()?;
+        self.pending.projected(&self.inner)
+    }
+
+    pub fn get_projected_doc(&self, did: &str) -> Result<DidDocument> {
+        self.projected_state()?.get_doc_at(did, None)
+    }
+
+    pub fn projected_last_tx_id(&self, did: &str) -> Result<Option<&TransactionIdWithHeight>> {
+        Ok(self.projected_state()?.last_tx_id(did))
+    }
+
     pub fn dry_run(&self, asset: &MorpheusAsset) -> Result<Vec<OperationError>> {
         if self.is_corrupted() {
             return Ok(vec![OperationError {
                 invalid_operation_attempt: None,
+                code: OperationErrorCode::StateCorrupted,
                 message: StateHolder::CORRUPTED_ERR_MSG.to_owned(),
             }]);
         }
 
-        let temp = self.inner.clone();
-        let res = asset.operation_attempts.iter().try_fold(
-            temp,
-            |mut inner, op| -> Result<Box<State>, OperationError> {
-                inner.apply(Mutation::DoAttempt { op }).map_err(|e| OperationError {
-                    invalid_operation_attempt: Some(op.clone()),
-                    message: e.to_string(),
-                })?;
-                Ok(inner)
-            },
-        );
-        // TODO The TS code just stopped on the 1st error, should we collect all?
-        let errors = match res {
-            Ok(_) => vec![],
-            Err(e) => vec![e],
-        };
+        // A failing attempt is skipped, so later ones are checked as if it was not in the asset
+        let mut inner = self.inner.clone();
+        let mut errors = vec![];
+        for op in &asset.operation_attempts {
+            let mut next = inner.clone();
+            match next.apply(Mutation::DoAttempt { op }) {
+                Ok(()) => inner = next,
+                Err(e) => errors.push(OperationError::new(Some(op.clone()), &e)),
+            }
+        }
         Ok(errors)
     }
 
     pub fn block_applying(&mut self, height: BlockHeight) -> Result<()> {
+        self.apply_block(height, None)
+    }
+
+    /// Like `block_applying`, but also records the time the block was forged at, so documents can
+    /// be queried by time. Block times must be strictly increasing.
+    pub fn block_applying_at(&mut self, height: BlockHeight, timestamp: Timestamp) -> Result<()> {
+        self.apply_block(height, Some(timestamp))
+    }
+
+    fn apply_block(&mut self, height: BlockHeight, timestamp: Option<Timestamp>) -> Result<()> {
         self.ensure_not_corrupted()?;
-        self.may_corrupt_state(|inner| inner.apply(Mutation::SetBlockHeight { height }))
+        if let Some(timestamp) = timestamp {
+            // Invalid block times are rejected without corrupting the state
+            self.inner.check_block_time(height, timestamp)?;
+        }
+        self.checkpoints.block_applying(height, &self.inner);
+        self.may_corrupt_state(
+            || format!("Applying block {}", height),
+            |inner| Self::apply_block_height(inner, height, timestamp),
+        )?;
+        Self::prune_final_blocks(&mut self.inner, self.finality_depth)?;
+        self.pending.outdate();
+        Ok(())
     }
 
     pub fn apply_transaction(&mut self, txid: &str, asset: &MorpheusAsset) -> Result<()> {
         self.ensure_not_corrupted()?;
+        let mut changes = vec![];
+        let observed = self.is_observed();
+        let rejection_opt =
+            Self::apply_txn_observed(&mut self.inner, txid, asset, observed, &mut changes)?;
+        self.pending.forget(txid);
+        self.notify(&changes);
+        match rejection_opt {
+            Some(rejection) => Err(rejection),
+            None => Ok(()),
+        }
+    }
+
+    pub fn block_reverting(&mut self, height: BlockHeight) -> Result<()> {
+        self.ensure_not_corrupted()?;
+        Self::ensure_revertable(&self.inner, height)?;
+        self.checkpoints.block_reverting(height);
+        self.may_corrupt_state(
+            || format!("Reverting block {}", height),
+            |inner| {
+                inner.revert(Mutation::SetBlockHeight { height })?;
+                inner.forget_block_times_from(height);
+                Ok(())
+            },
+        )?;
+        self.pending.outdate();
+        Ok(())
+    }
+
+    pub fn revert_transaction(&mut self, txid: &str, asset: &MorpheusAsset) -> Result<()> {
+        self.ensure_not_corrupted()?;
+        let mut changes = vec![];
+        let observed = self.is_observed();
+        self.may_corrupt_state(
+            || format!("Reverting transaction {}", txid),
+            |inner| Self::revert_txn_observed(inner, txid, asset, observed, &mut changes),
+        )?;
+        self.pending.outdate();
+        self.notify(&changes);
+        Ok(())
+    }
+
+    /// Unwinds all blocks above `height` in one step, e.g. to the fork point of a reorganization.
+    /// `reverted_blocks` must contain all blocks with Morpheus transactions above `height` in
+    /// increasing order of height. If anything fails, the state is left unchanged.
+    pub fn revert_to_height(
+        &mut self, height: BlockHeight, reverted_blocks: &[Block],
+    ) -> Result<()> {
+        self.reorganize(height, reverted_blocks, &[])
+    }
+
+    /// Unwinds all blocks above `fork_height` like `revert_to_height`, then applies the blocks of
+    /// the new branch in increasing order of height. Transactions of the new branch might be rejected
+    /// like in `apply_transaction`. If anything else fails, the state is left unchanged.
+    pub fn reorganize(
+        &mut self, fork_height: BlockHeight, reverted_blocks: &[Block], applied_blocks: &[Block],
+    ) -> Result<()> {
+        self.ensure_not_corrupted()?;
+        Self::ensure_increasing_heights(fork_height, reverted_blocks)?;
+        Self::ensure_increasing_heights(fork_height, applied_blocks)?;
+
+        // Cloning is cheap and the state is only swapped when all steps succeeded
+        let mut inner = self.inner.clone();
+        let mut checkpoints = self.checkpoints.clone();
+        let mut changes = vec![];
+        let observed = self.is_observed();
+        checkpoints.block_reverting(fork_height + 1);
+        let last_seen_height = inner.last_seen_height();
+        Self::ensure_revertable(&inner, fork_height + 1)?;
+        ensure!(
+            fork_height <= last_seen_height,
+            "Cannot revert to height {}, because the state is only at height {}",
+            fork_height,
+            last_seen_height
+        );
+        for block in reverted_blocks.iter().rev() {
+            ensure!(
+                block.height <= last_seen_height,
+                "Cannot revert block at height {}, because the state is only at height {}",
+                block.height,
+                last_seen_height
+            );
+            inner.revert(Mutation::SetBlockHeight { height: block.height })?;
+            block.transactions.iter().rev().try_for_each(|tx| -> Result<()> {
+                Self::revert_txn_observed(&mut inner, &tx.txid, &tx.asset, observed, &mut changes)
+                    .with_context(|| format!("Reverting block at height {}", block.height))
+            })?;
+        }
+        // Empty blocks might not be listed, but their times have to be forgotten as well
+        inner.forget_block_times_from(fork_height + 1);
+        inner.revert(Mutation::SetBlockHeight { height: fork_height })?;
+        inner.ensure_nothing_above(fork_height)?;
+
+        for block in applied_blocks {
+            checkpoints.block_applying(block.height, &inner);
+            Self::apply_block_height(&mut inner, block.height, block.timestamp)?;
+            block.transactions.iter().try_for_each(|tx| -> Result<()> {
+                // Rejected transactions are recorded in the state, only other errors abort the reorg
+                let _rejection = Self::apply_txn_observed(
+                    &mut inner,
+                    &tx.txid,
+                    &tx.asset,
+                    observed,
+                    &mut changes,
+                )
+                .with_context(|| format!("Applying block at height {}", block.height))?;
+                Ok(())
+            })?;
+        }
+
+        Self::prune_final_blocks(&mut inner, self.finality_depth)?;
+        self.inner = inner;
+        self.pending.forget_confirmed(&self.inner);
+        self.checkpoints = checkpoints;
+        self.notify(&changes);
+        Ok(())
+    }
+
+    fn ensure_increasing_heights(fork_height: BlockHeight, blocks: &[Block]) -> Result<()> {
+        let mut prev_height = fork_height;
+        for block in blocks {
+            ensure!(
+                prev_height < block.height,
+                "Block at height {} must be above height {}",
+                block.height,
+                prev_height
+            );
+            prev_height = block.height;
+        }
+        Ok(())
+    }
+
+    fn ensure_revertable(inner: &State, height: BlockHeight) -> Result<()> {
+        let pruned_below_height = inner.pruned_below_height();
+        ensure!(
+            height > pruned_below_height,
+            "Cannot revert block at height {}, history is pruned below height {}",
+            height,
+            pruned_below_height
+        );
+        Ok(())
+    }
+
+    // Pruning is done only after `depth` blocks became final since the last pruning, so its cost is
+    // shared by many blocks
+    fn prune_final_blocks(inner: &mut State, depth: BlockCount) -> Result<()> {
+        if depth == 0 {
+            return Ok(());
+        }
+        let final_height = inner.last_seen_height().saturating_sub(depth);
+        if final_height >= inner.pruned_below_height() + depth {
+            inner.prune_below(final_height)?;
+        }
+        Ok(())
+    }
+
+    fn apply_block_height(
+        inner: &mut State, height: BlockHeight, timestamp: Option<Timestamp>,
+    ) -> Result<()> {
+        inner.apply(Mutation::SetBlockHeight { height })?;
+        if let Some(timestamp) = timestamp {
+            inner.record_block_time(height, timestamp)?;
+        }
+        Ok(())
+    }
+
+    fn is_observed(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    fn notify(&mut self, changes: &[StateChange]) {
+        for observer in self.observers.iter_mut() {
+            for change in changes {
+                observer.on_change(change);
+            }
+        }
+    }
+
+    /// Like `apply_txn`, but also collects the changes into `changes` if `observed`.
+    fn apply_txn_observed(
+        inner: &mut Box<State>, txid: &str, asset: &MorpheusAsset, observed: bool,
+        changes: &mut Vec<StateChange>,
+    ) -> Result<Option<anyhow::Error>> {
+        if !observed {
+            return Self::apply_txn(inner, txid, asset);
+        }
+        // Cloning is cheap thanks to structural sharing. The transaction is only committed after
+        // its changes were collected, so observers cannot miss an applied transaction.
+        let mut applied = inner.clone();
+        let rejection_opt = Self::apply_txn(&mut applied, txid, asset)?;
+        let outcome = match &rejection_opt {
+            Some(rejection) => TxnOutcome::Rejected { error: Some(rejection.to_string()) },
+            None => TxnOutcome::Confirmed,
+        };
+        changes.extend(txn_changes(inner, txid, asset, outcome, false)?);
+        *inner = applied;
+        Ok(rejection_opt)
+    }
+
+    /// Like `revert_txn`, but also collects the reversed changes into `changes` if `observed`.
+    fn revert_txn_observed(
+        inner: &mut State, txid: &str, asset: &MorpheusAsset, observed: bool,
+        changes: &mut Vec<StateChange>,
+    ) -> Result<()> {
+        let confirmed_opt = inner.is_confirmed(txid);
+        Self::revert_txn(inner, txid, asset)?;
+        if observed {
+            let outcome = match confirmed_opt {
+                Some(true) => TxnOutcome::Confirmed,
+                _ => TxnOutcome::Rejected { error: None },
+            };
+            changes.extend(txn_changes(inner, txid, asset, outcome, true)?);
+        }
+        Ok(())
+    }
+
+    /// Returns the error that rejected the transaction. An `Err` means the state could not
+    /// be updated consistently.
+    pub(super) fn apply_txn(
+        inner: &mut Box<State>, txid: &str, asset: &MorpheusAsset,
+    ) -> Result<Option<anyhow::Error>> {
         asset
             .operation_attempts
             .iter()
-            .try_for_each(|op| self.inner.apply(Mutation::RegisterAttempt { txid, op }))?;
+            .try_for_each(|op| inner.apply(Mutation::RegisterAttempt { txid, op }))?;
+        // Cloning is cheap, only the DIDs touched by the operations will be copied
         let inner_res = asset.operation_attempts.iter().try_fold(
-            self.inner.clone(),
+            inner.clone(),
             |mut inner, op| -> Result<Box<State>> {
                 inner.apply(Mutation::DoAttempt { op })?;
                 Ok(inner)
             },
         );
         match inner_res {
-            Ok(mut inner) => {
-                inner.apply(Mutation::ConfirmTxn { txid })?;
-                self.inner = inner;
-                Ok(())
+            Ok(mut applied) => {
+                let ops = &asset.operation_attempts;
+                applied.apply(Mutation::ConfirmTxn { txid, ops })?;
+                *inner = applied;
+                Ok(None)
             }
             Err(e) => {
-                self.inner.apply(Mutation::RejectTxn { txid })?;
-                Err(e)
+                inner.apply(Mutation::RejectTxn { txid })?;
+                Ok(Some(e))
             }
         }
     }
 
-    pub fn block_reverting(&mut self, height: BlockHeight) -> Result<()> {
-        self.ensure_not_corrupted()?;
-        self.may_corrupt_state(|inner| inner.revert(Mutation::SetBlockHeight { height }))
-    }
-
-    pub fn revert_transaction(&mut self, txid: &str, asset: &MorpheusAsset) -> Result<()> {
-        self.ensure_not_corrupted()?;
-        self.may_corrupt_state(|inner| {
-            let confirmed_opt = inner.is_confirmed(txid);
-            ensure!(
-                confirmed_opt.is_some(),
-                "Transaction {} has not been applied, cannot revert.",
-                txid
-            );
+    fn revert_txn(inner: &mut State, txid: &str, asset: &MorpheusAsset) -> Result<()> {
+        let confirmed_opt = inner.is_confirmed(txid);
+        ensure!(
+            confirmed_opt.is_some(),
+            "Transaction {} has not been applied, cannot revert.",
+            txid
+        );
 
-            // Option::unwrap is panic-free after handling None above
-            if confirmed_opt.unwrap() {
-                inner.revert(Mutation::ConfirmTxn { txid })?;
-                asset.operation_attempts.iter().rev().try_for_each(|op| -> Result<()> {
-                    inner.revert(Mutation::DoAttempt { op })?;
-                    Ok(())
-                })?;
-            } else {
-                inner.revert(Mutation::RejectTxn { txid })?;
-            }
+        // Option::unwrap is panic-free after handling None above
+        if confirmed_opt.unwrap() {
+            let ops = &asset.operation_attempts;
+            inner.revert(Mutation::ConfirmTxn { txid, ops })?;
             asset.operation_attempts.iter().rev().try_for_each(|op| -> Result<()> {
-                inner.revert(Mutation::RegisterAttempt { txid, op })?;
+                inner.revert(Mutation::DoAttempt { op })?;
                 Ok(())
             })?;
+        } else {
+            inner.revert(Mutation::RejectTxn { txid })?;
+        }
+        asset.operation_attempts.iter().rev().try_for_each(|op| -> Result<()> {
+            inner.revert(Mutation::RegisterAttempt { txid, op })?;
             Ok(())
-        })
+        })?;
+        Ok(())
     }
 
-    fn may_corrupt_state(&mut self, action: impl FnOnce(&mut State) -> Result<()>) -> Result<()> {
+    fn may_corrupt_state(
+        &mut self, mutation: impl FnOnce() -> String, action: impl FnOnce(&mut State) -> Result<()>,
+    ) -> Result<()> {
+        let height = self.inner.last_seen_height();
         if let Err(e) = action(&mut self.inner) {
-            self.corrupted = true;
+            self.corruption = Some(CorruptionDiagnostic {
+                height,
+                mutation: mutation(),
+                error: format!("{:#}", e),
+            });
             Err(e)
         } else {
             Ok(())
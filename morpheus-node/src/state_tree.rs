@@ -0,0 +1,213 @@
+use super::*;
+
+/// Restores a leaf to undo a change, e.g. to rebuild the tree of an earlier height.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeafChange {
+    index: usize,
+    /// A missing leaf means the change appended the leaf.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    previous: Option<StateLeaf>,
+}
+
+/// Merkle tree of the state root, updated leaf by leaf as transactions are confirmed, so the root
+/// of each block is recorded without hashing the whole state again. Leaves are never reordered,
+/// so an update only rehashes the nodes above the changed leaf. Changes are kept by height, so
+/// proofs for earlier heights are made by undoing the changes above them.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct StateTree {
+    leaves: im::Vector<StateLeaf>,
+    // Indexes of leaves by their keys
+    positions: im::HashMap<String, usize>,
+    // Node hashes of each level, starting with the leaf hashes and ending with the root
+    levels: Vec<im::Vector<String>>,
+    // Roots of the heights the tree changed at
+    roots: im::OrdMap<BlockHeight, String>,
+    changes: im::OrdMap<BlockHeight, im::Vector<LeafChange>>,
+}
+
+impl StateTree {
+    pub fn root(&self) -> String {
+        match self.levels.last().and_then(|level| level.front()) {
+            Some(root) => root.to_owned(),
+            None => MerkleTree::new(vec![]).root(),
+        }
+    }
+
+    pub fn root_at(&self, height: BlockHeight) -> String {
+        match self.roots.range(..=height).next_back() {
+            Some((_height, root)) => root.to_owned(),
+            None => MerkleTree::new(vec![]).root(),
+        }
+    }
+
+    /// The tree as it was after the last block at or below `height`.
+    pub fn at_height(&self, height: BlockHeight) -> Result<Self> {
+        let mut tree = self.to_owned();
+        let later: Vec<BlockHeight> = tree.changes.range(height + 1..).map(|(h, _)| *h).collect();
+        for changed_at in later.into_iter().rev() {
+            if let Some(changes) = tree.changes.remove(&changed_at) {
+                for change in changes.into_iter().rev() {
+                    tree.undo(change)?;
+                }
+            }
+            tree.roots.remove(&changed_at);
+        }
+        Ok(tree)
+    }
+
+    pub fn leaves(&self) -> impl Iterator<Item = &StateLeaf> {
+        self.leaves.iter()
+    }
+
+    pub fn proof(&self, height: BlockHeight, key: &str) -> Result<Option<StateInclusionProof>> {
+        let tree = self.at_height(height)?;
+        let index = match tree.positions.get(key) {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+        let mut path = vec![];
+        let mut node = index;
+        for level in &tree.levels[..tree.levels.len() - 1] {
+            let sibling = node ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                let side = if sibling < node { MerkleSide::Left } else { MerkleSide::Right };
+                path.push(MerkleStep { side, hash: hash.to_owned() });
+            }
+            node /= 2;
+        }
+        let leaf = tree.leaves[index].to_owned();
+        Ok(Some(StateInclusionProof { height, leaf, path }))
+    }
+
+    /// Adds the leaf or replaces the one with the same key.
+    pub fn apply(&mut self, height: BlockHeight, leaf: StateLeaf) -> Result<()> {
+        let hash = leaf.hash()?;
+        let change = match self.positions.get(&leaf.key()) {
+            Some(index) => {
+                let index = *index;
+                let previous = self.leaves.set(index, leaf);
+                self.set_leaf_hash(index, hash);
+                LeafChange { index, previous: Some(previous) }
+            }
+            None => {
+                let index = self.leaves.len();
+                self.positions.insert(leaf.key(), index);
+                self.leaves.push_back(leaf);
+                self.set_leaf_hash(index, hash);
+                LeafChange { index, previous: None }
+            }
+        };
+        self.changes.entry(height).or_default().push_back(change);
+        self.roots.insert(height, self.root());
+        Ok(())
+    }
+
+    /// Undoes the last change at `height`, which must have committed a leaf with the given key.
+    pub fn revert(&mut self, height: BlockHeight, key: &str) -> Result<()> {
+        let change = match self.changes.get_mut(&height).and_then(|c| c.pop_back()) {
+            Some(change) => change,
+            None => bail!("State root has no changes at height {} to revert", height),
+        };
+        let changed_key = self.leaves.get(change.index).map(|leaf| leaf.key());
+        ensure!(
+            changed_key.as_deref() == Some(key),
+            "Last change of the state root at height {} is not about {}",
+            height,
+            key
+        );
+        self.undo(change)?;
+        if self.changes.get(&height).map(|c| c.is_empty()).unwrap_or_default() {
+            self.changes.remove(&height);
+            self.roots.remove(&height);
+        } else {
+            self.roots.insert(height, self.root());
+        }
+        Ok(())
+    }
+
+    /// Forgets the changes below `height`, so the tree cannot be rebuilt for earlier heights. The
+    /// root at `height` is kept even if the tree last changed below it.
+    pub fn prune_below(&mut self, height: BlockHeight) {
+        let root = self.root_at(height);
+        let (_pruned, changes_at_height, changes_above) = self.changes.split_lookup(&height);
+        self.changes = changes_above;
+        if let Some(changes) = changes_at_height {
+            self.changes.insert(height, changes);
+        }
+        let (_pruned, at_height, above) = self.roots.split_lookup(&height);
+        self.roots = above;
+        self.roots.insert(height, at_height.unwrap_or(root));
+    }
+
+    pub fn changed_above(&self, height: BlockHeight) -> Option<BlockHeight> {
+        self.changes.range(height + 1..).next().map(|(h, _)| *h)
+    }
+
+    fn undo(&mut self, change: LeafChange) -> Result<()> {
+        match change.previous {
+            Some(previous) => {
+                ensure!(change.index < self.leaves.len(), "Leaf {} is missing", change.index);
+                let hash = previous.hash()?;
+                self.leaves.set(change.index, previous);
+                self.set_leaf_hash(change.index, hash);
+            }
+            None => {
+                ensure!(
+                    change.index + 1 == self.leaves.len(),
+                    "Only the last leaf can be removed, not leaf {}",
+                    change.index
+                );
+                if let Some(leaf) = self.leaves.pop_back() {
+                    self.positions.remove(&leaf.key());
+                }
+                self.levels[0].pop_back();
+                self.rehash_from(change.index.saturating_sub(1));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_leaf_hash(&mut self, index: usize, hash: String) {
+        if self.levels.is_empty() {
+            self.levels.push(Default::default());
+        }
+        if index < self.levels[0].len() {
+            self.levels[0].set(index, hash);
+        } else {
+            self.levels[0].push_back(hash);
+        }
+        self.rehash_from(index);
+    }
+
+    // Recalculates the ancestors of the node at `index` on the leaf level. Levels shrink or grow
+    // with the number of leaves, a node without a sibling is promoted like in `MerkleTree`.
+    fn rehash_from(&mut self, mut index: usize) {
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            if self.levels.len() == level + 1 {
+                self.levels.push(Default::default());
+            }
+            let parent_len = self.levels[level].len().div_ceil(2);
+            let parent = index / 2;
+            let children = &self.levels[level];
+            let node = match children.get(2 * parent + 1) {
+                Some(right) => hash_merkle_node(&children[2 * parent], right),
+                None => children[2 * parent].to_owned(),
+            };
+            let parents = &mut self.levels[level + 1];
+            if parents.len() > parent_len {
+                parents.truncate(parent_len);
+            }
+            if parent < parents.len() {
+                parents.set(parent, node);
+            } else {
+                parents.push_back(node);
+            }
+            index = parent;
+            level += 1;
+        }
+        self.levels.truncate(level + 1);
+    }
+}
@@ -1,4 +1,94 @@
-// use super::*;
+use super::*;
+
+use iop_keyvault::{
+    ed25519::EdPrivateKey,
+    multicipher::{MKeyId, MPrivateKey},
+    PrivateKey, PublicKey,
+};
+use iop_morpheus_proto::{
+    crypto::sign::{PrivateKeySigner, SyncMorpheusSigner},
+    data::{BeforeProofBatch, RecoveryPolicy, ServiceType, UpdatePolicy, ValidationStatus},
+    txtype::{SignableOperation, SignableOperationAttempt},
+};
+
+pub(crate) fn private_key(idx: u8) -> MPrivateKey {
+    let sk = EdPrivateKey::from_bytes([idx; 32]).unwrap();
+    MPrivateKey::from(sk)
+}
+
+pub(crate) fn key_id(idx: u8) -> MKeyId {
+    private_key(idx).public_key().key_id()
+}
+
+pub(crate) fn did(idx: u8) -> Did {
+    Did::from(key_id(idx))
+}
+
+pub(crate) fn auth(idx: u8) -> Authentication {
+    Authentication::KeyId(key_id(idx))
+}
+
+pub(crate) fn signed_asset(
+    signer_idx: u8, did: &Did, last_tx_id: Option<&str>, ops: Vec<SignableOperationDetails>,
+) -> MorpheusAsset {
+    let signables = ops
+        .into_iter()
+        .map(|operation| SignableOperationAttempt {
+            did: did.to_owned(),
+            last_tx_id: last_tx_id.map(|t| t.to_owned()),
+            operation,
+        })
+        .collect();
+    let signer = PrivateKeySigner::new(private_key(signer_idx));
+    let signed = SignableOperation::new(signables).sign(&signer).unwrap();
+    MorpheusAsset::new(vec![OperationAttempt::Signed(signed)])
+}
+
+pub(crate) fn multi_signed_asset(
+    signer_idxs: &[u8], did: &Did, last_tx_id: Option<&str>, ops: Vec<SignableOperationDetails>,
+) -> MorpheusAsset {
+    let signables = ops
+        .into_iter()
+        .map(|operation| SignableOperationAttempt {
+            did: did.to_owned(),
+            last_tx_id: last_tx_id.map(|t| t.to_owned()),
+            operation,
+        })
+        .collect();
+    let signers: Vec<_> =
+        signer_idxs.iter().map(|idx| PrivateKeySigner::new(private_key(*idx))).collect();
+    let signers: Vec<&dyn SyncMorpheusSigner> =
+        signers.iter().map(|s| s as &dyn SyncMorpheusSigner).collect();
+    let signed = SignableOperation::new(signables).multi_sign(&signers).unwrap();
+    MorpheusAsset::new(vec![OperationAttempt::MultiSigned(signed)])
+}
+
+pub(crate) fn before_proof_asset(content_id: &str) -> MorpheusAsset {
+    MorpheusAsset::new(vec![OperationAttempt::RegisterBeforeProof {
+        content_id: content_id.to_owned(),
+    }])
+}
+
+/// DID 1 adds key 2 at height 2, grants it impersonation at height 3,
+/// and a before proof is also registered at height 3
+pub(crate) fn sample_state() -> StateHolder {
+    let did = did(1);
+    let mut state = StateHolder::new();
+
+    state.block_applying(2).unwrap();
+    let add_key = SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None };
+    state.apply_transaction("tx1", &signed_asset(1, &did, None, vec![add_key])).unwrap();
+
+    state.block_applying(3).unwrap();
+    let add_right = SignableOperationDetails::AddRight {
+        auth: auth(2),
+        right: Right::Impersonation.to_string(),
+    };
+    state.apply_transaction("tx2", &signed_asset(1, &did, Some("tx1"), vec![add_right])).unwrap();
+    state.apply_transaction("tx3", &before_proof_asset("cjuFoo")).unwrap();
+
+    state
+}
 
 #[test]
 pub fn did_doc_format() {
@@ -7,3 +97,1530 @@ pub fn did_doc_format() {
     // let op_attempts1 = vec![OperationAttempt::Signed()]
     // state.apply_transaction("tx1", MorpheusAsset::new(op_attempts1) )
 }
+
+#[test]
+fn snapshot_roundtrip() {
+    let state = sample_state();
+
+    let bytes = state.save_snapshot().unwrap();
+    let loaded = StateHolder::load_snapshot(&bytes).unwrap();
+
+    assert_eq!(loaded.state().unwrap(), state.state().unwrap());
+    assert_eq!(
+        loaded.state().unwrap().get_doc_at(&did(1).to_string(), None).unwrap(),
+        state.state().unwrap().get_doc_at(&did(1).to_string(), None).unwrap()
+    );
+}
+
+#[test]
+fn snapshot_rejects_other_format_version() {
+    let state = sample_state();
+
+    let mut snapshot: serde_json::Value =
+        serde_json::from_slice(&state.save_snapshot().unwrap()).unwrap();
+    snapshot["formatVersion"] = serde_json::json!(State::SNAPSHOT_FORMAT_VERSION + 1);
+    let bytes = serde_json::to_vec(&snapshot).unwrap();
+
+    let err = StateHolder::load_snapshot(&bytes).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Snapshot format version {} is not supported, expected {}",
+            State::SNAPSHOT_FORMAT_VERSION + 1,
+            State::SNAPSHOT_FORMAT_VERSION
+        )
+    );
+}
+
+#[test]
+fn failed_transaction_leaves_state_untouched() {
+    let mut state = sample_state();
+    let did = did(1);
+    let doc_before = state.state().unwrap().get_doc_at(&did.to_string(), None).unwrap();
+
+    state.block_applying(4).unwrap();
+    let add_key = SignableOperationDetails::AddKey { auth: auth(3), expires_at_height: None };
+    let revoke_key = SignableOperationDetails::RevokeKey { auth: auth(4) };
+    let asset = signed_asset(1, &did, Some("tx2"), vec![add_key, revoke_key]);
+    state.apply_transaction("tx4", &asset).unwrap_err();
+
+    let state = state.state().unwrap();
+    assert_eq!(state.is_confirmed("tx4"), Some(false));
+    assert_eq!(state.last_tx_id(&did.to_string()).unwrap().transaction_id, "tx2");
+    let doc_after = state.get_doc_at(&did.to_string(), Some(3)).unwrap();
+    assert_eq!(doc_after, doc_before);
+}
+
+#[test]
+fn cloned_state_shares_unchanged_dids() {
+    let mut state = StateHolder::new();
+    state.block_applying(2).unwrap();
+    for idx in 1..=200 {
+        let add_key =
+            SignableOperationDetails::AddKey { auth: auth(idx + 1), expires_at_height: None };
+        let asset = signed_asset(idx, &did(idx), None, vec![add_key]);
+        state.apply_transaction(&format!("tx{}", idx), &asset).unwrap();
+    }
+    let snapshot = state.state().unwrap().clone();
+
+    state.block_applying(3).unwrap();
+    let add_key = SignableOperationDetails::AddKey { auth: auth(250), expires_at_height: None };
+    state
+        .apply_transaction("tx201", &signed_asset(1, &did(1), Some("tx1"), vec![add_key]))
+        .unwrap();
+
+    // Entries are shared unless they were in a map node copied for the changed DID
+    let current = state.state().unwrap();
+    let is_shared = |idx: u8| {
+        let did = did(idx).to_string();
+        std::ptr::eq(snapshot.did_state(&did).unwrap(), current.did_state(&did).unwrap())
+    };
+    assert!(!is_shared(1));
+    let shared_count = (2..=200).filter(|idx| is_shared(*idx)).count();
+    assert!(shared_count > 150, "only {} of 199 DIDs are shared", shared_count);
+}
+
+#[test]
+fn services_are_tracked_over_heights() {
+    let mut state = sample_state();
+    let did = did(1);
+    let hub = Service {
+        type_: ServiceType::Hub,
+        name: "hub".to_owned(),
+        service_endpoint: "https://hub.example.com".to_owned(),
+    };
+
+    state.block_applying(4).unwrap();
+    let add_service = SignableOperationDetails::AddService { service: hub.clone() };
+    let add_asset = signed_asset(1, &did, Some("tx2"), vec![add_service]);
+    state.apply_transaction("tx4", &add_asset).unwrap();
+
+    state.block_applying(5).unwrap();
+    let remove_service = SignableOperationDetails::RemoveService { name: "hub".to_owned() };
+    let remove_asset = signed_asset(1, &did, Some("tx4"), vec![remove_service]);
+    state.apply_transaction("tx5", &remove_asset).unwrap();
+
+    let services_at = |state: &StateHolder, height| {
+        state.state().unwrap().get_doc_at(&did.to_string(), Some(height)).unwrap().services
+    };
+    assert!(services_at(&state, 3).is_empty());
+    assert_eq!(services_at(&state, 4), vec![hub.clone()]);
+    assert!(services_at(&state, 5).is_empty());
+
+    state.revert_transaction("tx5", &remove_asset).unwrap();
+    assert_eq!(services_at(&state, 5), vec![hub]);
+
+    state.block_reverting(4).unwrap();
+    state.revert_transaction("tx4", &add_asset).unwrap();
+    assert!(services_at(&state, 4).is_empty());
+}
+
+#[test]
+fn custom_rights_can_be_granted_and_reverted() {
+    let mut state = sample_state();
+    let did = did(1);
+    let sign_invoices: Right = "sign-invoices".parse().unwrap();
+    let doc_before = state.state().unwrap().get_doc_at(&did.to_string(), None).unwrap();
+
+    state.block_applying(4).unwrap();
+    let add_right =
+        SignableOperationDetails::AddRight { auth: auth(2), right: sign_invoices.to_string() };
+    let asset = signed_asset(1, &did, Some("tx2"), vec![add_right]);
+    state.apply_transaction("tx4", &asset).unwrap();
+
+    let doc = state.state().unwrap().get_doc_at(&did.to_string(), Some(4)).unwrap();
+    assert!(!doc.has_right_at(&auth(2), sign_invoices.clone(), 3).unwrap());
+    assert!(doc.has_right_at(&auth(2), sign_invoices.clone(), 4).unwrap());
+    assert!(!doc.has_right_at(&auth(1), sign_invoices.clone(), 4).unwrap());
+    let validation = doc.validate_right(&auth(2), sign_invoices.clone(), 3, 4).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Invalid);
+    let validation = doc.validate_right(&auth(1), sign_invoices.clone(), 3, 4).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Invalid);
+
+    state.revert_transaction("tx4", &asset).unwrap();
+    let doc_after = state.state().unwrap().get_doc_at(&did.to_string(), Some(3)).unwrap();
+    assert!(!doc_after.rights.contains_key(&sign_invoices));
+    assert_eq!(doc_after, doc_before);
+}
+
+#[test]
+fn invalid_right_names_are_rejected() {
+    assert!("".parse::<Right>().is_err());
+    assert!("Sign Invoices".parse::<Right>().is_err());
+    assert_eq!("update".parse::<Right>().unwrap(), Right::Update);
+    assert_eq!("impersonate".parse::<Right>().unwrap(), Right::Impersonation);
+}
+
+#[test]
+fn key_index_finds_all_dids_of_a_key() {
+    let mut state = sample_state();
+    let did1 = did(1);
+    let did3 = did(3);
+
+    state.block_applying(4).unwrap();
+    let add_key = SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None };
+    let asset = signed_asset(3, &did3, None, vec![add_key]);
+    state.apply_transaction("tx4", &asset).unwrap();
+
+    let usages = state.state().unwrap().get_key_usages_at(&auth(2), None).unwrap();
+    let dids: Vec<_> = usages.iter().map(|u| u.did.clone()).collect();
+    assert_eq!(dids, vec![did(2), did1.clone(), did3.clone()]);
+    let in_did1 = &usages[1];
+    assert_eq!(in_did1.key_link, "#1");
+    assert_eq!(in_did1.key.state.valid_from_block, Some(2));
+    assert!(in_did1.rights[&Right::Impersonation].history.iter().any(|item| item.valid));
+    assert!(usages[2].rights[&Right::Impersonation].history.iter().all(|item| !item.valid));
+
+    state.revert_transaction("tx4", &asset).unwrap();
+    let usages = state.state().unwrap().get_key_usages_at(&auth(2), None).unwrap();
+    let dids: Vec<_> = usages.iter().map(|u| u.did.clone()).collect();
+    assert_eq!(dids, vec![did(2), did1]);
+}
+
+/// The blocks `sample_state` applied
+fn sample_blocks() -> Vec<Block> {
+    let state = sample_state();
+    let did = did(1);
+    let inner = state.state().unwrap();
+    let asset_of = |txid: &str, signer_idx, last_tx_id, op| {
+        assert!(inner.is_confirmed(txid).is_some());
+        BlockTransaction {
+            txid: txid.to_owned(),
+            asset: signed_asset(signer_idx, &did, last_tx_id, vec![op]),
+        }
+    };
+    let add_key = SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None };
+    let add_right = SignableOperationDetails::AddRight {
+        auth: auth(2),
+        right: Right::Impersonation.to_string(),
+    };
+    vec![
+        Block { height: 2, timestamp: None, transactions: vec![asset_of("tx1", 1, None, add_key)] },
+        Block {
+            height: 3,
+            timestamp: None,
+            transactions: vec![
+                asset_of("tx2", 1, Some("tx1"), add_right),
+                BlockTransaction { txid: "tx3".to_owned(), asset: before_proof_asset("cjuFoo") },
+            ],
+        },
+    ]
+}
+
+#[test]
+fn revert_to_height_unwinds_multiple_blocks() {
+    let mut state = sample_state();
+    let did = did(1).to_string();
+    let empty_doc = StateHolder::new().state().unwrap().get_doc_at(&did, Some(1)).unwrap();
+
+    state.revert_to_height(1, &sample_blocks()).unwrap();
+
+    let inner = state.state().unwrap();
+    assert_eq!(inner.last_seen_height(), 1);
+    assert_eq!(inner.is_confirmed("tx1"), None);
+    assert_eq!(inner.is_confirmed("tx3"), None);
+    assert!(inner.last_tx_id(&did).is_none());
+    assert!(!inner.before_proof_exists_at("cjuFoo", None).unwrap());
+    assert_eq!(inner.get_doc_at(&did, None).unwrap(), empty_doc);
+}
+
+#[test]
+fn failed_reorg_leaves_state_untouched() {
+    let mut state = sample_state();
+    let state_before = state.state().unwrap().to_owned();
+
+    // Block 3 is missing, so reverting the DID operations of block 2 fails
+    let _err = state.revert_to_height(1, &sample_blocks()[..1]).unwrap_err();
+    // The before proof of block 3 is missing, so it would be left in the state
+    let mut blocks = sample_blocks();
+    blocks[1].transactions.pop();
+    let _err = state.revert_to_height(1, &blocks).unwrap_err();
+    // Block 2 of the new branch is below the fork point
+    let _err = state.reorganize(2, &sample_blocks()[1..], &sample_blocks()[..1]).unwrap_err();
+
+    assert!(!state.is_corrupted());
+    assert_eq!(state.state().unwrap(), &state_before);
+}
+
+#[test]
+fn reorganize_replays_new_branch() {
+    let mut state = sample_state();
+    let did = did(1);
+
+    // On the new branch block 3 revokes key 2 instead of granting a right to it
+    let revoke_key = SignableOperationDetails::RevokeKey { auth: auth(2) };
+    let revoke_asset = signed_asset(1, &did, Some("tx1"), vec![revoke_key]);
+    // Attempts to grant a right to the revoked key are rejected, but still recorded
+    let add_right = SignableOperationDetails::AddRight {
+        auth: auth(2),
+        right: Right::Impersonation.to_string(),
+    };
+    let add_right_asset = signed_asset(1, &did, Some("tx3b"), vec![add_right]);
+    let new_branch = vec![
+        Block {
+            height: 3,
+            timestamp: None,
+            transactions: vec![BlockTransaction { txid: "tx3b".to_owned(), asset: revoke_asset }],
+        },
+        Block {
+            height: 4,
+            timestamp: None,
+            transactions: vec![BlockTransaction {
+                txid: "tx4b".to_owned(),
+                asset: add_right_asset,
+            }],
+        },
+    ];
+    state.reorganize(2, &sample_blocks()[1..], &new_branch).unwrap();
+
+    let inner = state.state().unwrap();
+    assert_eq!(inner.last_seen_height(), 4);
+    assert_eq!(inner.is_confirmed("tx2"), None);
+    assert_eq!(inner.is_confirmed("tx3b"), Some(true));
+    assert_eq!(inner.is_confirmed("tx4b"), Some(false));
+    assert!(!inner.before_proof_exists_at("cjuFoo", None).unwrap());
+    let doc = inner.get_doc_at(&did.to_string(), None).unwrap();
+    assert_eq!(doc.keys[1].state.valid_until_block, Some(3));
+}
+
+#[test]
+fn reorganize_forgets_times_of_unlisted_empty_blocks() {
+    let mut state = sample_state();
+    state.block_applying_at(4, 1000).unwrap();
+
+    // The empty block 4 is not listed among the reverted blocks
+    let new_branch = vec![Block { height: 4, timestamp: Some(900), transactions: vec![] }];
+    state.reorganize(3, &[], &new_branch).unwrap();
+    assert_eq!(state.state().unwrap().block_time(4), Some(900));
+
+    state.revert_to_height(3, &[]).unwrap();
+    let inner = state.state().unwrap();
+    assert_eq!(inner.block_time(4), None);
+    assert_eq!(inner.height_at_time(950), None);
+}
+
+#[test]
+fn recover_restores_latest_checkpoint() {
+    let mut state = StateHolder::with_checkpoints(2, 10);
+    for block in sample_blocks() {
+        state.block_applying(block.height).unwrap();
+        for tx in block.transactions {
+            state.apply_transaction(&tx.txid, &tx.asset).unwrap();
+        }
+    }
+    // Block 2 was not applied yet when the checkpoint was taken
+    assert_eq!(state.checkpoint_heights(), vec![0]);
+    state.block_applying(4).unwrap();
+    assert_eq!(state.checkpoint_heights(), vec![0, 3]);
+    let state_at_3 = sample_state().state().unwrap().to_owned();
+
+    let _err = state.revert_transaction("tx5", &before_proof_asset("cjuBar")).unwrap_err();
+    assert!(state.is_corrupted());
+    let _err = state.block_applying(5).unwrap_err();
+
+    let recovery = state.recover().unwrap();
+    assert_eq!(recovery.restored_height, 3);
+    assert_eq!(recovery.replay_from_height, 4);
+    assert_eq!(recovery.diagnostic.height, 4);
+    assert_eq!(recovery.diagnostic.mutation, "Reverting transaction tx5");
+    assert_eq!(recovery.diagnostic.error, "Transaction tx5 has not been applied, cannot revert.");
+    assert!(!state.is_corrupted());
+    assert_eq!(state.state().unwrap(), &state_at_3);
+
+    state.recover().unwrap_err();
+    state.block_applying(4).unwrap();
+}
+
+#[test]
+fn observers_are_notified_of_recovery() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let mut state = StateHolder::with_checkpoints(2, 10);
+    let changes = Rc::new(RefCell::new(Vec::<StateChange>::new()));
+    let recorded = Rc::clone(&changes);
+    state.subscribe(Box::new(move |change: &StateChange| {
+        recorded.borrow_mut().push(change.to_owned())
+    }));
+    for block in sample_blocks() {
+        state.block_applying(block.height).unwrap();
+        for tx in block.transactions {
+            state.apply_transaction(&tx.txid, &tx.asset).unwrap();
+        }
+    }
+    state.block_applying(4).unwrap();
+    let proof_asset = before_proof_asset("cjuBar");
+    state.apply_transaction("tx4", &proof_asset).unwrap();
+    let changes_at_4 = changes.borrow().len();
+    let _err = state.revert_transaction("tx5", &proof_asset).unwrap_err();
+    assert_eq!(changes.borrow().len(), changes_at_4);
+
+    let recovery = state.recover().unwrap();
+    let recovered = changes.borrow_mut().pop().unwrap();
+    assert_eq!(recovered.event, StateEvent::StateRecovered);
+    assert_eq!(recovered.height, recovery.restored_height);
+    assert_eq!(recovered.height, 3);
+    assert!(recovered.txid.is_empty());
+    assert!(!recovered.reverted);
+    let json = serde_json::to_value(&recovered).unwrap();
+    assert_eq!(json["type"], "stateRecovered");
+
+    // Observers drop what was discarded and get it again as the blocks are replayed
+    changes.borrow_mut().retain(|change| change.height <= recovered.height);
+    assert_eq!(changes.borrow().len(), changes_at_4 - 1);
+    state.block_applying(4).unwrap();
+    state.apply_transaction("tx4", &proof_asset).unwrap();
+    let replayed = changes.borrow().last().cloned().unwrap();
+    assert_eq!((replayed.height, replayed.txid.as_str()), (4, "tx4"));
+    assert_eq!(changes.borrow().len(), changes_at_4);
+}
+
+#[test]
+fn reverted_blocks_invalidate_checkpoints() {
+    let mut state = StateHolder::with_checkpoints(1, 10);
+    state.block_applying(1).unwrap();
+    state.block_applying(2).unwrap();
+    state.block_applying(3).unwrap();
+    assert_eq!(state.checkpoint_heights(), vec![0, 1, 2]);
+
+    state.block_reverting(2).unwrap();
+    assert_eq!(state.checkpoint_heights(), vec![0, 1]);
+    state.revert_to_height(0, &[]).unwrap();
+    assert_eq!(state.checkpoint_heights(), vec![0]);
+}
+
+#[test]
+fn threshold_update_policy_requires_multiple_signatures() {
+    let mut state = StateHolder::new();
+    let did = did(1);
+    let doc_at = |state: &StateHolder, height| {
+        state.state().unwrap().get_doc_at(&did.to_string(), Some(height)).unwrap()
+    };
+
+    state.block_applying(2).unwrap();
+    let add_keys = vec![
+        SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None },
+        SignableOperationDetails::AddKey { auth: auth(3), expires_at_height: None },
+        SignableOperationDetails::AddRight { auth: auth(2), right: Right::Update.to_string() },
+    ];
+    state.apply_transaction("tx1", &signed_asset(1, &did, None, add_keys)).unwrap();
+
+    state.block_applying(3).unwrap();
+    let policy = UpdatePolicy::Threshold { threshold: 2, keys: vec![auth(1), auth(2), auth(3)] };
+    let set_policy = SignableOperationDetails::SetUpdatePolicy { policy: policy.clone() };
+    let policy_asset = signed_asset(1, &did, Some("tx1"), vec![set_policy]);
+    state.apply_transaction("tx2", &policy_asset).unwrap();
+    assert_eq!(doc_at(&state, 2).update_policy, UpdatePolicy::Any);
+    assert_eq!(doc_at(&state, 3).update_policy, policy);
+
+    state.block_applying(4).unwrap();
+    let tombstone = || vec![SignableOperationDetails::TombstoneDid {}];
+    let err = state
+        .apply_transaction("tx3", &signed_asset(1, &did, Some("tx2"), tombstone()))
+        .unwrap_err();
+    assert!(err.to_string().contains("has no right to update"));
+    // Key 3 is listed in the policy, but it has no update right
+    let asset = multi_signed_asset(&[1, 3], &did, Some("tx2"), tombstone());
+    state.apply_transaction("tx4", &asset).unwrap_err();
+    let asset = multi_signed_asset(&[1, 1], &did, Some("tx2"), tombstone());
+    let err = state.apply_transaction("tx5", &asset).unwrap_err();
+    assert!(err.to_string().contains("signed multiple times"));
+    let revoke = vec![SignableOperationDetails::RevokeKey { auth: auth(3) }];
+    let asset = multi_signed_asset(&[1, 2], &did, Some("tx2"), revoke);
+    let err = state.apply_transaction("tx6", &asset).unwrap_err();
+    assert!(err.to_string().contains("is part of the update policy"));
+
+    let tombstone_asset = multi_signed_asset(&[2, 1], &did, Some("tx2"), tombstone());
+    state.apply_transaction("tx7", &tombstone_asset).unwrap();
+    assert!(doc_at(&state, 4).tombstoned);
+
+    state.revert_transaction("tx7", &tombstone_asset).unwrap();
+    assert!(!doc_at(&state, 4).tombstoned);
+    state.block_reverting(3).unwrap();
+    state.revert_transaction("tx2", &policy_asset).unwrap();
+    assert_eq!(doc_at(&state, 3).update_policy, UpdatePolicy::Any);
+}
+
+#[test]
+fn invalid_update_policies_are_rejected() {
+    let policy = |threshold, keys| UpdatePolicy::Threshold { threshold, keys };
+    assert!(policy(0, vec![auth(1)]).validate().is_err());
+    assert!(policy(2, vec![auth(1)]).validate().is_err());
+    assert!(policy(2, vec![auth(1), auth(1)]).validate().is_err());
+    assert!(policy(2, vec![auth(1), auth(2)]).validate().is_ok());
+}
+
+#[test]
+fn controller_did_keys_can_update_controlled_did() {
+    let mut state = StateHolder::new();
+    let (org, controlled) = (did(5), did(1));
+    let controller_auth: Authentication = org.to_string().parse().unwrap();
+    assert_eq!(controller_auth, Authentication::Did(org.clone()));
+
+    state.block_applying(2).unwrap();
+    let add_controller = vec![
+        SignableOperationDetails::AddKey { auth: controller_auth.clone(), expires_at_height: None },
+        SignableOperationDetails::AddRight {
+            auth: controller_auth.clone(),
+            right: Right::Update.to_string(),
+        },
+    ];
+    state.apply_transaction("tx1", &signed_asset(1, &controlled, None, add_controller)).unwrap();
+    // The controller is controlled by the controlled DID as well
+    let add_cycle = vec![
+        SignableOperationDetails::AddKey {
+            auth: Authentication::Did(controlled.clone()),
+            expires_at_height: None,
+        },
+        SignableOperationDetails::AddRight {
+            auth: Authentication::Did(controlled.clone()),
+            right: Right::Update.to_string(),
+        },
+    ];
+    state.apply_transaction("tx2", &signed_asset(5, &org, None, add_cycle)).unwrap();
+
+    state.block_applying(3).unwrap();
+    let inner = state.state().unwrap();
+    let doc = inner.get_doc_at(&controlled.to_string(), None).unwrap();
+    assert!(!doc.has_right_at(&auth(5), Right::Update, 3).unwrap());
+    assert!(doc.has_right_with_resolver_at(&auth(5), Right::Update, 3, inner).unwrap());
+    assert!(!doc.has_right_with_resolver_at(&auth(5), Right::Impersonation, 3, inner).unwrap());
+    assert!(!doc.has_right_with_resolver_at(&auth(6), Right::Update, 3, inner).unwrap());
+    let validation =
+        doc.validate_right_with_resolver(&auth(5), Right::Update, 2, 3, inner).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Valid);
+    let validation =
+        doc.validate_right_with_resolver(&auth(6), Right::Update, 2, 3, inner).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Invalid);
+    let usages = inner.get_key_usages_at(&controller_auth, None).unwrap();
+    assert_eq!(usages.len(), 1);
+    assert_eq!(usages[0].did, controlled);
+
+    let add_service = || {
+        vec![SignableOperationDetails::AddService {
+            service: Service {
+                type_: ServiceType::Hub,
+                name: "hub".to_owned(),
+                service_endpoint: "https://hub.example.com".to_owned(),
+            },
+        }]
+    };
+    let asset = signed_asset(6, &controlled, Some("tx1"), add_service());
+    let err = state.apply_transaction("tx3", &asset).unwrap_err();
+    assert!(err.to_string().contains("has no right to update"));
+    let asset = signed_asset(5, &controlled, Some("tx1"), add_service());
+    state.apply_transaction("tx4", &asset).unwrap();
+
+    state.revert_transaction("tx4", &asset).unwrap();
+    let doc = state.state().unwrap().get_doc_at(&controlled.to_string(), None).unwrap();
+    assert!(doc.services.is_empty());
+}
+
+#[test]
+fn threshold_counts_each_policy_key_once() {
+    let mut state = StateHolder::new();
+    let (controlled, org) = (did(1), did(5));
+    let org_auth = Authentication::Did(org.clone());
+
+    state.block_applying(2).unwrap();
+    let add_keys = [org_auth.clone(), auth(3), auth(5)]
+        .iter()
+        .flat_map(|auth| {
+            vec![
+                SignableOperationDetails::AddKey { auth: auth.clone(), expires_at_height: None },
+                SignableOperationDetails::AddRight {
+                    auth: auth.clone(),
+                    right: Right::Update.to_string(),
+                },
+            ]
+        })
+        .collect();
+    state.apply_transaction("tx1", &signed_asset(1, &controlled, None, add_keys)).unwrap();
+    let add_org_key = vec![
+        SignableOperationDetails::AddKey { auth: auth(6), expires_at_height: None },
+        SignableOperationDetails::AddRight { auth: auth(6), right: Right::Update.to_string() },
+    ];
+    state.apply_transaction("tx2", &signed_asset(5, &org, None, add_org_key)).unwrap();
+
+    state.block_applying(3).unwrap();
+    let inner = state.state().unwrap();
+    let mut doc = inner.get_doc_at(&controlled.to_string(), None).unwrap();
+    let authorized = |doc: &DidDocument, signer_idxs: &[u8]| {
+        let signers: Vec<_> = signer_idxs.iter().map(|idx| auth(*idx)).collect();
+        doc.is_update_authorized_at(&signers, 3, inner).unwrap()
+    };
+
+    // Keys 5 and 6 can only act as the organization
+    let keys = vec![org_auth.clone(), auth(1), auth(3)];
+    doc.update_policy = UpdatePolicy::Threshold { threshold: 2, keys };
+    assert!(!authorized(&doc, &[5, 6]));
+    assert!(authorized(&doc, &[5, 3]));
+    assert!(authorized(&doc, &[6, 1]));
+
+    // Key 5 can act as itself, so key 6 is left to act as the organization
+    let keys = vec![org_auth, auth(5), auth(3)];
+    doc.update_policy = UpdatePolicy::Threshold { threshold: 2, keys };
+    assert!(!authorized(&doc, &[5]));
+    assert!(authorized(&doc, &[5, 6]));
+    assert!(authorized(&doc, &[6, 5]));
+}
+
+#[test]
+fn guardians_can_reset_keys_after_delay() {
+    let mut state = StateHolder::new();
+    let (did, guardian_did, recovered_did) = (did(1), did(3), did(4));
+    let doc_at = |state: &StateHolder, height| {
+        state.state().unwrap().get_doc_at(&did.to_string(), Some(height)).unwrap()
+    };
+
+    state.block_applying(2).unwrap();
+    let policy = RecoveryPolicy::Guardians {
+        threshold: 2,
+        guardians: vec![auth(2), Authentication::Did(guardian_did)],
+        delay: 5,
+    };
+    let set_policy = vec![SignableOperationDetails::SetRecoveryPolicy { policy: policy.clone() }];
+    state.apply_transaction("tx1", &signed_asset(1, &did, None, set_policy)).unwrap();
+    assert_eq!(doc_at(&state, 2).recovery_policy, policy);
+
+    state.block_applying(3).unwrap();
+    let start = || vec![SignableOperationDetails::StartRecovery { auth: auth(4) }];
+    let asset = signed_asset(2, &did, Some("tx1"), start());
+    let err = state.apply_transaction("tx2", &asset).unwrap_err();
+    assert!(err.to_string().contains("has no right to update"));
+    let asset = multi_signed_asset(&[2, 3], &did, Some("tx1"), start());
+    state.apply_transaction("tx3", &asset).unwrap();
+    let pending = doc_at(&state, 3).pending_recovery.unwrap();
+    assert_eq!(pending.auth, auth(4));
+    assert_eq!(pending.completable_at_height, 8);
+    let asset = multi_signed_asset(&[2, 3], &did, Some("tx3"), start());
+    let err = state.apply_transaction("tx4", &asset).unwrap_err();
+    assert!(err.to_string().contains("already has a pending recovery"));
+
+    state.block_applying(4).unwrap();
+    let complete = || vec![SignableOperationDetails::CompleteRecovery { auth: auth(4) }];
+    let asset = signed_asset(4, &did, Some("tx3"), complete());
+    let err = state.apply_transaction("tx5", &asset).unwrap_err();
+    assert!(err.to_string().contains("cannot be completed before height 8"));
+    let cancel = vec![SignableOperationDetails::CancelRecovery {}];
+    let cancel_asset = signed_asset(1, &did, Some("tx3"), cancel);
+    state.apply_transaction("tx6", &cancel_asset).unwrap();
+    assert!(doc_at(&state, 4).pending_recovery.is_none());
+    assert!(doc_at(&state, 3).pending_recovery.is_some());
+    state.revert_transaction("tx6", &cancel_asset).unwrap();
+    assert!(doc_at(&state, 4).pending_recovery.is_some());
+
+    state.block_applying(8).unwrap();
+    let asset = signed_asset(5, &did, Some("tx3"), complete());
+    let err = state.apply_transaction("tx7", &asset).unwrap_err();
+    assert!(err.to_string().contains("has no right to update"));
+    let complete_asset = signed_asset(4, &did, Some("tx3"), complete());
+    state.apply_transaction("tx8", &complete_asset).unwrap();
+    let doc = doc_at(&state, 8);
+    assert!(doc.pending_recovery.is_none());
+    assert!(!doc.has_right_at(&auth(1), Right::Update, 8).unwrap());
+    assert!(doc.has_right_at(&auth(4), Right::Update, 8).unwrap());
+    assert!(doc.has_right_at(&auth(4), Right::Impersonation, 8).unwrap());
+    assert!(doc.has_right_at(&auth(1), Right::Update, 7).unwrap());
+    let usage_dids = |state: &StateHolder| -> Vec<Did> {
+        let usages = state.state().unwrap().get_key_usages_at(&auth(4), None).unwrap();
+        usages.into_iter().map(|u| u.did).collect()
+    };
+    assert_eq!(usage_dids(&state), vec![recovered_did.clone(), did.clone()]);
+
+    state.revert_transaction("tx8", &complete_asset).unwrap();
+    let doc = doc_at(&state, 8);
+    assert!(doc.pending_recovery.is_some());
+    assert!(doc.has_right_at(&auth(1), Right::Update, 8).unwrap());
+    assert!(!doc.has_right_at(&auth(4), Right::Update, 8).unwrap());
+    assert_eq!(usage_dids(&state), vec![recovered_did]);
+}
+
+#[test]
+fn invalid_recovery_policies_are_rejected() {
+    let policy =
+        |threshold, guardians, delay| RecoveryPolicy::Guardians { threshold, guardians, delay };
+    assert!(policy(0, vec![auth(1)], 10).validate().is_err());
+    assert!(policy(2, vec![auth(1), auth(1)], 10).validate().is_err());
+    assert!(policy(1, vec![auth(1)], 0).validate().is_err());
+    assert!(policy(1, vec![auth(1), Authentication::Did(did(2))], 10).validate().is_ok());
+}
+
+#[test]
+fn guardian_keys_sign_for_one_guardian_only() {
+    let mut state = StateHolder::new();
+    let (did, guardian_1, guardian_2) = (did(1), did(5), did(6));
+
+    state.block_applying(2).unwrap();
+    // Key 5 holds the update right of both guardian DIDs
+    let add_key = vec![
+        SignableOperationDetails::AddKey { auth: auth(5), expires_at_height: None },
+        SignableOperationDetails::AddRight { auth: auth(5), right: Right::Update.to_string() },
+    ];
+    state.apply_transaction("tx1", &signed_asset(6, &guardian_2, None, add_key)).unwrap();
+
+    state.block_applying(3).unwrap();
+    let inner = state.state().unwrap();
+    let mut doc = inner.get_doc_at(&did.to_string(), None).unwrap();
+    doc.recovery_policy = RecoveryPolicy::Guardians {
+        threshold: 2,
+        guardians: vec![Authentication::Did(guardian_1), Authentication::Did(guardian_2)],
+        delay: 5,
+    };
+    let authorized = |signer_idxs: &[u8]| {
+        let signers: Vec<_> = signer_idxs.iter().map(|idx| auth(*idx)).collect();
+        doc.is_recovery_authorized_at(&signers, 3, inner).unwrap()
+    };
+    assert!(!authorized(&[5]));
+    assert!(!authorized(&[5, 5]));
+    assert!(authorized(&[5, 6]));
+    assert!(authorized(&[6, 5]));
+}
+
+#[test]
+fn pending_transactions_are_projected_until_confirmed() {
+    let mut state = StateHolder::new();
+    let did = did(1);
+    let did_str = did.to_string();
+    let key_count = |doc: DidDocument| doc.keys.len();
+
+    state.block_applying(2).unwrap();
+    let add_key =
+        |idx| vec![SignableOperationDetails::AddKey { auth: auth(idx), expires_at_height: None }];
+    state.apply_transaction("tx1", &signed_asset(1, &did, None, add_key(2))).unwrap();
+
+    let pending_asset = signed_asset(1, &did, Some("tx1"), add_key(3));
+    state.add_pending_transaction("ptx1", &pending_asset).unwrap();
+    // The projected last transaction of the DID is already ptx1
+    let conflicting_asset = signed_asset(1, &did, Some("tx1"), add_key(4));
+    let reports = state.add_pending_transaction("ptx2", &conflicting_asset).unwrap();
+    assert_eq!(reports.len(), 2);
+    assert!(reports[0].accepted);
+    assert!(!reports[1].accepted);
+    assert!(reports[1].rejection.as_ref().unwrap().contains("ptx1"));
+    assert!(state.add_pending_transaction("ptx1", &pending_asset).is_err());
+
+    assert_eq!(key_count(state.get_projected_doc(&did_str).unwrap()), 3);
+    assert_eq!(state.get_projected_doc(&did_str).unwrap().queried_at_height, 3);
+    let projected_last = state.projected_last_tx_id(&did_str).unwrap().unwrap();
+    assert_eq!(projected_last.transaction_id, "ptx1");
+    let confirmed = state.state().unwrap();
+    assert_eq!(key_count(confirmed.get_doc_at(&did_str, None).unwrap()), 2);
+    assert_eq!(confirmed.last_tx_id(&did_str).unwrap().transaction_id, "tx1");
+
+    state.block_applying(3).unwrap();
+    state.apply_transaction("ptx1", &pending_asset).unwrap();
+    let reports = state.pending_transactions().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].txid, "ptx2");
+    assert_eq!(key_count(state.get_projected_doc(&did_str).unwrap()), 3);
+    assert!(state.remove_pending_transaction("ptx2").unwrap().is_empty());
+    assert!(state.remove_pending_transaction("ptx2").is_err());
+
+    state.block_reverting(3).unwrap();
+    state.revert_transaction("ptx1", &pending_asset).unwrap();
+    assert_eq!(key_count(state.get_projected_doc(&did_str).unwrap()), 2);
+}
+
+#[test]
+fn confirmed_transactions_invalidate_conflicting_pending_ones() {
+    let mut state = sample_state();
+    let did = did(1);
+    let did_str = did.to_string();
+    let add_key =
+        |idx| vec![SignableOperationDetails::AddKey { auth: auth(idx), expires_at_height: None }];
+
+    let pending_asset = signed_asset(1, &did, Some("tx2"), add_key(3));
+    state.add_pending_transaction("ptx1", &pending_asset).unwrap();
+    state.add_pending_transaction("ptx2", &before_proof_asset("cjuBar")).unwrap();
+
+    state.block_applying(4).unwrap();
+    let asset = signed_asset(1, &did, Some("tx2"), add_key(4));
+    state.apply_transaction("tx4", &asset).unwrap();
+    state.apply_transaction("tx5", &before_proof_asset("cjuBaz")).unwrap();
+
+    let reports = state.pending_transactions().unwrap();
+    assert_eq!(reports.len(), 2);
+    assert!(!reports[0].accepted);
+    assert!(reports[0].rejection.as_ref().unwrap().contains("tx4"));
+    assert!(reports[1].accepted);
+    let doc = state.get_projected_doc(&did_str).unwrap();
+    assert!(doc.keys.iter().any(|key| key.state.authentication == auth(4)));
+    assert!(!doc.keys.iter().any(|key| key.state.authentication == auth(3)));
+    assert!(state.projected_state().unwrap().before_proof_exists_at("cjuBar", None).unwrap());
+}
+
+#[test]
+fn observers_are_notified_of_changes_and_reversals() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let mut state = StateHolder::new();
+    let did = did(1);
+    let changes = Rc::new(RefCell::new(Vec::<StateChange>::new()));
+    let recorded = Rc::clone(&changes);
+    state.subscribe(Box::new(move |change: &StateChange| {
+        recorded.borrow_mut().push(change.to_owned())
+    }));
+    let take_events = || -> Vec<(bool, StateEvent)> {
+        changes.borrow_mut().drain(..).map(|c| (c.reverted, c.event)).collect()
+    };
+
+    state.block_applying(2).unwrap();
+    let ops = vec![
+        SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None },
+        SignableOperationDetails::AddRight { auth: auth(2), right: Right::Update.to_string() },
+    ];
+    let asset = signed_asset(1, &did, None, ops);
+    state.apply_transaction("tx1", &asset).unwrap();
+    let key_added = StateEvent::KeyAdded { did: did.clone(), auth: auth(2) };
+    let right_granted = StateEvent::RightGranted {
+        did: did.clone(),
+        auth: auth(2),
+        right: Right::Update.to_string(),
+    };
+    assert_eq!(take_events(), vec![(false, key_added.clone()), (false, right_granted.clone())]);
+    assert_eq!(changes.borrow().len(), 0);
+
+    let proof_asset = before_proof_asset("content");
+    state.apply_transaction("tx2", &proof_asset).unwrap();
+    let proof_registered = StateEvent::BeforeProofRegistered { content_id: "content".to_owned() };
+    let json = serde_json::to_value(&proof_registered).unwrap();
+    assert_eq!(json["contentId"], "content");
+    assert_eq!(take_events(), vec![(false, proof_registered)]);
+
+    let tombstone = vec![SignableOperationDetails::TombstoneDid {}];
+    let rejected_asset = signed_asset(3, &did, Some("tx1"), tombstone);
+    state.apply_transaction("tx3", &rejected_asset).unwrap_err();
+    match take_events().as_slice() {
+        [(false, StateEvent::TransactionRejected { error: Some(error) })] => {
+            assert!(error.contains("has no right to update"))
+        }
+        events => panic!("Unexpected events {:?}", events),
+    }
+
+    state.revert_transaction("tx3", &rejected_asset).unwrap();
+    assert_eq!(take_events(), vec![(true, StateEvent::TransactionRejected { error: None })]);
+    state.revert_transaction("tx2", &proof_asset).unwrap();
+    take_events();
+    state.revert_transaction("tx1", &asset).unwrap();
+    assert_eq!(take_events(), vec![(true, right_granted), (true, key_added)]);
+
+    // Failed changes are not reported
+    state.revert_transaction("tx1", &asset).unwrap_err();
+    assert!(take_events().is_empty());
+}
+
+#[test]
+fn doc_diff_lists_changes_with_their_transactions() {
+    let mut state = sample_state();
+    let did = did(1);
+    let did_str = did.to_string();
+
+    state.block_applying(5).unwrap();
+    let add_key =
+        vec![SignableOperationDetails::AddKey { auth: auth(3), expires_at_height: Some(7) }];
+    state.apply_transaction("tx4", &signed_asset(1, &did, Some("tx2"), add_key)).unwrap();
+    state.block_applying(6).unwrap();
+    let revoke_key = vec![SignableOperationDetails::RevokeKey { auth: auth(2) }];
+    state.apply_transaction("tx5", &signed_asset(1, &did, Some("tx4"), revoke_key)).unwrap();
+    state.block_applying(8).unwrap();
+    let tombstone = vec![SignableOperationDetails::TombstoneDid {}];
+    state.apply_transaction("tx6", &signed_asset(1, &did, Some("tx5"), tombstone)).unwrap();
+
+    let inner = state.state().unwrap();
+    let diff = inner.get_doc_diff(&did_str, 1, 8).unwrap();
+    let changes: Vec<_> = diff
+        .changes
+        .iter()
+        .map(|c| (c.height, c.kind.clone(), c.transaction_ids.clone()))
+        .collect();
+    let txns = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+    let link = |idx: u8| format!("#{}", idx);
+    assert_eq!(
+        changes,
+        vec![
+            (2, DidChangeKind::KeyAdded { key_link: link(1), auth: auth(2) }, txns(&["tx1"])),
+            (
+                3,
+                DidChangeKind::RightChanged {
+                    key_link: link(1),
+                    auth: auth(2),
+                    right: Right::Impersonation,
+                    valid: true
+                },
+                txns(&["tx2"])
+            ),
+            (5, DidChangeKind::KeyAdded { key_link: link(2), auth: auth(3) }, txns(&["tx4"])),
+            (6, DidChangeKind::KeyRevoked { key_link: link(1), auth: auth(2) }, txns(&["tx5"])),
+            (7, DidChangeKind::KeyExpired { key_link: link(2), auth: auth(3) }, txns(&[])),
+            (8, DidChangeKind::Tombstoned, txns(&["tx6"])),
+        ]
+    );
+
+    let diff = inner.get_doc_diff(&did_str, 5, 7).unwrap();
+    assert_eq!(diff.changes.len(), 2);
+    let json = serde_json::to_value(&diff.changes[0]).unwrap();
+    assert_eq!(json["type"], "keyRevoked");
+    assert_eq!(json["keyLink"], "#1");
+    assert!(inner.get_doc_diff(&did_str, 5, 5).is_err());
+    assert!(inner.get_doc_diff(&did_str, 5, 9).is_err());
+}
+
+#[test]
+fn documents_can_be_queried_by_block_time() {
+    let mut state = StateHolder::new();
+    let did = did(1);
+    let did_str = did.to_string();
+
+    state.block_applying_at(2, 1000).unwrap();
+    let add_key = vec![SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None }];
+    state.apply_transaction("tx1", &signed_asset(1, &did, None, add_key)).unwrap();
+    state.block_applying_at(3, 1060).unwrap();
+    state.block_applying_at(4, 1120).unwrap();
+    let revoke_key = vec![SignableOperationDetails::RevokeKey { auth: auth(2) }];
+    state.apply_transaction("tx2", &signed_asset(1, &did, Some("tx1"), revoke_key)).unwrap();
+
+    let err = state.block_applying_at(5, 1120).unwrap_err();
+    assert!(err.to_string().contains("must follow block 4"));
+    assert!(!state.is_corrupted());
+
+    let inner = state.state().unwrap();
+    assert_eq!(inner.height_at_time(999), None);
+    assert_eq!(inner.height_at_time(1000), Some(2));
+    assert_eq!(inner.height_at_time(1119), Some(3));
+    assert_eq!(inner.height_at_time(5000), Some(4));
+    assert_eq!(inner.block_time(3), Some(1060));
+
+    let doc = inner.get_doc_at_time(&did_str, 1070).unwrap();
+    assert_eq!(doc, inner.get_doc_at(&did_str, Some(3)).unwrap());
+    assert_eq!(doc.keys[1].state.valid_from_time, Some(1000));
+    assert_eq!(doc.keys[1].state.valid_until_time, Some(1120));
+    assert!(inner.get_doc_at_time(&did_str, 999).is_err());
+
+    let validation =
+        inner.validate_right_between_times(&did_str, &auth(1), Right::Update, 1000, 1130).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Valid);
+    let validation =
+        inner.validate_right_between_times(&did_str, &auth(2), Right::Update, 1000, 1130).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Invalid);
+
+    state.block_reverting(4).unwrap();
+    state
+        .revert_transaction(
+            "tx2",
+            &signed_asset(
+                1,
+                &did,
+                Some("tx1"),
+                vec![SignableOperationDetails::RevokeKey { auth: auth(2) }],
+            ),
+        )
+        .unwrap();
+    let inner = state.state().unwrap();
+    assert_eq!(inner.block_time(4), None);
+    assert_eq!(inner.height_at_time(5000), Some(3));
+    state.block_applying_at(4, 1130).unwrap();
+}
+
+#[test]
+fn state_root_commits_to_transactions_and_before_proofs() {
+    let mut state = sample_state();
+    let (did, other_did) = (did(1), did(2));
+    let did_str = did.to_string();
+    let inner = state.state().unwrap();
+
+    assert!(inner.state_leaves_at(1).unwrap().is_empty());
+    assert_eq!(inner.state_leaves_at(3).unwrap().len(), 2);
+    assert!(inner.state_root_at(Some(4)).is_err());
+    let root_2 = inner.state_root_at(Some(2)).unwrap();
+    let root_3 = inner.state_root_at(None).unwrap();
+    assert_ne!(root_2, root_3);
+    let hashes = inner.state_leaves_at(3).unwrap().iter().map(|l| l.hash().unwrap()).collect();
+    assert_eq!(MerkleTree::new(hashes).root(), root_3);
+
+    // The document at height 2 already lists the right granted at height 3
+    let doc_2 = inner.get_doc_at(&did_str, Some(2)).unwrap();
+    let doc_3 = inner.get_doc_at(&did_str, None).unwrap();
+    let proof = inner.did_state_proof(&did_str, Some(2)).unwrap();
+    proof.verify_did(&doc_2, "tx1", &root_2).unwrap();
+    assert!(proof.verify_did(&doc_2, "tx1", &root_3).is_err());
+    assert!(proof.verify_did(&doc_2, "tx2", &root_2).is_err());
+    assert!(proof.verify_did(&doc_3, "tx1", &root_2).is_err());
+    let mut tampered = doc_2.clone();
+    tampered.keys.pop();
+    assert!(proof.verify_did(&tampered, "tx1", &root_2).is_err());
+
+    let proof = inner.did_state_proof(&did_str, None).unwrap();
+    proof.verify_did(&doc_3, "tx2", &root_3).unwrap();
+    assert!(inner.did_state_proof(&other_did.to_string(), None).is_err());
+
+    let proof = inner.before_proof_state_proof("cjuFoo", None).unwrap();
+    proof.verify_before_proof("cjuFoo", &root_3).unwrap();
+    assert!(proof.verify_before_proof("cjuBar", &root_3).is_err());
+    assert!(inner.before_proof_state_proof("cjuFoo", Some(2)).is_err());
+
+    // Roots of earlier blocks are kept as later blocks are applied
+    state.block_applying(4).unwrap();
+    let add_key = SignableOperationDetails::AddKey { auth: auth(3), expires_at_height: None };
+    state.apply_transaction("tx4", &signed_asset(2, &other_did, None, vec![add_key])).unwrap();
+    let inner = state.state().unwrap();
+    assert_eq!(inner.state_root_at(Some(3)).unwrap(), root_3);
+    let root_4 = inner.state_root_at(None).unwrap();
+    assert_ne!(root_4, root_3);
+    let proof = inner.did_state_proof(&did_str, Some(2)).unwrap();
+    proof.verify_did(&doc_2, "tx1", &root_2).unwrap();
+    let other_doc = inner.get_doc_at(&other_did.to_string(), None).unwrap();
+    let proof = inner.did_state_proof(&other_did.to_string(), None).unwrap();
+    proof.verify_did(&other_doc, "tx4", &root_4).unwrap();
+
+    let revoke_key = vec![SignableOperationDetails::RevokeKey { auth: auth(2) }];
+    let revoke_asset = signed_asset(1, &did, Some("tx2"), revoke_key);
+    state.apply_transaction("tx5", &revoke_asset).unwrap();
+    let inner = state.state().unwrap();
+    let doc_3_revoked = inner.get_doc_at(&did_str, Some(3)).unwrap();
+    assert_ne!(doc_3_revoked, doc_3);
+    let proof = inner.did_state_proof(&did_str, Some(3)).unwrap();
+    proof.verify_did(&doc_3_revoked, "tx2", &root_3).unwrap();
+    let doc_4 = inner.get_doc_at(&did_str, None).unwrap();
+    assert!(proof.verify_did(&doc_4, "tx2", &root_3).is_err());
+    let proof = inner.did_state_proof(&did_str, None).unwrap();
+    proof.verify_did(&doc_4, "tx5", &inner.state_root_at(None).unwrap()).unwrap();
+    assert!(proof.verify_did(&doc_4, "tx5", &root_4).is_err());
+    state.revert_transaction("tx5", &revoke_asset).unwrap();
+
+    let add_key = SignableOperationDetails::AddKey { auth: auth(3), expires_at_height: None };
+    state.revert_transaction("tx4", &signed_asset(2, &other_did, None, vec![add_key])).unwrap();
+    state.block_reverting(4).unwrap();
+    assert_eq!(state.state().unwrap().state_root_at(None).unwrap(), root_3);
+    state.revert_to_height(2, &sample_blocks()[1..]).unwrap();
+    let inner = state.state().unwrap();
+    assert_eq!(inner.state_root_at(None).unwrap(), root_2);
+    assert!(inner.before_proof_state_proof("cjuFoo", None).is_err());
+}
+
+#[test]
+fn state_root_is_updated_leaf_by_leaf() {
+    let mut state = StateHolder::new();
+    state.block_applying(2).unwrap();
+    let mut roots = vec![state.state().unwrap().state_root_at(None).unwrap()];
+    let content_ids: Vec<_> = (0..9).map(|i| format!("cjuContent{}", i)).collect();
+    for content_id in &content_ids {
+        state.apply_transaction(content_id, &before_proof_asset(content_id)).unwrap();
+        let inner = state.state().unwrap();
+        let leaves = inner.state_leaves_at(2).unwrap();
+        let hashes = leaves.iter().map(|l| l.hash().unwrap()).collect();
+        let root = inner.state_root_at(None).unwrap();
+        assert_eq!(MerkleTree::new(hashes).root(), root);
+        for (content_id, _) in content_ids.iter().zip(&leaves) {
+            let proof = inner.before_proof_state_proof(content_id, None).unwrap();
+            proof.verify_before_proof(content_id, &root).unwrap();
+        }
+        roots.push(root);
+    }
+    for content_id in content_ids.iter().rev() {
+        roots.pop();
+        state.revert_transaction(content_id, &before_proof_asset(content_id)).unwrap();
+        assert_eq!(&state.state().unwrap().state_root_at(None).unwrap(), roots.last().unwrap());
+    }
+}
+
+#[test]
+fn pruning_keeps_answers_at_and_above_the_pruning_point() {
+    let mut state = sample_state();
+    let (did, did_str) = (did(1), did(1).to_string());
+    state.block_applying(4).unwrap();
+    let add_key = vec![SignableOperationDetails::AddKey { auth: auth(3), expires_at_height: None }];
+    state.apply_transaction("tx4", &signed_asset(1, &did, Some("tx2"), add_key)).unwrap();
+    state.block_applying(5).unwrap();
+    let revoke_key = vec![SignableOperationDetails::RevokeKey { auth: auth(3) }];
+    state.apply_transaction("tx5", &signed_asset(1, &did, Some("tx4"), revoke_key)).unwrap();
+    state.block_applying(6).unwrap();
+
+    let inner = state.state().unwrap();
+    let answers = |inner: &State| {
+        (5..=6)
+            .map(|h| {
+                let doc = inner.get_doc_at(&did_str, Some(h)).unwrap();
+                let impersonation = doc.has_right_at(&auth(2), Right::Impersonation, h).unwrap();
+                let root = inner.state_root_at(Some(h)).unwrap();
+                let proof = inner.did_state_proof(&did_str, Some(h)).unwrap();
+                (doc, impersonation, root, proof)
+            })
+            .collect::<Vec<_>>()
+    };
+    let validate = |inner: &State| {
+        let doc = inner.get_doc_at(&did_str, Some(6)).unwrap();
+        doc.validate_right(&auth(3), Right::Update, 5, 6).unwrap().status()
+    };
+    let validated_before = validate(inner);
+    let before = answers(inner);
+    let doc_before = inner.get_doc_at(&did_str, None).unwrap();
+
+    state.prune_below(5).unwrap();
+    let inner = state.state().unwrap();
+    assert_eq!(inner.pruned_below_height(), 5);
+    assert_eq!(answers(inner), before);
+    assert_eq!(validate(inner), validated_before);
+    let doc = inner.get_doc_at(&did_str, None).unwrap();
+    assert_eq!(doc, doc_before);
+
+    let err = inner.get_doc_at(&did_str, Some(4)).unwrap_err();
+    assert!(err.to_string().contains("pruned"));
+    assert!(inner.state_root_at(Some(4)).is_err());
+    assert!(inner.get_doc_diff(&did_str, 4, 6).is_err());
+    assert!(inner.before_proof_exists_at("cjuFoo", None).unwrap());
+
+    // The last confirmed transaction of a DID below the pruning point is kept
+    assert_eq!(inner.is_confirmed("tx1"), None);
+    assert_eq!(inner.is_confirmed("tx3"), None);
+    assert_eq!(inner.is_confirmed("tx4"), Some(true));
+    assert!(inner.get_tx_ids(&did_str, true, 0, None).is_err());
+    let txids: Vec<_> =
+        inner.get_tx_ids(&did_str, true, 5, None).unwrap().unwrap().map(|t| t.height).collect();
+    assert_eq!(txids, vec![5]);
+    assert_eq!(inner.last_tx_id(&did_str).unwrap().transaction_id, "tx5");
+
+    let err = state.block_reverting(5).unwrap_err();
+    assert!(err.to_string().contains("pruned below height 5"));
+    assert!(!state.is_corrupted());
+    assert!(state.revert_to_height(4, &[]).is_err());
+    state.block_reverting(6).unwrap();
+
+    let restored = StateHolder::load_snapshot(&state.save_snapshot().unwrap()).unwrap();
+    assert_eq!(restored.state().unwrap(), state.state().unwrap());
+
+    let remove_key = vec![SignableOperationDetails::RevokeKey { auth: auth(2) }];
+    state.block_applying(7).unwrap();
+    state.apply_transaction("tx7", &signed_asset(1, &did, Some("tx5"), remove_key)).unwrap();
+}
+
+#[test]
+fn pruning_keeps_rights_of_keys_revoked_below_the_pruning_point() {
+    let mut state = sample_state();
+    let (did, did_str) = (did(1), did(1).to_string());
+    state.block_applying(4).unwrap();
+    let revoke_key = vec![SignableOperationDetails::RevokeKey { auth: auth(2) }];
+    state.apply_transaction("tx4", &signed_asset(1, &did, Some("tx2"), revoke_key)).unwrap();
+    state.block_applying(5).unwrap();
+    state.block_applying(6).unwrap();
+
+    let validate = |inner: &State| {
+        let doc = inner.get_doc_at(&did_str, Some(6)).unwrap();
+        let impersonation = doc.has_right_at(&auth(2), Right::Impersonation, 5).unwrap();
+        (doc.validate_right(&auth(2), Right::Impersonation, 5, 6).unwrap(), impersonation)
+    };
+    let validated_before = validate(state.state().unwrap());
+
+    state.prune_below(5).unwrap();
+    let inner = state.state().unwrap();
+    assert_eq!(validate(inner), validated_before);
+    let doc = inner.get_doc_at(&did_str, Some(5)).unwrap();
+    assert_eq!(doc.rights[&Right::Impersonation][1].derived.key_link, "#1");
+}
+
+#[test]
+fn pruning_keeps_suspensions_started_below_the_pruning_point() {
+    let mut state = sample_state();
+    let (did, did_str) = (did(1), did(1).to_string());
+    state.block_applying(4).unwrap();
+    let add_right = vec![SignableOperationDetails::AddRight {
+        auth: auth(2),
+        right: Right::Update.to_string(),
+    }];
+    state.apply_transaction("tx4", &signed_asset(1, &did, Some("tx2"), add_right)).unwrap();
+    state.block_applying(5).unwrap();
+    let suspend = vec![SignableOperationDetails::SuspendKey { auth: auth(2) }];
+    state.apply_transaction("tx5", &signed_asset(1, &did, Some("tx4"), suspend)).unwrap();
+    state.block_applying(6).unwrap();
+    state.block_applying(7).unwrap();
+
+    state.prune_below(6).unwrap();
+    let inner = state.state().unwrap();
+    let doc = inner.get_doc_at(&did_str, Some(7)).unwrap();
+    assert_eq!(
+        doc.keys[1].state.suspensions,
+        vec![KeySuspension { from_height: 5, until_height: None }]
+    );
+    assert!(!doc.keys[1].derived.valid);
+    assert!(!doc.has_right_at(&auth(2), Right::Update, 7).unwrap());
+    let validation = doc.validate_right(&auth(2), Right::Update, 6, 7).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Invalid);
+    assert!(!doc.is_update_authorized_at(&[auth(2)], 7, inner).unwrap());
+    assert!(doc.is_update_authorized_at(&[auth(1)], 7, inner).unwrap());
+}
+
+#[test]
+fn queries_below_the_pruning_point_fail() {
+    let mut state = sample_state();
+    let did_str = did(1).to_string();
+    state.block_applying(4).unwrap();
+    state.prune_below(4).unwrap();
+    let inner = state.state().unwrap();
+
+    let err = inner.get_tx_ids(&did_str, false, 3, None).err().unwrap();
+    assert!(err.to_string().contains("pruned"));
+    assert!(inner.get_tx_ids_page(&did_str, false, 0, None, None, 10).is_err());
+    let page = inner.get_tx_ids_page(&did_str, false, 4, None, None, 10).unwrap();
+    assert!(page.items.is_empty());
+
+    assert_eq!(inner.is_confirmed("tx1"), None);
+    assert_eq!(inner.is_confirmed("txUnknown"), None);
+    assert_eq!(inner.is_confirmed("tx2"), Some(true));
+    assert!(!inner.before_proof_exists_at("cjuUnknown", None).unwrap());
+
+    assert!(inner.before_proof_history("cjuFoo", Some(3)).is_err());
+    assert!(inner.before_proof_exists_at("cjuFoo", Some(3)).is_err());
+    let history = inner.before_proof_history("cjuFoo", Some(4)).unwrap();
+    assert_eq!(history.exists_from_height, Some(3));
+    assert_eq!(history.queried_at_height, 4);
+}
+
+#[test]
+fn final_blocks_are_pruned_automatically() {
+    let mut state = StateHolder::new();
+    state.set_finality_depth(2);
+    let mut pruned_heights = vec![];
+    for height in 1..=7 {
+        state.block_applying(height).unwrap();
+        pruned_heights.push(state.state().unwrap().pruned_below_height());
+    }
+    assert_eq!(pruned_heights, vec![0, 0, 0, 2, 2, 4, 4]);
+    assert!(state.block_reverting(4).is_err());
+    state.block_reverting(7).unwrap();
+}
+
+#[test]
+fn batched_before_proofs_exist_with_inclusion_proofs() {
+    let mut state = StateHolder::new();
+    let content_ids: Vec<String> = (0..3).map(|i| format!("cjuDocument{}", i)).collect();
+    let batch = BeforeProofBatch::new(content_ids.clone()).unwrap();
+    let register_batch = |root: &str| {
+        MorpheusAsset::new(vec![OperationAttempt::RegisterBeforeProofBatch {
+            root: root.to_owned(),
+        }])
+    };
+
+    state.block_applying(2).unwrap();
+    state.apply_transaction("tx1", &register_batch(&batch.root())).unwrap();
+    assert!(state.apply_transaction("tx2", &register_batch(&batch.root())).is_err());
+    state.block_applying(3).unwrap();
+    state.apply_transaction("tx3", &before_proof_asset(&content_ids[1])).unwrap();
+
+    let inner = state.state().unwrap();
+    let proof = batch.proof(&content_ids[0]).unwrap();
+    assert!(!inner.before_proof_exists_at(&content_ids[0], None).unwrap());
+    assert!(!inner.before_proof_exists_in_batch_at(&proof, Some(1)).unwrap());
+    assert!(inner.before_proof_exists_in_batch_at(&proof, Some(2)).unwrap());
+    let history = inner.before_proof_history_in_batch(&proof).unwrap();
+    assert_eq!(history.exists_from_height, Some(2));
+    assert_eq!(history.batch_root, Some(batch.root()));
+
+    // The earlier registration wins
+    let history = inner.before_proof_history_in_batch(&batch.proof(&content_ids[1]).unwrap());
+    assert_eq!(history.unwrap().exists_from_height, Some(2));
+    assert_eq!(
+        inner.before_proof_history(&content_ids[1], None).unwrap().exists_from_height,
+        Some(3)
+    );
+
+    let mut forged = proof.clone();
+    forged.content_id = "cjuForged".to_owned();
+    assert!(inner.before_proof_exists_in_batch_at(&forged, None).is_err());
+    let other_batch = BeforeProofBatch::new(vec![content_ids[0].clone()]).unwrap();
+    let unregistered = other_batch.proof(&content_ids[0]).unwrap();
+    assert!(!inner.before_proof_exists_in_batch_at(&unregistered, None).unwrap());
+
+    let state_root = inner.state_root_at(None).unwrap();
+    let state_proof = inner.before_proof_batch_state_proof(&batch.root(), None).unwrap();
+    state_proof.verify_before_proof_batch(&batch.root(), &state_root).unwrap();
+
+    state.block_reverting(3).unwrap();
+    state.revert_transaction("tx3", &before_proof_asset(&content_ids[1])).unwrap();
+    state.block_reverting(2).unwrap();
+    state.revert_transaction("tx2", &register_batch(&batch.root())).unwrap();
+    state.revert_transaction("tx1", &register_batch(&batch.root())).unwrap();
+    let inner = state.state().unwrap();
+    assert!(!inner.before_proof_exists_in_batch_at(&proof, None).unwrap());
+}
+
+#[test]
+fn dids_resolve_to_w3c_documents_with_metadata() {
+    let mut state = StateHolder::new();
+    let (did, implicit_did) = (did(1), did(2));
+    let did_str = did.to_string();
+
+    state.block_applying_at(2, 1000).unwrap();
+    let add_key = vec![SignableOperationDetails::AddKey { auth: auth(2), expires_at_height: None }];
+    state.apply_transaction("tx1", &signed_asset(1, &did, None, add_key)).unwrap();
+    state.block_applying_at(3, 1060).unwrap();
+    state.block_applying_at(4, 1120).unwrap();
+    let tombstone = vec![SignableOperationDetails::TombstoneDid {}];
+    state.apply_transaction("tx2", &signed_asset(1, &did, Some("tx1"), tombstone)).unwrap();
+
+    let inner = state.state().unwrap();
+    let resolved = inner.resolve_w3c(&did_str, Some(3)).unwrap();
+    let doc = &resolved.did_document;
+    assert_eq!(doc.id, did_str);
+    assert_eq!(doc.verification_method.len(), 2);
+    assert_eq!(doc.authentication, vec![format!("{}#0", did_str)]);
+    assert_eq!(doc.capability_invocation, vec![format!("{}#0", did_str)]);
+    let metadata = &resolved.did_document_metadata;
+    assert_eq!(metadata.created.as_deref(), Some("1970-01-01T00:16:40Z"));
+    assert_eq!(metadata.updated, metadata.created);
+    assert_eq!(metadata.version_id.as_deref(), Some("tx1"));
+    assert_eq!((metadata.created_at_height, metadata.updated_at_height), (Some(2), Some(2)));
+    assert!(!metadata.deactivated);
+
+    let resolved = inner.resolve_w3c(&did_str, None).unwrap();
+    assert!(resolved.did_document.verification_method.is_empty());
+    let metadata = &resolved.did_document_metadata;
+    assert!(metadata.deactivated);
+    assert_eq!(metadata.updated.as_deref(), Some("1970-01-01T00:18:40Z"));
+    assert_eq!(metadata.version_id.as_deref(), Some("tx2"));
+    let json = serde_json::to_value(&resolved).unwrap();
+    assert_eq!(json["didDocumentMetadata"]["versionId"], "tx2");
+    assert_eq!(json["didDocument"]["id"], did_str);
+
+    let resolved = inner.resolve_w3c(&implicit_did.to_string(), None).unwrap();
+    assert_eq!(resolved.did_document.verification_method.len(), 1);
+    assert_eq!(resolved.did_document_metadata, Default::default());
+}
+
+#[test]
+fn dids_and_transactions_are_listed_in_pages() {
+    let mut state = StateHolder::new();
+    let add_key =
+        |idx| vec![SignableOperationDetails::AddKey { auth: auth(idx), expires_at_height: None }];
+
+    state.block_applying(2).unwrap();
+    for idx in 1..=3 {
+        let asset = signed_asset(idx, &did(idx), None, add_key(5 + idx));
+        state.apply_transaction(&format!("tx{}", idx), &asset).unwrap();
+    }
+    state.block_applying(3).unwrap();
+    let tombstone = vec![SignableOperationDetails::TombstoneDid {}];
+    state.apply_transaction("tx4", &signed_asset(3, &did(3), Some("tx3"), tombstone)).unwrap();
+    state.apply_transaction("tx5", &signed_asset(1, &did(1), Some("tx1"), add_key(5))).unwrap();
+    state.block_applying(4).unwrap();
+    let revoke_key = vec![SignableOperationDetails::RevokeKey { auth: auth(5) }];
+    state.apply_transaction("tx6", &signed_asset(1, &did(1), Some("tx5"), revoke_key)).unwrap();
+    state.apply_transaction("tx7", &signed_asset(2, &did(2), Some("tx2"), add_key(5))).unwrap();
+
+    let inner = state.state().unwrap();
+    let mut all_dids: Vec<_> = (1..=3).map(|idx| did(idx).to_string()).collect();
+    all_dids.sort();
+    let first = inner.list_dids(&DidFilter::default(), None, 2).unwrap();
+    assert_eq!(first.items, all_dids[..2]);
+    let cursor = first.next_cursor.unwrap();
+    let second = inner.list_dids(&DidFilter::default(), Some(&cursor), 2).unwrap();
+    assert_eq!(second.items, all_dids[2..]);
+    assert_eq!(second.next_cursor, None);
+    assert!(inner.list_dids(&DidFilter::default(), None, 0).is_err());
+
+    let list = |filter: DidFilter| inner.list_dids(&filter, None, 10).unwrap().items;
+    assert_eq!(
+        list(DidFilter { tombstoned: Some(true), ..Default::default() }),
+        vec![did(3).to_string()]
+    );
+    assert_eq!(list(DidFilter { tombstoned: Some(false), ..Default::default() }).len(), 2);
+    let changed_at_4 = DidFilter {
+        changed_from_height: Some(4),
+        changed_until_height: Some(4),
+        ..Default::default()
+    };
+    assert_eq!(list(changed_at_4).len(), 2);
+    let changed_at_2 = DidFilter { changed_until_height: Some(2), ..Default::default() };
+    assert_eq!(list(changed_at_2), all_dids);
+    // Key 5 was revoked from the first DID
+    assert_eq!(
+        list(DidFilter { key: Some(auth(5)), ..Default::default() }),
+        vec![did(2).to_string()]
+    );
+
+    let did_1 = did(1).to_string();
+    let first = inner.get_tx_ids_page(&did_1, false, 0, None, None, 2).unwrap();
+    let txids: Vec<_> = first.items.iter().map(|t| t.transaction_id.as_str()).collect();
+    assert_eq!(txids, vec!["tx6", "tx5"]);
+    assert_eq!(first.next_cursor.as_deref(), Some("tx5"));
+    let second = inner.get_tx_ids_page(&did_1, false, 0, None, Some("tx5"), 2).unwrap();
+    assert_eq!(
+        second.items,
+        vec![TransactionIdWithHeight { transaction_id: "tx1".to_owned(), height: 2 }]
+    );
+    assert_eq!(second.next_cursor, None);
+    assert!(inner.get_tx_ids_page(&did_1, false, 0, None, Some("tx7"), 2).is_err());
+}
+
+#[test]
+fn keys_can_be_suspended_resumed_and_have_their_expiry_changed() {
+    let mut state = sample_state();
+    let did = did(1);
+    let did_str = did.to_string();
+    let doc_before = state.state().unwrap().get_doc_at(&did_str, None).unwrap();
+
+    state.block_applying(4).unwrap();
+    let set_expiry =
+        vec![SignableOperationDetails::SetKeyExpiry { auth: auth(2), expires_at_height: Some(10) }];
+    let expiry_asset = signed_asset(1, &did, Some("tx2"), set_expiry);
+    state.apply_transaction("tx4", &expiry_asset).unwrap();
+
+    state.block_applying(5).unwrap();
+    let suspend = vec![SignableOperationDetails::SuspendKey { auth: auth(2) }];
+    let suspend_asset = signed_asset(1, &did, Some("tx4"), suspend);
+    state.apply_transaction("tx5", &suspend_asset).unwrap();
+    let self_resume = vec![SignableOperationDetails::ResumeKey { auth: auth(2) }];
+    state.apply_transaction("tx6", &signed_asset(2, &did, Some("tx5"), self_resume)).unwrap_err();
+
+    state.block_applying(7).unwrap();
+    let resume = vec![SignableOperationDetails::ResumeKey { auth: auth(2) }];
+    let resume_asset = signed_asset(1, &did, Some("tx5"), resume.clone());
+    state.apply_transaction("tx7", &resume_asset).unwrap();
+    state.apply_transaction("tx8", &signed_asset(1, &did, Some("tx7"), resume)).unwrap_err();
+
+    state.block_applying(8).unwrap();
+    let past_expiry =
+        vec![SignableOperationDetails::SetKeyExpiry { auth: auth(2), expires_at_height: Some(8) }];
+    state.apply_transaction("tx9", &signed_asset(1, &did, Some("tx7"), past_expiry)).unwrap_err();
+    let remove_expiry =
+        vec![SignableOperationDetails::SetKeyExpiry { auth: auth(2), expires_at_height: None }];
+    let remove_expiry_asset = signed_asset(1, &did, Some("tx7"), remove_expiry);
+    state.apply_transaction("tx10", &remove_expiry_asset).unwrap();
+
+    let inner = state.state().unwrap();
+    let doc = inner.get_doc_at(&did_str, None).unwrap();
+    let key = &doc.keys[1].state;
+    assert_eq!(key.valid_until_block, None);
+    assert_eq!(key.suspensions, vec![KeySuspension { from_height: 5, until_height: Some(7) }]);
+    let valid_at: Vec<_> =
+        (3..=8).map(|h| doc.has_right_at(&auth(2), Right::Impersonation, h).unwrap()).collect();
+    assert_eq!(valid_at, vec![true, true, false, false, true, true]);
+    let validation = doc.validate_right(&auth(2), Right::Impersonation, 5, 7).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::Invalid);
+    let validation = doc.validate_right(&auth(2), Right::Impersonation, 3, 8).unwrap();
+    assert_eq!(validation.status(), ValidationStatus::MaybeValid);
+    let suspended_doc = inner.get_doc_at(&did_str, Some(6)).unwrap();
+    assert!(!suspended_doc.keys[1].derived.valid);
+    let root_at_6 = inner.state_root_at(Some(6)).unwrap();
+
+    let diff = inner.get_doc_diff(&did_str, 3, 8).unwrap();
+    let kinds: Vec<_> = diff.changes.iter().map(|c| (c.height, c.kind.clone())).collect();
+    let key_link = "#1".to_owned();
+    assert_eq!(
+        kinds,
+        vec![
+            (
+                4,
+                DidChangeKind::KeyExpiryChanged {
+                    key_link: key_link.clone(),
+                    auth: auth(2),
+                    expires_at_height: Some(10)
+                }
+            ),
+            (5, DidChangeKind::KeySuspended { key_link: key_link.clone(), auth: auth(2) }),
+            (7, DidChangeKind::KeyResumed { key_link: key_link.clone(), auth: auth(2) }),
+            (
+                8,
+                DidChangeKind::KeyExpiryChanged {
+                    key_link,
+                    auth: auth(2),
+                    expires_at_height: None
+                }
+            ),
+        ]
+    );
+
+    state.revert_transaction("tx10", &remove_expiry_asset).unwrap();
+    let doc = state.state().unwrap().get_doc_at(&did_str, None).unwrap();
+    assert_eq!(doc.keys[1].state.valid_until_block, Some(10));
+    state.block_reverting(7).unwrap();
+    state.revert_transaction("tx7", &resume_asset).unwrap();
+    assert_eq!(state.state().unwrap().state_root_at(Some(6)).unwrap(), root_at_6);
+    state.block_reverting(5).unwrap();
+    state.revert_transaction("tx5", &suspend_asset).unwrap();
+    state.block_reverting(4).unwrap();
+    state.revert_transaction("tx4", &expiry_asset).unwrap();
+    let doc_after = state.state().unwrap().get_doc_at(&did_str, Some(3)).unwrap();
+    assert_eq!(doc_after, doc_before);
+}
+
+#[test]
+fn dry_run_reports_every_failing_attempt_with_its_code() {
+    let mut state = sample_state();
+    let did = did(1);
+    state.block_applying(4).unwrap();
+
+    let attempt = |signer_idx: u8, last_tx_id: &str, op: SignableOperationDetails| {
+        let asset = signed_asset(signer_idx, &did, Some(last_tx_id), vec![op]);
+        asset.operation_attempts[0].clone()
+    };
+    let add_key =
+        |idx: u8| SignableOperationDetails::AddKey { auth: auth(idx), expires_at_height: None };
+    let hub = Service {
+        type_: ServiceType::Hub,
+        name: "hub".to_owned(),
+        service_endpoint: "https://hub.example.com".to_owned(),
+    };
+    let mut forged = attempt(1, "tx2", add_key(5));
+    if let OperationAttempt::Signed(op) = &mut forged {
+        op.signer_public_key = private_key(3).public_key().to_string();
+    }
+    let asset = MorpheusAsset::new(vec![
+        before_proof_asset("cjuFoo").operation_attempts[0].clone(),
+        attempt(1, "tx1", add_key(3)),
+        attempt(2, "tx2", add_key(3)),
+        attempt(1, "tx2", add_key(2)),
+        attempt(1, "tx2", SignableOperationDetails::AddService { service: hub }),
+        attempt(1, "tx2", SignableOperationDetails::TombstoneDid {}),
+        attempt(1, "tx2", add_key(4)),
+        forged,
+    ]);
+
+    let errors = state.dry_run(&asset).unwrap();
+    let codes: Vec<_> = errors.iter().map(|e| e.code).collect();
+    use OperationErrorCode::*;
+    assert_eq!(
+        codes,
+        vec![
+            BeforeProofAlreadyRegistered,
+            StaleLastTxId,
+            NotAuthorized,
+            KeyAlreadyAdded,
+            TombstonedDid,
+            InvalidSignature
+        ]
+    );
+    assert_eq!(errors[1].invalid_operation_attempt.as_ref(), Some(&asset.operation_attempts[1]));
+    let json = serde_json::to_value(&errors[1]).unwrap();
+    assert_eq!(json["code"], "staleLastTxId");
+
+    let valid_asset = signed_asset(1, &did, Some("tx2"), vec![add_key(3)]);
+    assert!(state.dry_run(&valid_asset).unwrap().is_empty());
+}
+
+#[test]
+fn issuers_can_revoke_their_credentials() {
+    let mut state = sample_state();
+    let other_did_str = did(2).to_string();
+    let did = did(1);
+    let did_str = did.to_string();
+    let statement_id = "cjuStatement";
+    let revoke =
+        || vec![SignableOperationDetails::RevokeCredential { content_id: statement_id.to_owned() }];
+
+    state.block_applying(4).unwrap();
+    // Key 2 holds the impersonation right, which is enough to revoke, key 3 has no rights
+    state.apply_transaction("tx4", &signed_asset(3, &did, Some("tx2"), revoke())).unwrap_err();
+    let asset = signed_asset(2, &did, Some("tx2"), revoke());
+    state.apply_transaction("tx5", &asset).unwrap();
+    let errors = state.dry_run(&signed_asset(1, &did, Some("tx5"), revoke())).unwrap();
+    assert_eq!(errors[0].code, OperationErrorCode::CredentialAlreadyRevoked);
+
+    let inner = state.state().unwrap();
+    assert!(!inner.is_revoked_at(&did_str, statement_id, Some(3)).unwrap());
+    assert!(inner.is_revoked_at(&did_str, statement_id, Some(4)).unwrap());
+    assert!(inner.is_revoked_at(&did_str, statement_id, None).unwrap());
+    assert!(!inner.is_revoked_at(&other_did_str, statement_id, None).unwrap());
+    assert!(!inner.is_revoked_at(&did_str, "cjuOther", None).unwrap());
+
+    state.revert_transaction("tx5", &asset).unwrap();
+    assert!(!state.state().unwrap().is_revoked_at(&did_str, statement_id, None).unwrap());
+}
@@ -1,12 +1,13 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct TimeSeriesPoint<T> {
     height: BlockHeight,
     value: T,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TimeSeries<T: fmt::Display + PartialEq<T>> {
     initial_value: T,
     points: Vec<TimeSeriesPoint<T>>,
@@ -43,18 +44,36 @@ impl<T: fmt::Display + PartialEq<T>> TimeSeries<T> {
         self.points.last().map(|p| p.height)
     }
 
+    /// Squashes the points before `height` into the initial value. Values at `height` and above
+    /// are unchanged.
+    pub fn prune_below(&mut self, height: BlockHeight) {
+        let pruned_count = self.points.iter().take_while(|p| p.height < height).count();
+        if let Some(last_pruned) = self.points.drain(..pruned_count).last() {
+            self.initial_value = last_pruned.value;
+        }
+    }
+
     pub fn apply<D: fmt::Display>(
         &mut self, height: BlockHeight, value: T, context: impl FnOnce() -> D,
     ) -> Result<()> {
         if let Some(last) = self.points.last() {
-            ensure!(last.height < height, "{} was already set at height {}", context(), height);
+            ensure!(
+                last.height < height,
+                OperationErrorCode::AlreadyChangedInBlock.error(format!(
+                    "{} was already set at height {}",
+                    context(),
+                    height
+                ))
+            );
         }
         ensure!(
             self.latest_value() != &value,
-            "{} was already set to {} at height {}",
-            context(),
-            value,
-            height
+            OperationErrorCode::AlreadySet.error(format!(
+                "{} was already set to {} at height {}",
+                context(),
+                value,
+                height
+            ))
         );
         self.points.push(TimeSeriesPoint { height, value });
         Ok(())
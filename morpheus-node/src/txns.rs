@@ -6,9 +6,10 @@ pub(super) struct DidTransactionItem<'a> {
     pub height: BlockHeight,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub(super) struct DidTransactionsState {
-    map: HashMap<String, Vec<TransactionIdWithHeight>>,
+    map: im::HashMap<String, Vec<TransactionIdWithHeight>>,
 }
 
 impl DidTransactionsState {
@@ -22,6 +23,52 @@ impl DidTransactionsState {
         })
     }
 
+    pub fn find_above(&self, height: BlockHeight) -> Option<&TransactionIdWithHeight> {
+        // Latest transaction is always the first one for each DID
+        self.map.values().filter_map(|txns| txns.first()).find(|item| item.height > height)
+    }
+
+    /// Forgets transactions below `height` except the last confirmed one of each DID, which is
+    /// needed to check the `last_tx_id` of new operations. Returns the forgotten transaction ids
+    /// and the ones kept below `height`.
+    pub fn prune_below(
+        &mut self, height: BlockHeight, is_confirmed: impl Fn(&str) -> bool,
+    ) -> (HashSet<String>, HashSet<String>) {
+        let (mut forgotten, mut kept) = (HashSet::new(), HashSet::new());
+        let mut changed = vec![];
+        for (did, txns) in self.map.iter() {
+            let mut last_confirmed_kept = false;
+            let retained: Vec<_> = txns
+                .iter()
+                .filter(|item| {
+                    if item.height >= height {
+                        return true;
+                    }
+                    if !last_confirmed_kept && is_confirmed(&item.transaction_id) {
+                        last_confirmed_kept = true;
+                        kept.insert(item.transaction_id.to_owned());
+                        return true;
+                    }
+                    forgotten.insert(item.transaction_id.to_owned());
+                    false
+                })
+                .cloned()
+                .collect();
+            // Only DIDs with forgotten transactions are copied
+            if retained.len() < txns.len() {
+                changed.push((did.to_owned(), retained));
+            }
+        }
+        for (did, retained) in changed {
+            if retained.is_empty() {
+                self.map.remove(&did);
+            } else {
+                self.map.insert(did, retained);
+            }
+        }
+        (forgotten, kept)
+    }
+
     pub fn apply(&mut self, item: DidTransactionItem) {
         let (did, txid, height) = (item.did, item.txid, item.height);
         let txns = self.map.entry(did).or_default();
@@ -9,6 +9,9 @@ pub enum Authentication {
     KeyId(multicipher::MKeyId),
     #[serde(with = "serde_str")]
     PublicKey(multicipher::MPublicKey),
+    /// Keys holding the update right in the document of this controller DID
+    #[serde(with = "serde_str")]
+    Did(Did),
 }
 
 impl PartialEq for Authentication {
@@ -17,10 +20,16 @@ impl PartialEq for Authentication {
             Authentication::KeyId(id) => match other {
                 Authentication::KeyId(other_id) => *id == *other_id,
                 Authentication::PublicKey(other_key) => other_key.validate_id(id),
+                Authentication::Did(_) => false,
             },
             Authentication::PublicKey(key) => match other {
                 Authentication::KeyId(other_id) => key.validate_id(other_id),
                 Authentication::PublicKey(other_key) => *key == *other_key,
+                Authentication::Did(_) => false,
+            },
+            Authentication::Did(did) => match other {
+                Authentication::Did(other_did) => *did == *other_did,
+                _ => false,
             },
         }
     }
@@ -39,6 +48,19 @@ impl fmt::Display for Authentication {
         match self {
             Self::KeyId(id) => id.fmt(f),
             Self::PublicKey(key) => key.fmt(f),
+            Self::Did(did) => did.fmt(f),
+        }
+    }
+}
+
+impl Authentication {
+    /// The id of the key, regardless whether the authentication holds the id or the full public key.
+    /// Controller DIDs have no single key.
+    pub fn key_id(&self) -> Option<multicipher::MKeyId> {
+        match self {
+            Self::KeyId(id) => Some(id.to_owned()),
+            Self::PublicKey(key) => Some(key.key_id()),
+            Self::Did(_) => None,
         }
     }
 }
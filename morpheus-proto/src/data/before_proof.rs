@@ -1,9 +1,118 @@
 use super::*;
 
+use crate::crypto::hash::ContentId;
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BeforeProofHistory {
     pub content_id: String,
     pub exists_from_height: Option<BlockHeight>,
     pub queried_at_height: BlockHeight,
+    /// Set if the content id exists because it was included in a registered batch.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub batch_root: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchLeaf<'a> {
+    batched_content_id: &'a str,
+}
+
+// Leaves are JSON content ids, while inner nodes hash bytes, so a leaf cannot be confused with a
+// subtree even if the content id itself is a binary hash
+fn batch_leaf_hash(content_id: &str) -> Result<String> {
+    digest_data(&BatchLeaf { batched_content_id: content_id })
+}
+
+/// Content ids timestamped together by registering only the Merkle root of the batch with
+/// `OperationAttempt::RegisterBeforeProofBatch`.
+#[derive(Clone, Debug)]
+pub struct BeforeProofBatch {
+    content_ids: Vec<ContentId>,
+    tree: MerkleTree,
+}
+
+impl BeforeProofBatch {
+    pub fn new(content_ids: Vec<ContentId>) -> Result<Self> {
+        ensure!(!content_ids.is_empty(), "Before proof batch must contain content ids");
+        let mut unique = content_ids.clone();
+        unique.sort();
+        unique.dedup();
+        ensure!(unique.len() == content_ids.len(), "Before proof batch contains duplicates");
+        let hashes = content_ids.iter().map(|id| batch_leaf_hash(id)).collect::<Result<_>>()?;
+        Ok(Self { content_ids, tree: MerkleTree::new(hashes) })
+    }
+
+    pub fn content_ids(&self) -> &[ContentId] {
+        &self.content_ids
+    }
+
+    pub fn root(&self) -> ContentId {
+        self.tree.root()
+    }
+
+    pub fn proof(&self, content_id: &str) -> Result<BeforeProofInclusionProof> {
+        let index = self
+            .content_ids
+            .iter()
+            .position(|id| id == content_id)
+            .ok_or_else(|| anyhow!("Content id {} is not in the batch", content_id))?;
+        Ok(BeforeProofInclusionProof {
+            content_id: content_id.to_owned(),
+            root: self.root(),
+            path: self.tree.path(index)?,
+        })
+    }
+}
+
+/// Shows that a content id is included in the before proof batch with the given root.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BeforeProofInclusionProof {
+    pub content_id: ContentId,
+    pub root: ContentId,
+    pub path: Vec<MerkleStep>,
+}
+
+impl BeforeProofInclusionProof {
+    pub fn validate(&self) -> Result<()> {
+        let root = root_of_path(batch_leaf_hash(&self.content_id)?, &self.path);
+        ensure!(
+            root == self.root,
+            "Content id {} is not included in before proof batch {}",
+            self.content_id,
+            self.root
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batch_proofs_validate_only_their_content_id() -> Result<()> {
+        let ids: Vec<ContentId> = (0..5).map(|i| format!("cjuDocument{}", i)).collect();
+        let batch = BeforeProofBatch::new(ids.clone())?;
+        for id in &ids {
+            let proof = batch.proof(id)?;
+            assert_eq!(proof.root, batch.root());
+            proof.validate()?;
+
+            let mut forged = proof.clone();
+            forged.content_id = "cjuOther".to_owned();
+            assert!(forged.validate().is_err());
+        }
+        assert!(batch.proof("cjuOther").is_err());
+
+        let single = BeforeProofBatch::new(vec![ids[0].clone()])?;
+        single.proof(&ids[0])?.validate()?;
+        assert_ne!(single.root(), ids[0]);
+
+        assert!(BeforeProofBatch::new(vec![]).is_err());
+        assert!(BeforeProofBatch::new(vec![ids[0].clone(), ids[0].clone()]).is_err());
+        Ok(())
+    }
 }
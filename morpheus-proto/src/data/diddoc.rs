@@ -3,24 +3,24 @@ use super::*;
 use crate::data::auth::Authentication;
 use crate::data::{
     did::Did,
-    validation::{ValidationIssueSeverity as Severity, ValidationResult},
+    validation::{ValidationIssueSeverity as Severity, ValidationResult, ValidationStatus},
 };
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
+/// Rights that keys can hold in a DID document. Update and impersonation are system rights with
+/// a special meaning in the protocol, all other rights are defined and interpreted by applications.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
 pub enum Right {
-    #[serde(rename = "update")]
     Update,
-    #[serde(rename = "impersonate")]
     Impersonation,
+    Custom(String),
 }
 
 impl Display for Right {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let value =
-            serde_json::to_value(self).expect("Implementation error: Right is not serializable");
-        match value {
-            serde_json::Value::String(s) => write!(f, "{}", s),
-            _ => panic!("Implementation error: unexpected Right serialization"),
+        match self {
+            Self::Update => write!(f, "{}", Self::UPDATE),
+            Self::Impersonation => write!(f, "{}", Self::IMPERSONATION),
+            Self::Custom(name) => write!(f, "{}", name),
         }
     }
 }
@@ -28,13 +28,60 @@ impl Display for Right {
 impl FromStr for Right {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(serde_json::from_value(serde_json::Value::String(s.to_owned()))?)
+        match s {
+            Self::UPDATE => Ok(Self::Update),
+            Self::IMPERSONATION => Ok(Self::Impersonation),
+            name => {
+                ensure!(
+                    !name.is_empty() && name.len() <= Self::MAX_NAME_LEN,
+                    "Right name must be 1-{} characters long",
+                    Self::MAX_NAME_LEN
+                );
+                ensure!(
+                    name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+                    "Right name {} can contain only lowercase letters, digits and hyphens",
+                    name
+                );
+                Ok(Self::Custom(name.to_owned()))
+            }
+        }
+    }
+}
+
+impl Serialize for Right {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Right {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
     }
 }
 
 impl Right {
+    const UPDATE: &'static str = "update";
+    const IMPERSONATION: &'static str = "impersonate";
+    const MAX_NAME_LEN: usize = 64;
+
+    pub fn system_rights() -> Vec<Right> {
+        vec![Self::Update, Self::Impersonation]
+    }
+
+    pub fn is_system(&self) -> bool {
+        !matches!(self, Self::Custom(_))
+    }
+
     pub fn map_all<T>(f: impl Fn(&Right) -> T) -> HashMap<Right, T> {
-        vec![Self::Update, Self::Impersonation].drain(..).map(|r| (r, f(&r))).collect()
+        Self::system_rights()
+            .into_iter()
+            .map(|r| {
+                let value = f(&r);
+                (r, value)
+            })
+            .collect()
     }
 }
 
@@ -66,6 +113,30 @@ pub struct KeyState {
     pub valid_from_block: Option<BlockHeight>, // TODO should be timestamp on the long term
     #[serde(rename = "validUntilHeight")]
     pub valid_until_block: Option<BlockHeight>, // TODO should be timestamp on the long term
+    /// Time of the block at `valid_from_block`, if the node knows it.
+    #[serde(rename = "validFromTime", skip_serializing_if = "Option::is_none", default)]
+    pub valid_from_time: Option<Timestamp>,
+    /// Time of the block at `valid_until_block`, if the node knows it.
+    #[serde(rename = "validUntilTime", skip_serializing_if = "Option::is_none", default)]
+    pub valid_until_time: Option<Timestamp>,
+    /// Periods in which the key was suspended, which are excluded from its validity.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub suspensions: Vec<KeySuspension>,
+}
+
+/// The key is invalid from `from_height` until it is resumed at `until_height`, if ever.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeySuspension {
+    pub from_height: BlockHeight,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub until_height: Option<BlockHeight>,
+}
+
+impl KeySuspension {
+    pub fn covers(&self, height: BlockHeight) -> bool {
+        is_in_opt_range(height, Some(self.from_height), self.until_height)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -83,13 +154,25 @@ pub struct KeyData {
 
 impl KeyData {
     fn from_auth(authentication: Authentication) -> Self {
-        let state = KeyState { authentication, valid_from_block: None, valid_until_block: None };
+        let state = KeyState {
+            authentication,
+            valid_from_block: None,
+            valid_until_block: None,
+            valid_from_time: None,
+            valid_until_time: None,
+            suspensions: Default::default(),
+        };
         let derived = KeyDataDerived { valid: true };
         Self { state, derived }
     }
 
     fn is_valid_at(&self, height: BlockHeight) -> bool {
         is_in_opt_range(height, self.state.valid_from_block, self.state.valid_until_block)
+            && !self.is_suspended_at(height)
+    }
+
+    pub fn is_suspended_at(&self, height: BlockHeight) -> bool {
+        self.state.suspensions.iter().any(|s| s.covers(height))
     }
 }
 
@@ -143,9 +226,11 @@ impl KeyRightHistory {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub enum ServiceType {
-    // TODO
+    Messaging,
+    Hub,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
@@ -153,9 +238,130 @@ pub struct Service {
     #[serde(rename = "type")]
     pub type_: ServiceType,
     pub name: String,
+    #[serde(rename = "serviceEndpoint")]
     pub service_endpoint: String, // TODO should we use multiaddr::Multiaddr here and thus add CID-dependency?
 }
 
+/// Decides which keys have to sign an operation to update a DID document.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum UpdatePolicy {
+    /// A signature of any key holding the update right is sufficient.
+    Any,
+    /// At least `threshold` distinct keys listed in `keys` have to sign, each holding the update right.
+    Threshold { threshold: u32, keys: Vec<Authentication> },
+}
+
+impl Default for UpdatePolicy {
+    fn default() -> Self {
+        Self::Any
+    }
+}
+
+impl Display for UpdatePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "any key with update right"),
+            Self::Threshold { threshold, keys } => {
+                let keys: Vec<_> = keys.iter().map(|k| k.to_string()).collect();
+                write!(f, "{} of [{}]", threshold, keys.join(", "))
+            }
+        }
+    }
+}
+
+impl UpdatePolicy {
+    pub fn is_any(&self) -> bool {
+        *self == Self::Any
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Self::Threshold { threshold, keys } = self {
+            ensure!(
+                1 <= *threshold && *threshold as usize <= keys.len(),
+                "Threshold {} must be between 1 and the number of keys {}",
+                threshold,
+                keys.len()
+            );
+            for (idx, key) in keys.iter().enumerate() {
+                ensure!(!keys[..idx].contains(key), "Key {} is listed multiple times", key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decides who can reset the keys of a DID after all of its update keys were lost.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum RecoveryPolicy {
+    /// Keys of the DID cannot be reset.
+    Disabled,
+    /// At least `threshold` distinct guardians have to sign to start a key reset, which can be
+    /// completed `delay` blocks later, unless the update keys of the DID cancel it in the meantime.
+    /// Guardians are recovery keys or DIDs, whose keys with the update right act on their behalf.
+    Guardians { threshold: u32, guardians: Vec<Authentication>, delay: BlockCount },
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self::Disabled
+    }
+}
+
+impl Display for RecoveryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "disabled"),
+            Self::Guardians { threshold, guardians, delay } => {
+                let guardians: Vec<_> = guardians.iter().map(|g| g.to_string()).collect();
+                write!(f, "{} of [{}] after {} blocks", threshold, guardians.join(", "), delay)
+            }
+        }
+    }
+}
+
+impl RecoveryPolicy {
+    pub fn is_disabled(&self) -> bool {
+        *self == Self::Disabled
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Self::Guardians { threshold, guardians, delay } = self {
+            ensure!(
+                1 <= *threshold && *threshold as usize <= guardians.len(),
+                "Threshold {} must be between 1 and the number of guardians {}",
+                threshold,
+                guardians.len()
+            );
+            for (idx, guardian) in guardians.iter().enumerate() {
+                ensure!(
+                    !guardians[..idx].contains(guardian),
+                    "Guardian {} is listed multiple times",
+                    guardian
+                );
+            }
+            ensure!(*delay > 0, "Recovery delay must be at least 1 block");
+        }
+        Ok(())
+    }
+}
+
+/// A key reset started by the guardians of a DID, which was not completed or cancelled yet.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingRecovery {
+    /// The only key left valid after the reset, holding all system rights.
+    pub auth: Authentication,
+    pub started_at_height: BlockHeight,
+    pub completable_at_height: BlockHeight,
+}
+
+/// Provides documents of controller DIDs, so authentications of type `Did` can be evaluated.
+pub trait DidResolver {
+    fn resolve_at(&self, did: &Did, height: BlockHeight) -> Result<DidDocument>;
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct DidDocument {
     #[serde(with = "serde_str")]
@@ -165,6 +371,16 @@ pub struct DidDocument {
     pub rights: HashMap<Right, Vec<KeyRightHistory>>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub services: Vec<Service>,
+    #[serde(rename = "updatePolicy", skip_serializing_if = "UpdatePolicy::is_any", default)]
+    pub update_policy: UpdatePolicy,
+    #[serde(
+        rename = "recoveryPolicy",
+        skip_serializing_if = "RecoveryPolicy::is_disabled",
+        default
+    )]
+    pub recovery_policy: RecoveryPolicy,
+    #[serde(rename = "pendingRecovery", skip_serializing_if = "Option::is_none", default)]
+    pub pending_recovery: Option<PendingRecovery>,
     #[serde(rename = "tombstonedAtHeight")]
     pub tombstoned_at_height: Option<BlockHeight>,
     pub tombstoned: bool,
@@ -180,6 +396,9 @@ impl DidDocument {
             keys: vec![default_key],
             rights: Default::default(),
             services: Default::default(),
+            update_policy: Default::default(),
+            recovery_policy: Default::default(),
+            pending_recovery: Default::default(),
             tombstoned_at_height: Default::default(),
             tombstoned: Default::default(),
             queried_at_height: Default::default(),
@@ -232,6 +451,192 @@ impl DidDocument {
         Ok(false)
     }
 
+    /// Like `has_right_at`, but also accepts keys holding the update right in the document of a
+    /// controller DID that holds the right in this document. Controllers are resolved recursively.
+    pub fn has_right_with_resolver_at(
+        &self, auth: &Authentication, right: Right, height: BlockHeight, resolver: &dyn DidResolver,
+    ) -> Result<bool> {
+        let mut visited = vec![self.did.to_owned()];
+        self.has_right_via_controllers_at(auth, right, height, resolver, &mut visited)
+    }
+
+    fn has_right_via_controllers_at(
+        &self, auth: &Authentication, right: Right, height: BlockHeight,
+        resolver: &dyn DidResolver, visited: &mut Vec<Did>,
+    ) -> Result<bool> {
+        if self.has_right_at(auth, right.clone(), height)? {
+            return Ok(true);
+        }
+
+        for controller in self.controllers() {
+            // Cycles of controllers cannot grant more rights than already checked
+            if visited.contains(controller) {
+                continue;
+            }
+            let controller_auth = Authentication::Did(controller.to_owned());
+            if !self.has_right_at(&controller_auth, right.clone(), height)? {
+                continue;
+            }
+            visited.push(controller.to_owned());
+            let controller_doc = resolver.resolve_at(controller, height)?;
+            if controller_doc.has_right_via_controllers_at(
+                auth,
+                Right::Update,
+                height,
+                resolver,
+                visited,
+            )? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn controllers(&self) -> impl Iterator<Item = &Did> {
+        self.keys.iter().filter_map(|key| match &key.state.authentication {
+            Authentication::Did(did) => Some(did),
+            _ => None,
+        })
+    }
+
+    /// Checks whether the operation signed by all `signers` satisfies the update policy of the DID.
+    pub fn is_update_authorized_at(
+        &self, signers: &[Authentication], height: BlockHeight, resolver: &dyn DidResolver,
+    ) -> Result<bool> {
+        let mut distinct_signers: Vec<&Authentication> = vec![];
+        for signer in signers {
+            if !distinct_signers.contains(&signer) {
+                distinct_signers.push(signer);
+            }
+        }
+
+        match &self.update_policy {
+            UpdatePolicy::Any => {
+                for signer in distinct_signers {
+                    if self.has_right_with_resolver_at(signer, Right::Update, height, resolver)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            UpdatePolicy::Threshold { threshold, keys } => {
+                let mut acts_as = vec![];
+                for signer in distinct_signers {
+                    let mut key_indexes = vec![];
+                    for (idx, key) in keys.iter().enumerate() {
+                        if self.acts_as_at(signer, key, height, resolver)? {
+                            key_indexes.push(idx);
+                        }
+                    }
+                    acts_as.push(key_indexes);
+                }
+                Ok(Self::matched_key_count(&acts_as, keys.len()) >= *threshold as usize)
+            }
+        }
+    }
+
+    /// The number of policy keys signed when each signer acts as at most one of the keys it can
+    /// act as and each key is signed by at most one signer, e.g. several keys of a controller DID
+    /// listed once in the policy count only once. `acts_as` has the key indexes for each signer.
+    fn matched_key_count(acts_as: &[Vec<usize>], key_count: usize) -> usize {
+        // Assigns the signer to a key, moving signers already assigned to other keys if needed
+        fn assign(
+            signer: usize, acts_as: &[Vec<usize>], signer_of_key: &mut [Option<usize>],
+            visited: &mut [bool],
+        ) -> bool {
+            for &key in &acts_as[signer] {
+                if visited[key] {
+                    continue;
+                }
+                visited[key] = true;
+                let is_free = match signer_of_key[key] {
+                    Some(other) => assign(other, acts_as, signer_of_key, visited),
+                    None => true,
+                };
+                if is_free {
+                    signer_of_key[key] = Some(signer);
+                    return true;
+                }
+            }
+            false
+        }
+
+        let mut signer_of_key = vec![None; key_count];
+        (0..acts_as.len())
+            .filter(|signer| {
+                let mut visited = vec![false; key_count];
+                assign(*signer, acts_as, &mut signer_of_key, &mut visited)
+            })
+            .count()
+    }
+
+    fn acts_as_at(
+        &self, signer: &Authentication, key: &Authentication, height: BlockHeight,
+        resolver: &dyn DidResolver,
+    ) -> Result<bool> {
+        if !self.has_right_at(key, Right::Update, height)? {
+            return Ok(false);
+        }
+        match key {
+            Authentication::Did(controller) => {
+                let controller_doc = resolver.resolve_at(controller, height)?;
+                controller_doc.has_right_with_resolver_at(signer, Right::Update, height, resolver)
+            }
+            _ => Ok(signer == key),
+        }
+    }
+
+    /// Checks whether the operation signed by all `signers` satisfies the recovery policy of the
+    /// DID, i.e. enough distinct guardians signed it.
+    pub fn is_recovery_authorized_at(
+        &self, signers: &[Authentication], height: BlockHeight, resolver: &dyn DidResolver,
+    ) -> Result<bool> {
+        let (threshold, guardians) = match &self.recovery_policy {
+            RecoveryPolicy::Disabled => return Ok(false),
+            RecoveryPolicy::Guardians { threshold, guardians, .. } => (threshold, guardians),
+        };
+
+        let mut guardian_docs = vec![];
+        for guardian in guardians {
+            let guardian_doc = match guardian {
+                Authentication::Did(guardian_did) => {
+                    Some(resolver.resolve_at(guardian_did, height)?)
+                }
+                _ => None,
+            };
+            guardian_docs.push(guardian_doc);
+        }
+
+        // A signer holding keys of several guardian DIDs signs only for one of them
+        let mut distinct_signers: Vec<&Authentication> = vec![];
+        for signer in signers {
+            if !distinct_signers.contains(&signer) {
+                distinct_signers.push(signer);
+            }
+        }
+        let mut acts_as = vec![];
+        for signer in distinct_signers {
+            let mut guardian_indexes = vec![];
+            for (idx, (guardian, guardian_doc)) in guardians.iter().zip(&guardian_docs).enumerate()
+            {
+                let signed = match guardian_doc {
+                    Some(guardian_doc) => guardian_doc.has_right_with_resolver_at(
+                        signer,
+                        Right::Update,
+                        height,
+                        resolver,
+                    )?,
+                    None => signer == guardian,
+                };
+                if signed {
+                    guardian_indexes.push(idx);
+                }
+            }
+            acts_as.push(guardian_indexes);
+        }
+        Ok(Self::matched_key_count(&acts_as, guardians.len()) >= *threshold as usize)
+    }
+
     pub fn is_tombstoned_at(&self, height: BlockHeight) -> Result<bool> {
         self.ensure_known_height(height)?;
 
@@ -311,8 +716,24 @@ impl DidDocument {
             }
         }
 
+        for suspension in &key_data.state.suspensions {
+            if suspension.covers(from) {
+                match suspension.until_height {
+                    Some(resumed) if resumed < until => {
+                        result.add_issue(Severity::Warning, "Key was resumed during given period")
+                    }
+                    _ => result.add_issue(Severity::Error, "Key was suspended during given period"),
+                }
+            } else if is_between(suspension.from_height, from, until) {
+                result.add_issue(Severity::Warning, "Key was suspended during given period");
+            }
+        }
+
         let history = &key_right.state.history;
-        ensure!(! history.is_empty(), "Implementation error: key related to rights were already filtered, right must be present here");
+        if history.is_empty() {
+            result.add_issue(Severity::Error, "Required right was never granted for key");
+            return Ok(result);
+        }
 
         let right_changes_in_range = history
             .iter()
@@ -329,6 +750,53 @@ impl DidDocument {
 
         Ok(result)
     }
+
+    /// Like `validate_right`, but also accepts keys holding the update right in the document of a
+    /// controller DID that holds the right in this document. Controllers are resolved recursively.
+    pub fn validate_right_with_resolver(
+        &self, auth: &Authentication, right: Right, from: BlockHeight, until: BlockHeight,
+        resolver: &dyn DidResolver,
+    ) -> Result<ValidationResult> {
+        let mut visited = vec![self.did.to_owned()];
+        self.validate_right_via_controllers(auth, right, from, until, resolver, &mut visited)
+    }
+
+    fn validate_right_via_controllers(
+        &self, auth: &Authentication, right: Right, from: BlockHeight, until: BlockHeight,
+        resolver: &dyn DidResolver, visited: &mut Vec<Did>,
+    ) -> Result<ValidationResult> {
+        let direct = self.validate_right(auth, right.clone(), from, until)?;
+        if direct.status() != ValidationStatus::Invalid {
+            return Ok(direct);
+        }
+
+        for controller in self.controllers() {
+            if visited.contains(controller) {
+                continue;
+            }
+            let controller_auth = Authentication::Did(controller.to_owned());
+            let mut result = self.validate_right(&controller_auth, right.clone(), from, until)?;
+            if result.status() == ValidationStatus::Invalid {
+                continue;
+            }
+            visited.push(controller.to_owned());
+            let controller_doc = resolver.resolve_at(controller, until)?;
+            let via_controller = controller_doc.validate_right_via_controllers(
+                auth,
+                Right::Update,
+                from,
+                until,
+                resolver,
+                visited,
+            )?;
+            if via_controller.status() == ValidationStatus::Invalid {
+                continue;
+            }
+            result.merge(via_controller);
+            return Ok(result);
+        }
+        Ok(direct)
+    }
 }
 
 #[cfg(test)]
@@ -2,10 +2,99 @@ use super::*;
 
 use crate::txtype::OperationAttempt;
 
+/// Stable reasons for rejecting an operation attempt, so clients can react to them without
+/// parsing messages. Variants are only ever added, never renamed or removed.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OperationErrorCode {
+    /// None of the codes below apply, the message tells the details.
+    Other,
+    /// The node stopped processing changes and has to be rebuilt.
+    StateCorrupted,
+    InvalidSignature,
+    /// The `lastTxId` of the attempt is not the last transaction of the DID.
+    StaleLastTxId,
+    TombstonedDid,
+    /// The signers do not satisfy the update or recovery policy of the DID.
+    NotAuthorized,
+    /// A key cannot modify its own authorization.
+    OwnAuthorization,
+    /// Keys cannot be changed in the first blocks of the chain.
+    HeightTooLow,
+    BeforeProofAlreadyRegistered,
+    BeforeProofBatchAlreadyRegistered,
+    KeyAlreadyAdded,
+    KeyNotFound,
+    /// The key is expired, revoked or suspended.
+    KeyInvalid,
+    KeyAlreadyRevoked,
+    KeyNotSuspended,
+    KeyInUpdatePolicy,
+    InvalidExpiry,
+    InvalidRight,
+    RightNotFound,
+    /// A right, policy or expiry already has the given value.
+    AlreadySet,
+    /// The same right, policy or suspension was already changed in the same block.
+    AlreadyChangedInBlock,
+    ServiceAlreadyAdded,
+    ServiceNotFound,
+    InvalidPolicy,
+    RecoveryDisabled,
+    RecoveryAlreadyPending,
+    NoPendingRecovery,
+    /// The key given does not match the one the pending recovery resets to.
+    RecoveryKeyMismatch,
+    /// The delay of the pending recovery has not passed yet.
+    RecoveryNotCompletable,
+    CredentialAlreadyRevoked,
+}
+
+impl Default for OperationErrorCode {
+    fn default() -> Self {
+        Self::Other
+    }
+}
+
+impl OperationErrorCode {
+    pub fn error(self, message: impl ToString) -> CodedError {
+        CodedError { code: self, message: message.to_string() }
+    }
+
+    /// The code of the first coded error in the chain of `err`.
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.chain().find_map(|e| e.downcast_ref::<CodedError>()).map(|e| e.code).unwrap_or_default()
+    }
+}
+
+/// An error with a code, which can be recovered with `OperationErrorCode::of` after it was
+/// converted into an `anyhow::Error`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CodedError {
+    pub code: OperationErrorCode,
+    pub message: String,
+}
+
+impl Display for CodedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OperationError {
     pub invalid_operation_attempt: Option<OperationAttempt>,
-    // code: u16; TODO: later we need exact error codes
+    #[serde(default)]
+    pub code: OperationErrorCode,
     pub message: String,
 }
+
+impl OperationError {
+    pub fn new(invalid_operation_attempt: Option<OperationAttempt>, err: &anyhow::Error) -> Self {
+        let code = OperationErrorCode::of(err);
+        Self { invalid_operation_attempt, code, message: err.to_string() }
+    }
+}
@@ -7,7 +7,12 @@ mod error;
 mod present;
 mod process;
 mod schema;
+mod state_proof;
+#[cfg(test)]
+mod test_fixtures;
 mod validation;
+mod verifier;
+mod w3c;
 
 pub use auth::*;
 pub use before_proof::*;
@@ -18,7 +23,10 @@ pub use error::*;
 pub use present::*;
 pub use process::*;
 pub use schema::*;
+pub use state_proof::*;
 pub use validation::*;
+pub use verifier::*;
+pub use w3c::*;
 
 use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
@@ -3,9 +3,9 @@ use super::*;
 use crate::{
     crypto::{
         hash::Content,
-        sign::{Signable, Signed},
+        sign::{Signable, Signed, SyncMorpheusSigner},
     },
-    data::did::Did,
+    data::{claim::WitnessStatement, did::Did},
 };
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, PartialOrd, Serialize)]
@@ -48,4 +48,149 @@ pub struct ClaimPresentation {
 impl Content for ClaimPresentation {}
 impl Signable for ClaimPresentation {}
 
+/// Collects witness statements and licenses into a presentation that reveals only the parts of
+/// each claim the holder chooses to disclose. Masked parts keep their content ids, so the
+/// signatures of the statements remain valid.
+#[derive(Clone, Debug, Default)]
+pub struct PresentationBuilder {
+    proven_claims: Vec<ProvenClaim>,
+    licenses: Vec<License>,
+    nonce: Option<Nonce264>,
+}
+
+impl PresentationBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a claim proven by the given statements, which all have to be about the same claim.
+    /// `keep_paths` are the comma-separated paths of the claim to disclose, e.g.
+    /// `.subject,.content.address`, everything else is masked. Only objects are masked into their
+    /// content ids, so scalar values are hidden by masking their parent. The authority of the
+    /// statements is always disclosed.
+    pub fn add_claim(
+        &mut self, statements: Vec<Signed<WitnessStatement>>, keep_paths: &str,
+    ) -> Result<&mut Self> {
+        ensure!(!statements.is_empty(), "A claim has to be proven by at least one statement");
+        let claim_paths = json_digest::json_path::split_alternatives(keep_paths);
+        let mut statement_paths = vec![".constraints.authority".to_owned()];
+        statement_paths.extend(claim_paths.iter().map(|path| format!(".claim{}", path)));
+        let statement_paths: Vec<_> = statement_paths.iter().map(|path| path.as_str()).collect();
+
+        let first_claim = serde_json::to_value(&statements[0].content().claim)?;
+        let claim_id = digest_data(&first_claim)?;
+        let mut masked_statements = Vec::with_capacity(statements.len());
+        for statement in statements {
+            ensure!(statement.validate(), "Signature of a statement is invalid");
+            let (public_key, content, signature, nonce) = statement.into_parts();
+            ensure!(
+                digest_data(&content.claim)? == claim_id,
+                "All statements of a claim have to be about the same claim"
+            );
+            let content = serde_json::to_value(&content)?;
+            let masked = json_digest::mask_json_subtree(&content, &statement_paths)?;
+            masked_statements.push(Signed::from_parts(public_key, masked, signature, nonce));
+        }
+
+        let claim = json_digest::mask_json_subtree(&first_claim, &claim_paths)?;
+        self.proven_claims.push(ProvenClaim { claim, statements: masked_statements });
+        Ok(self)
+    }
+
+    pub fn add_license(&mut self, license: License) -> &mut Self {
+        self.licenses.push(license);
+        self
+    }
+
+    pub fn with_nonce(&mut self, nonce: Nonce264) -> &mut Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn build(&self) -> ClaimPresentation {
+        ClaimPresentation {
+            proven_claims: self.proven_claims.clone(),
+            licenses: self.licenses.clone(),
+            nonce: self.nonce.clone(),
+        }
+    }
+
+    pub fn sign(&self, signer: &dyn SyncMorpheusSigner) -> Result<Signed<ClaimPresentation>> {
+        signer.sign_claim_presentation(self.build())
+    }
+}
+
 // TODO Maskable: T -> serde_json::Value
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::data::test_fixtures::*;
+    use crate::data::validation::ValidationStatus;
+
+    fn statement(subject: &Did, name: &str, authority_idx: u8) -> Result<Signed<WitnessStatement>> {
+        let statement = witness_statement(subject, name, &did(authority_idx));
+        signer(authority_idx).sign_witness_statement(statement)
+    }
+
+    #[test]
+    fn built_presentation_discloses_only_kept_paths() -> Result<()> {
+        let holder = did(1);
+        let statements = vec![statement(&holder, "Alice", 2)?, statement(&holder, "Alice", 3)?];
+        let original_claim = serde_json::to_value(&statements[0].content().claim)?;
+        let license = License {
+            issued_to: holder.clone(),
+            purpose: "inspection".to_owned(),
+            valid_from: "2020-01-01T00:00:00Z".to_owned(),
+            valid_until: "2021-01-01T00:00:00Z".to_owned(),
+        };
+        let nonce = Nonce264::generate();
+
+        let mut builder = PresentationBuilder::new();
+        builder.add_claim(statements, ".subject,.content.name")?;
+        builder.add_license(license).with_nonce(nonce.clone());
+        let signed = builder.sign(&signer(1))?;
+        assert!(signed.validate());
+
+        let presentation = signed.content();
+        let claim = &presentation.proven_claims[0].claim;
+        assert_eq!(digest_data(claim)?, digest_data(&original_claim)?);
+        assert_eq!(claim["subject"], serde_json::json!(holder.to_string()));
+        assert_eq!(claim["content"]["name"], serde_json::json!("Alice"));
+        assert!(claim["content"]["address"].as_str().unwrap().starts_with("cj"));
+        for statement in &presentation.proven_claims[0].statements {
+            assert!(statement.validate());
+            assert_eq!(&statement.content()["claim"], claim);
+            assert!(statement.content()["processId"].as_str().unwrap().starts_with("cj"));
+        }
+
+        let resolver = resolver(&[holder.clone(), did(2), did(3)]);
+        let time = time("2020-06-01T00:00:00Z");
+        let verifier =
+            PresentationVerifier::new(&resolver, holder, "inspection", 5, time).with_nonce(nonce);
+        assert_eq!(verifier.verify_signed(&signed)?.status(), ValidationStatus::Valid);
+        Ok(())
+    }
+
+    #[test]
+    fn statements_of_a_claim_must_be_valid_and_agree() -> Result<()> {
+        let holder = did(1);
+        let mut builder = PresentationBuilder::new();
+        assert!(builder.add_claim(vec![], ".subject").is_err());
+
+        let different = vec![statement(&holder, "Alice", 2)?, statement(&holder, "Bob", 3)?];
+        assert!(builder.add_claim(different, ".subject").is_err());
+
+        let (public_key, content, _, nonce) = statement(&holder, "Alice", 2)?.into_parts();
+        let (_, _, signature, _) = statement(&holder, "Bob", 2)?.into_parts();
+        let forged = Signed::from_parts(public_key, content, signature, nonce);
+        assert!(builder.add_claim(vec![forged], ".subject").is_err());
+
+        builder.add_claim(vec![statement(&holder, "Alice", 2)?], "")?;
+        let presentation = builder.build();
+        assert_eq!(presentation.proven_claims.len(), 1);
+        assert!(presentation.proven_claims[0].claim.is_string());
+        Ok(())
+    }
+}
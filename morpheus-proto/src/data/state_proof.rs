@@ -0,0 +1,434 @@
+use super::*;
+
+use crate::crypto::hash::{hash_bytes, ContentId};
+
+/// An entry committed into the state root of a block. Leaves only contain data all nodes agree on
+/// after processing the same blocks, so their roots are the same.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum StateLeaf {
+    /// A DID that had confirmed transactions, committed by the last one of them and by the digest
+    /// of its document after it, see `StateLeaf::did_document_digest`.
+    Did {
+        #[serde(with = "serde_str")]
+        did: Did,
+        #[serde(rename = "lastTxId")]
+        last_tx_id: String,
+        #[serde(rename = "lastTxHeight")]
+        last_tx_height: BlockHeight,
+        #[serde(rename = "documentDigest")]
+        document_digest: String,
+    },
+    BeforeProof {
+        #[serde(rename = "contentId")]
+        content_id: ContentId,
+        #[serde(rename = "registeredAtHeight")]
+        registered_at_height: BlockHeight,
+    },
+    BeforeProofBatch {
+        root: ContentId,
+        #[serde(rename = "registeredAtHeight")]
+        registered_at_height: BlockHeight,
+    },
+}
+
+/// The parts of a `DidDocument` committed in a `StateLeaf::Did`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CommittedDocument<'a> {
+    #[serde(with = "serde_str")]
+    did: &'a Did,
+    keys: Vec<CommittedKey<'a>>,
+    rights: HashMap<&'a Right, Vec<CommittedRight<'a>>>,
+    services: &'a [Service],
+    update_policy: &'a UpdatePolicy,
+    recovery_policy: &'a RecoveryPolicy,
+    pending_recovery: &'a Option<PendingRecovery>,
+    tombstoned_at_height: Option<BlockHeight>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CommittedKey<'a> {
+    auth: &'a Authentication,
+    valid_from_height: Option<BlockHeight>,
+    valid_until_height: Option<BlockHeight>,
+    suspensions: Vec<KeySuspension>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CommittedRight<'a> {
+    key_link: &'a str,
+    history: Vec<&'a KeyRightHistoryItem>,
+}
+
+impl StateLeaf {
+    pub fn did(doc: &DidDocument, last_tx_id: &str, last_tx_height: BlockHeight) -> Result<Self> {
+        Ok(Self::Did {
+            did: doc.did.to_owned(),
+            last_tx_id: last_tx_id.to_owned(),
+            last_tx_height,
+            document_digest: Self::did_document_digest(doc, last_tx_height)?,
+        })
+    }
+
+    /// Digest of what the document tells about its DID up to `last_tx_height`, the height of the
+    /// last confirmed transaction of the DID. Documents queried at any height before the next
+    /// transaction of the DID have the same digest, even if they were queried after it.
+    ///
+    /// Everything after `last_tx_height` is left out, also expiries set before it, because
+    /// documents do not tell them apart from later revocations. So is what changes without
+    /// transactions: derived validity, block times and the queried height.
+    pub fn did_document_digest(doc: &DidDocument, last_tx_height: BlockHeight) -> Result<String> {
+        let committed =
+            |height: Option<BlockHeight>| height.map(|h| h <= last_tx_height).unwrap_or(true);
+        let keys: Vec<_> = doc
+            .keys
+            .iter()
+            .take_while(|k| committed(k.state.valid_from_block))
+            .map(|k| {
+                let suspensions = k
+                    .state
+                    .suspensions
+                    .iter()
+                    .filter(|s| s.from_height <= last_tx_height)
+                    .map(|s| KeySuspension {
+                        from_height: s.from_height,
+                        until_height: s.until_height.filter(|h| *h <= last_tx_height),
+                    })
+                    .collect();
+                CommittedKey {
+                    auth: &k.state.authentication,
+                    valid_from_height: k.state.valid_from_block,
+                    valid_until_height: k.state.valid_until_block.filter(|h| *h <= last_tx_height),
+                    suspensions,
+                }
+            })
+            .collect();
+        let key_count = keys.len();
+        let mut rights = HashMap::new();
+        for (right, histories) in &doc.rights {
+            // Rights are listed for keys they were ever granted to, so ones first granted after the
+            // last transaction are left out as well as the ones of later keys
+            let committed_rights: Vec<_> = histories
+                .iter()
+                .filter(|h| {
+                    let idx =
+                        h.derived.key_link.strip_prefix('#').and_then(|i| i.parse::<usize>().ok());
+                    matches!(idx, Some(i) if i < key_count)
+                })
+                .map(|h| CommittedRight {
+                    key_link: &h.derived.key_link,
+                    history: h.state.history.iter().filter(|i| committed(i.height)).collect(),
+                })
+                .filter(|r| r.history.iter().any(|i| i.valid))
+                .collect();
+            if !committed_rights.is_empty() {
+                rights.insert(right, committed_rights);
+            }
+        }
+        let committed_doc = CommittedDocument {
+            did: &doc.did,
+            keys,
+            rights,
+            services: &doc.services,
+            update_policy: &doc.update_policy,
+            recovery_policy: &doc.recovery_policy,
+            pending_recovery: &doc.pending_recovery,
+            tombstoned_at_height: doc.tombstoned_at_height.filter(|h| *h <= last_tx_height),
+        };
+        digest_data(&committed_doc)
+    }
+
+    pub fn before_proof(history: &BeforeProofHistory) -> Result<Self> {
+        let registered_at_height = match history.exists_from_height {
+            Some(height) => height,
+            None => bail!("Before proof {} is not registered", history.content_id),
+        };
+        Ok(Self::BeforeProof { content_id: history.content_id.to_owned(), registered_at_height })
+    }
+
+    /// Identifies what the leaf commits to. Leaves are kept in the tree in the order their keys were
+    /// first committed, and a leaf replaces the one with the same key.
+    pub fn key(&self) -> String {
+        match self {
+            Self::Did { did, .. } => Self::did_key(did),
+            Self::BeforeProof { content_id, .. } => Self::before_proof_key(content_id),
+            Self::BeforeProofBatch { root, .. } => Self::before_proof_batch_key(root),
+        }
+    }
+
+    pub fn did_key(did: &Did) -> String {
+        did.to_string()
+    }
+
+    pub fn before_proof_key(content_id: &str) -> String {
+        format!("beforeProof/{}", content_id)
+    }
+
+    pub fn before_proof_batch_key(root: &str) -> String {
+        format!("beforeProofBatch/{}", root)
+    }
+
+    /// Leaf hashes are JSON content ids ("cj..."), while inner nodes hash bytes ("cb..."), so a
+    /// leaf can never be confused with a subtree.
+    pub fn hash(&self) -> Result<String> {
+        digest_data(self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MerkleSide {
+    Left,
+    Right,
+}
+
+/// A sibling on the path from a leaf to the root.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleStep {
+    pub side: MerkleSide,
+    pub hash: String,
+}
+
+/// Hash of an inner node of a `MerkleTree`.
+pub fn hash_merkle_node(left: &str, right: &str) -> String {
+    hash_bytes(format!("{}{}", left, right).as_bytes())
+}
+
+/// The root a path leads to from the hash of a leaf.
+pub(crate) fn root_of_path(leaf_hash: String, path: &[MerkleStep]) -> String {
+    path.iter().fold(leaf_hash, |hash, step| match step.side {
+        MerkleSide::Left => hash_merkle_node(&step.hash, &hash),
+        MerkleSide::Right => hash_merkle_node(&hash, &step.hash),
+    })
+}
+
+/// Binary Merkle tree over leaf hashes. A node without a sibling is promoted to the next level
+/// unchanged instead of being paired with itself.
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    levels: Vec<Vec<String>>,
+}
+
+impl MerkleTree {
+    pub fn new(leaf_hashes: Vec<String>) -> Self {
+        let mut levels = vec![leaf_hashes];
+        while levels.last().map(|l| l.len() > 1).unwrap_or_default() {
+            let level = levels.last().expect("checked above");
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_merkle_node(left, right),
+                    [single] => single.to_owned(),
+                    _ => unreachable!("chunks of 2"),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// The root of an empty tree is the hash of no bytes.
+    pub fn root(&self) -> String {
+        match self.levels.last().and_then(|l| l.first()) {
+            Some(root) => root.to_owned(),
+            None => hash_bytes(&[]),
+        }
+    }
+
+    pub fn path(&self, leaf_index: usize) -> Result<Vec<MerkleStep>> {
+        let leaf_count = self.levels[0].len();
+        ensure!(leaf_index < leaf_count, "Leaf index {} is out of {}", leaf_index, leaf_count);
+        let mut path = vec![];
+        let mut index = leaf_index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                let side = if sibling < index { MerkleSide::Left } else { MerkleSide::Right };
+                path.push(MerkleStep { side, hash: hash.to_owned() });
+            }
+            index /= 2;
+        }
+        Ok(path)
+    }
+}
+
+/// Proves that a leaf is committed in the state root of a block, so light clients can check data
+/// received from a node against a root they trust.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateInclusionProof {
+    pub height: BlockHeight,
+    pub leaf: StateLeaf,
+    pub path: Vec<MerkleStep>,
+}
+
+impl StateInclusionProof {
+    /// The root the path leads to from the leaf.
+    pub fn root(&self) -> Result<String> {
+        Ok(root_of_path(self.leaf.hash()?, &self.path))
+    }
+
+    pub fn verify(&self, trusted_root: &str) -> Result<()> {
+        let root = self.root()?;
+        ensure!(root == trusted_root, "Proof leads to root {} instead of {}", root, trusted_root);
+        Ok(())
+    }
+
+    /// Checks that `doc` is the document of its DID queried at the height of the proof and that the
+    /// last confirmed transaction of the DID was `last_tx_id` at that height.
+    pub fn verify_did(
+        &self, doc: &DidDocument, last_tx_id: &str, trusted_root: &str,
+    ) -> Result<()> {
+        let did = &doc.did;
+        ensure!(
+            doc.queried_at_height == self.height,
+            "Document of {} was queried at height {} instead of {}",
+            did,
+            doc.queried_at_height,
+            self.height
+        );
+        match &self.leaf {
+            StateLeaf::Did { did: d, last_tx_id: txid, last_tx_height, document_digest }
+                if d == did && txid == last_tx_id =>
+            {
+                ensure!(
+                    *last_tx_height <= self.height,
+                    "Transaction {} of {} was confirmed after height {}",
+                    last_tx_id,
+                    did,
+                    self.height
+                );
+                let digest = StateLeaf::did_document_digest(doc, *last_tx_height)?;
+                ensure!(
+                    &digest == document_digest,
+                    "Document of {} does not match the one committed at height {}",
+                    did,
+                    last_tx_height
+                );
+            }
+            _ => bail!("Proof is not about {} last changed by {}", did, last_tx_id),
+        }
+        self.verify(trusted_root)
+    }
+
+    /// Checks that the before proof was registered at or before the height of the proof.
+    pub fn verify_before_proof(&self, content_id: &str, trusted_root: &str) -> Result<()> {
+        match &self.leaf {
+            StateLeaf::BeforeProof { content_id: id, registered_at_height } if id == content_id => {
+                ensure!(
+                    *registered_at_height <= self.height,
+                    "Before proof {} was registered after height {}",
+                    content_id,
+                    self.height
+                );
+            }
+            _ => bail!("Proof is not about before proof {}", content_id),
+        }
+        self.verify(trusted_root)
+    }
+
+    /// Checks that the batch root was registered at or before the height of the proof.
+    pub fn verify_before_proof_batch(&self, root: &str, trusted_root: &str) -> Result<()> {
+        match &self.leaf {
+            StateLeaf::BeforeProofBatch { root: r, registered_at_height } if r == root => {
+                ensure!(
+                    *registered_at_height <= self.height,
+                    "Before proof batch {} was registered after height {}",
+                    root,
+                    self.height
+                );
+            }
+            _ => bail!("Proof is not about before proof batch {}", root),
+        }
+        self.verify(trusted_root)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaf(i: usize) -> StateLeaf {
+        let content_id = format!("cjcontent{}", i);
+        StateLeaf::BeforeProof { content_id, registered_at_height: i as BlockHeight }
+    }
+
+    #[test]
+    fn every_leaf_is_proven_in_trees_of_any_size() -> Result<()> {
+        for count in 1..=9 {
+            let leaves: Vec<_> = (0..count).map(leaf).collect();
+            let hashes = leaves.iter().map(|l| l.hash()).collect::<Result<Vec<_>>>()?;
+            let tree = MerkleTree::new(hashes);
+            let root = tree.root();
+            for (index, leaf) in leaves.into_iter().enumerate() {
+                let proof = StateInclusionProof { height: 10, leaf, path: tree.path(index)? };
+                proof.verify(&root)?;
+                proof.verify_before_proof(&format!("cjcontent{}", index), &root)?;
+                assert!(proof.verify_before_proof("cjother", &root).is_err());
+            }
+            assert!(tree.path(count).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() -> Result<()> {
+        let hashes = (0..5).map(|i| leaf(i).hash()).collect::<Result<Vec<_>>>()?;
+        let tree = MerkleTree::new(hashes);
+        let root = tree.root();
+
+        let mut proof = StateInclusionProof { height: 10, leaf: leaf(2), path: tree.path(2)? };
+        proof.verify(&root)?;
+        proof.leaf = leaf(3);
+        assert!(proof.verify(&root).is_err());
+
+        let mut proof = StateInclusionProof { height: 10, leaf: leaf(2), path: tree.path(2)? };
+        proof.path[0].side = MerkleSide::Left;
+        assert!(proof.verify(&root).is_err());
+
+        let proof = StateInclusionProof { height: 1, leaf: leaf(2), path: tree.path(2)? };
+        assert!(proof.verify_before_proof("cjcontent2", &root).is_err());
+
+        assert_eq!(MerkleTree::new(vec![]).root(), hash_bytes(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn did_proofs_commit_to_the_last_transaction_and_document() -> Result<()> {
+        let did: Did = "did:morpheus:ezbeWGSY2dqcUBqT8K7R14xr".parse()?;
+        let mut doc = DidDocument::implicit(&did);
+        doc.queried_at_height = 7;
+        let did_leaf = StateLeaf::did(&doc, "tx1", 7)?;
+        let hashes = vec![leaf(0).hash()?, did_leaf.hash()?, leaf(2).hash()?];
+        let tree = MerkleTree::new(hashes);
+        let root = tree.root();
+
+        let proof = StateInclusionProof { height: 8, leaf: did_leaf.clone(), path: tree.path(1)? };
+        assert!(proof.verify_did(&doc, "tx1", &root).is_err());
+        doc.queried_at_height = 8;
+        proof.verify_did(&doc, "tx1", &root)?;
+        assert!(proof.verify_did(&doc, "tx2", &root).is_err());
+        let other: Did = "did:morpheus:ez25N5WZ1Q6TQpgpyYgiu9gTX".parse()?;
+        assert!(proof
+            .verify_did(&DidDocument { did: other, ..doc.clone() }, "tx1", &root)
+            .is_err());
+
+        // Changes after the last transaction are not committed, changes before it are
+        let mut revoked_later = doc.clone();
+        revoked_later.keys[0].state.valid_until_block = Some(9);
+        revoked_later.keys[0].derived.valid = false;
+        proof.verify_did(&revoked_later, "tx1", &root)?;
+        let mut revoked_before = doc.clone();
+        revoked_before.keys[0].state.valid_until_block = Some(6);
+        assert!(proof.verify_did(&revoked_before, "tx1", &root).is_err());
+
+        let proof = StateInclusionProof { height: 6, leaf: did_leaf, path: tree.path(1)? };
+        doc.queried_at_height = 6;
+        assert!(proof.verify_did(&doc, "tx1", &root).is_err());
+        Ok(())
+    }
+}
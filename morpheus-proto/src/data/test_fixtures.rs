@@ -0,0 +1,77 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use iop_keyvault::{ed25519::EdPrivateKey, multicipher::MPrivateKey, PrivateKey as _};
+
+use crate::crypto::sign::PrivateKeySigner;
+use crate::data::claim::{Claim, Constraints};
+
+/// Resolves the given documents, and implicit documents for any other DID.
+pub struct TestResolver(HashMap<Did, DidDocument>);
+
+impl DidResolver for TestResolver {
+    fn resolve_at(&self, did: &Did, height: BlockHeight) -> Result<DidDocument> {
+        let mut doc = self.0.get(did).cloned().unwrap_or_else(|| DidDocument::implicit(did));
+        doc.queried_at_height = height;
+        Ok(doc)
+    }
+}
+
+/// Fails to resolve any DID, like a resolver whose node cannot be reached.
+pub struct UnavailableResolver;
+
+impl DidResolver for UnavailableResolver {
+    fn resolve_at(&self, did: &Did, _height: BlockHeight) -> Result<DidDocument> {
+        bail!("Document of {} is not available", did)
+    }
+}
+
+/// Implicit documents of the DIDs with the impersonation right granted to their initial key.
+pub fn resolver(dids: &[Did]) -> TestResolver {
+    let docs = dids.iter().map(|did| {
+        let mut doc = DidDocument::implicit(did);
+        let history = KeyRightHistory {
+            state: KeyRightState {
+                history: vec![KeyRightHistoryItem { height: None, valid: true }],
+            },
+            derived: KeyRightDerived { key_link: "#0".to_owned(), valid: true },
+        };
+        doc.rights.insert(Right::Impersonation, vec![history]);
+        (did.to_owned(), doc)
+    });
+    TestResolver(docs.collect())
+}
+
+pub fn private_key(idx: u8) -> MPrivateKey {
+    MPrivateKey::from(EdPrivateKey::from_bytes([idx; 32]).unwrap())
+}
+
+pub fn signer(idx: u8) -> PrivateKeySigner {
+    PrivateKeySigner::new(private_key(idx))
+}
+
+pub fn did(idx: u8) -> Did {
+    Did::from(private_key(idx).public_key().key_id())
+}
+
+pub fn witness_statement(subject: &Did, name: &str, authority: &Did) -> WitnessStatement {
+    WitnessStatement {
+        process_id: "cjuProcess".to_owned(),
+        claim: Claim {
+            subject: subject.to_owned(),
+            content: serde_json::json!({ "name": name, "address": { "city": "Budapest" } }),
+        },
+        constraints: Constraints {
+            after: None,
+            before: None,
+            witness: "iezWitness".to_owned(),
+            authority: authority.to_owned(),
+            content: serde_json::json!({}),
+        },
+        nonce: None,
+    }
+}
+
+pub fn time(rfc3339: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+}
@@ -88,4 +88,8 @@ impl ValidationResult {
     pub fn issues(&self) -> &[ValidationIssue] {
         self.issues.as_slice()
     }
+
+    pub fn merge(&mut self, other: ValidationResult) {
+        self.issues.extend(other.issues)
+    }
 }
@@ -0,0 +1,341 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+
+use crate::crypto::{hash::Content, sign::Signed};
+use crate::data::validation::{ValidationIssueSeverity as Severity, ValidationResult};
+
+/// Verifies claim presentations end to end. Documents of the presenter and of the authorities
+/// signing witness statements are resolved at `height`, licenses are checked at `time`.
+pub struct PresentationVerifier<'a> {
+    resolver: &'a dyn DidResolver,
+    presenter: Did,
+    purpose: String,
+    height: BlockHeight,
+    time: DateTime<Utc>,
+    nonce: Option<Nonce264>,
+}
+
+impl<'a> PresentationVerifier<'a> {
+    pub fn new(
+        resolver: &'a dyn DidResolver, presenter: Did, purpose: impl ToString, height: BlockHeight,
+        time: DateTime<Utc>,
+    ) -> Self {
+        let purpose = purpose.to_string();
+        Self { resolver, presenter, purpose, height, time, nonce: None }
+    }
+
+    /// Requires presentations to carry this nonce, e.g. a challenge of the verifier, so they
+    /// cannot be replayed.
+    pub fn with_nonce(mut self, nonce: Nonce264) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn verify(&self, presentation: &ClaimPresentation) -> Result<ValidationResult> {
+        let mut result = ValidationResult::default();
+        self.check_nonce(presentation, &mut result);
+        if presentation.proven_claims.is_empty() {
+            result.add_issue(Severity::Error, "Presentation has no proven claims");
+        }
+        for proven_claim in &presentation.proven_claims {
+            self.check_proven_claim(proven_claim, &mut result)?;
+        }
+        self.check_licenses(&presentation.licenses, &mut result);
+        Ok(result)
+    }
+
+    /// Like `verify`, but also checks that the presentation was signed by a key holding the
+    /// impersonation right of the presenter.
+    pub fn verify_signed(&self, signed: &Signed<ClaimPresentation>) -> Result<ValidationResult> {
+        let mut result = self.verify(signed.content())?;
+        if !signed.validate() {
+            result.add_issue(Severity::Error, "Signature of presentation is invalid");
+        }
+        self.check_signer(signed.public_key(), &self.presenter, "Presentation", &mut result);
+        Ok(result)
+    }
+
+    fn check_nonce(&self, presentation: &ClaimPresentation, result: &mut ValidationResult) {
+        if let Some(expected) = &self.nonce {
+            match &presentation.nonce {
+                Some(nonce) if nonce == expected => {}
+                Some(_) => result.add_issue(Severity::Error, "Presentation nonce does not match"),
+                None => result.add_issue(Severity::Error, "Presentation has no nonce"),
+            }
+        }
+    }
+
+    /// Statements might be masked, but they have to contain the claim, masked or not, and the
+    /// authority that signed them.
+    fn check_proven_claim(
+        &self, proven_claim: &ProvenClaim, result: &mut ValidationResult,
+    ) -> Result<()> {
+        let claim_id = digest_data(&proven_claim.claim)?;
+        if proven_claim.statements.is_empty() {
+            let reason = format!("Claim {} has no witness statements", claim_id);
+            result.add_issue(Severity::Error, &reason);
+        }
+        for statement in &proven_claim.statements {
+            let statement_id = statement.content().content_id()?;
+            if !statement.validate() {
+                let reason = format!("Signature of statement {} is invalid", statement_id);
+                result.add_issue(Severity::Error, &reason);
+            }
+
+            let content = statement.content();
+            match content.get("claim") {
+                Some(claim) if digest_data(claim)? == claim_id => {}
+                Some(_) => {
+                    let reason =
+                        format!("Statement {} is not about claim {}", statement_id, claim_id);
+                    result.add_issue(Severity::Error, &reason);
+                }
+                None => {
+                    let reason = format!("Statement {} has no claim", statement_id);
+                    result.add_issue(Severity::Error, &reason);
+                }
+            }
+
+            let authority = content
+                .get("constraints")
+                .and_then(|constraints| constraints.get("authority"))
+                .and_then(|authority| authority.as_str())
+                .and_then(|authority| authority.parse::<Did>().ok());
+            match authority {
+                Some(authority) => {
+                    let label = format!("Statement {}", statement_id);
+                    self.check_signer(statement.public_key(), &authority, &label, result);
+                }
+                None => {
+                    let reason =
+                        format!("Statement {} does not reveal its authority", statement_id);
+                    result.add_issue(Severity::Error, &reason);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A DID that cannot be resolved is reported as an issue, so the rest of the presentation is
+    /// still checked.
+    fn check_signer(
+        &self, public_key: &MPublicKey, did: &Did, label: &str, result: &mut ValidationResult,
+    ) {
+        let auth = Authentication::PublicKey(public_key.to_owned());
+        let can_impersonate = self.resolver.resolve_at(did, self.height).and_then(|doc| {
+            doc.has_right_with_resolver_at(&auth, Right::Impersonation, self.height, self.resolver)
+        });
+        match can_impersonate {
+            Ok(true) => {}
+            Ok(false) => {
+                let reason = format!(
+                    "{} was signed by {}, which cannot impersonate {} at height {}",
+                    label, public_key, did, self.height
+                );
+                result.add_issue(Severity::Error, &reason);
+            }
+            Err(e) => {
+                let reason = format!(
+                    "{} was signed by {}, but rights of {} cannot be resolved at height {}: {:#}",
+                    label, public_key, did, self.height, e
+                );
+                result.add_issue(Severity::Error, &reason);
+            }
+        }
+    }
+
+    fn check_licenses(&self, licenses: &[License], result: &mut ValidationResult) {
+        let mut covered = false;
+        let mut issued = false;
+        for license in licenses {
+            if license.issued_to != self.presenter || license.purpose != self.purpose {
+                continue;
+            }
+            issued = true;
+            let from = DateTime::parse_from_rfc3339(&license.valid_from);
+            let until = DateTime::parse_from_rfc3339(&license.valid_until);
+            match (from, until) {
+                (Ok(from), Ok(until)) => covered |= from <= self.time && self.time < until,
+                _ => {
+                    let reason = format!(
+                        "License to {} has an invalid validity period {} - {}",
+                        license.issued_to, license.valid_from, license.valid_until
+                    );
+                    result.add_issue(Severity::Error, &reason);
+                }
+            }
+        }
+        if !issued {
+            let reason = format!("No license is issued to {} for {}", self.presenter, self.purpose);
+            result.add_issue(Severity::Error, &reason);
+        } else if !covered {
+            let reason = format!(
+                "No license to {} for {} is valid at {}",
+                self.presenter,
+                self.purpose,
+                self.time.to_rfc3339()
+            );
+            result.add_issue(Severity::Error, &reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::sign::SyncMorpheusSigner;
+    use crate::data::test_fixtures::*;
+    use crate::data::validation::ValidationStatus;
+
+    fn statement(authority: &Did, subject: &Did, signer_idx: u8) -> Result<Signed<MorpheusValue>> {
+        let statement = witness_statement(subject, "Alice", authority);
+        let signed = signer(signer_idx).sign_witness_statement(statement)?;
+        let (public_key, content, signature, nonce) = signed.into_parts();
+        let content = serde_json::to_value(&content)?;
+        let masked =
+            json_digest::mask_json_subtree(&content, [".claim", ".constraints.authority"])?;
+        Ok(Signed::from_parts(public_key, masked, signature, nonce))
+    }
+
+    fn presentation(presenter: &Did, authority: &Did, signer_idx: u8) -> Result<ClaimPresentation> {
+        let statement = statement(authority, presenter, signer_idx)?;
+        let claim = statement.content()["claim"].to_owned();
+        let license = License {
+            issued_to: presenter.to_owned(),
+            purpose: "inspection".to_owned(),
+            valid_from: "2020-01-01T00:00:00Z".to_owned(),
+            valid_until: "2021-01-01T00:00:00Z".to_owned(),
+        };
+        Ok(ClaimPresentation {
+            proven_claims: vec![ProvenClaim { claim, statements: vec![statement] }],
+            licenses: vec![license],
+            nonce: None,
+        })
+    }
+
+    #[test]
+    fn valid_presentation_passes() -> Result<()> {
+        let (presenter, authority) = (did(1), did(2));
+        let resolver = resolver(&[presenter.clone(), authority.clone()]);
+        let verifier = PresentationVerifier::new(
+            &resolver,
+            presenter.clone(),
+            "inspection",
+            5,
+            time("2020-06-01T00:00:00Z"),
+        );
+
+        let presentation = presentation(&presenter, &authority, 2)?;
+        assert_eq!(verifier.verify(&presentation)?.status(), ValidationStatus::Valid);
+
+        let signed = signer(1).sign_claim_presentation(presentation.clone())?;
+        assert_eq!(verifier.verify_signed(&signed)?.status(), ValidationStatus::Valid);
+
+        let signed = signer(3).sign_claim_presentation(presentation)?;
+        assert_eq!(verifier.verify_signed(&signed)?.status(), ValidationStatus::Invalid);
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_claims_and_foreign_signers_are_rejected() -> Result<()> {
+        let (presenter, authority) = (did(1), did(2));
+        let resolver = resolver(&[presenter.clone(), authority.clone()]);
+        let verifier = PresentationVerifier::new(
+            &resolver,
+            presenter.clone(),
+            "inspection",
+            5,
+            time("2020-06-01T00:00:00Z"),
+        );
+
+        let mut tampered = presentation(&presenter, &authority, 2)?;
+        tampered.proven_claims[0].claim["content"]["name"] = serde_json::json!("Bob");
+        let result = verifier.verify(&tampered)?;
+        assert_eq!(result.status(), ValidationStatus::Invalid);
+        assert_eq!(result.issues().len(), 1);
+
+        let foreign = presentation(&presenter, &authority, 3)?;
+        assert_eq!(verifier.verify(&foreign)?.status(), ValidationStatus::Invalid);
+
+        let mut unsigned = presentation(&presenter, &authority, 2)?;
+        unsigned.proven_claims[0].statements.clear();
+        assert_eq!(verifier.verify(&unsigned)?.status(), ValidationStatus::Invalid);
+        Ok(())
+    }
+
+    #[test]
+    fn licenses_and_nonce_are_checked() -> Result<()> {
+        let (presenter, authority) = (did(1), did(2));
+        let resolver = resolver(&[presenter.clone(), authority.clone()]);
+        let presentation = presentation(&presenter, &authority, 2)?;
+
+        let expired = PresentationVerifier::new(
+            &resolver,
+            presenter.clone(),
+            "inspection",
+            5,
+            time("2021-01-01T00:00:00Z"),
+        );
+        assert_eq!(expired.verify(&presentation)?.status(), ValidationStatus::Invalid);
+
+        let other_purpose = PresentationVerifier::new(
+            &resolver,
+            presenter.clone(),
+            "marketing",
+            5,
+            time("2020-06-01T00:00:00Z"),
+        );
+        assert_eq!(other_purpose.verify(&presentation)?.status(), ValidationStatus::Invalid);
+
+        let other_presenter = PresentationVerifier::new(
+            &resolver,
+            authority.clone(),
+            "inspection",
+            5,
+            time("2020-06-01T00:00:00Z"),
+        );
+        assert_eq!(other_presenter.verify(&presentation)?.status(), ValidationStatus::Invalid);
+
+        let nonce = Nonce264::generate();
+        let with_nonce = PresentationVerifier::new(
+            &resolver,
+            presenter,
+            "inspection",
+            5,
+            time("2020-06-01T00:00:00Z"),
+        )
+        .with_nonce(nonce.clone());
+        assert_eq!(with_nonce.verify(&presentation)?.status(), ValidationStatus::Invalid);
+        let mut replayed = presentation.clone();
+        replayed.nonce = Some(Nonce264::generate());
+        assert_eq!(with_nonce.verify(&replayed)?.status(), ValidationStatus::Invalid);
+        let mut fresh = presentation;
+        fresh.nonce = Some(nonce);
+        assert_eq!(with_nonce.verify(&fresh)?.status(), ValidationStatus::Valid);
+        Ok(())
+    }
+
+    #[test]
+    fn unresolvable_signers_are_reported_as_issues() -> Result<()> {
+        let (presenter, authority) = (did(1), did(2));
+        let verifier = PresentationVerifier::new(
+            &UnavailableResolver,
+            presenter.clone(),
+            "inspection",
+            5,
+            time("2020-06-01T00:00:00Z"),
+        );
+
+        let presentation = presentation(&presenter, &authority, 2)?;
+        let result = verifier.verify(&presentation)?;
+        assert_eq!(result.status(), ValidationStatus::Invalid);
+        assert_eq!(result.issues().len(), 1);
+        assert!(result.issues()[0].reason().contains("is not available"));
+
+        let signed = signer(1).sign_claim_presentation(presentation)?;
+        assert_eq!(verifier.verify_signed(&signed)?.issues().len(), 2);
+        Ok(())
+    }
+}
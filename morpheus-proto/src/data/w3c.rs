@@ -0,0 +1,399 @@
+use super::*;
+
+use iop_keyvault::multicipher::MPublicKey;
+
+use std::collections::BTreeMap;
+
+const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+const MULTIKEY_CONTEXT: &str = "https://w3id.org/security/multikey/v1";
+/// Base IRI of the terms Morpheus adds to the DID Core vocabulary.
+const MORPHEUS_VOCABULARY: &str = "https://developer.iop.technology/glossary#";
+const MULTIKEY: &str = "Multikey";
+const MULTICIPHER_KEY_ID: &str = "MulticipherKeyId";
+const RESOLUTION_CONTEXT: &str = "https://w3id.org/did-resolution/v1";
+const DID_LD_JSON: &str = "application/did+ld+json";
+
+/// An entry of the `@context` of a W3C DID document: the URL of a published context or terms
+/// defined inline.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum W3cContext {
+    Url(String),
+    Terms(BTreeMap<String, String>),
+}
+
+impl W3cContext {
+    fn url(url: &str) -> Self {
+        Self::Url(url.to_owned())
+    }
+
+    /// Morpheus extension for keys known only by their multicipher key id (e.g. `iez...`), which
+    /// have no registered verification method type. Defines the `MulticipherKeyId` type and its
+    /// `multicipherKeyId` property.
+    fn morpheus_key_ids() -> Self {
+        let terms = [MULTICIPHER_KEY_ID, "multicipherKeyId"]
+            .iter()
+            .map(|term| (term.to_string(), format!("{}{}", MORPHEUS_VOCABULARY, term)))
+            .collect();
+        Self::Terms(terms)
+    }
+}
+
+/// A key of a DID document in the W3C DID Core format. Its id is the DID with the key link of the
+/// Morpheus document as fragment, e.g. `did:morpheus:ez...#0`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct W3cVerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub controller: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub public_key_multibase: Option<String>,
+    /// Morpheus extension for keys added only by their id, defined in `@context` by
+    /// `W3cContext::morpheus_key_ids`. Tooling can match it against the multicipher public key
+    /// attached to signatures.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub multicipher_key_id: Option<String>,
+}
+
+impl W3cVerificationMethod {
+    fn new(did: &Did, key_link: &str, auth: &Authentication) -> Option<Self> {
+        let (type_, public_key_multibase, multicipher_key_id) = match auth {
+            Authentication::PublicKey(MPublicKey::Ed25519(pk)) => {
+                (MULTIKEY, Some(multibase_key(&[0xed, 0x01], &pk.to_bytes())), None)
+            }
+            Authentication::PublicKey(MPublicKey::Secp256k1(pk)) => {
+                (MULTIKEY, Some(multibase_key(&[0xe7, 0x01], &pk.to_bytes())), None)
+            }
+            Authentication::KeyId(id) => (MULTICIPHER_KEY_ID, None, Some(id.to_string())),
+            Authentication::Did(_) => return None,
+        };
+        Some(Self {
+            id: format!("{}{}", did, key_link),
+            type_: type_.to_owned(),
+            controller: did.to_string(),
+            public_key_multibase,
+            multicipher_key_id,
+        })
+    }
+}
+
+/// Multibase encoded key bytes prefixed by their multicodec, as used by `publicKeyMultibase` of
+/// `Multikey` verification methods. Secp256k1 keys are compressed.
+fn multibase_key(multicodec: &[u8], key_bytes: &[u8]) -> String {
+    let bytes = [multicodec, key_bytes].concat();
+    multibase::encode(multibase::Base::Base58Btc, bytes)
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct W3cService {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub service_endpoint: String,
+}
+
+/// A DID document in the W3C DID Core JSON-LD format. It shows the state at the queried height
+/// without history and custom rights.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct W3cDidDocument {
+    #[serde(rename = "@context")]
+    pub context: Vec<W3cContext>,
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub controller: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub verification_method: Vec<W3cVerificationMethod>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub authentication: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub assertion_method: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub capability_invocation: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub capability_delegation: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub service: Vec<W3cService>,
+}
+
+impl DidDocument {
+    /// Exports the document in the W3C DID Core format. Keys valid at the queried height become
+    /// verification methods. Keys with the impersonation right can authenticate and make
+    /// assertions as the DID, keys with the update right can invoke and delegate capabilities,
+    /// and controller DIDs with the update right are listed as controllers. A document tombstoned
+    /// at the queried height has no keys or services.
+    pub fn to_w3c(&self) -> W3cDidDocument {
+        let mut w3c = W3cDidDocument {
+            context: vec![W3cContext::url(DID_CONTEXT)],
+            id: self.did.to_string(),
+            controller: vec![],
+            verification_method: vec![],
+            authentication: vec![],
+            assertion_method: vec![],
+            capability_invocation: vec![],
+            capability_delegation: vec![],
+            service: vec![],
+        };
+        if matches!(self.tombstoned_at_height, Some(h) if h <= self.queried_at_height) {
+            return w3c;
+        }
+
+        let holders = |right: Right| -> Vec<String> {
+            let histories = self.rights.get(&right).map(|h| h.as_slice()).unwrap_or_default();
+            histories
+                .iter()
+                .filter(|h| h.derived.valid)
+                .map(|h| h.derived.key_link.to_owned())
+                .collect()
+        };
+        let (impersonators, updaters) = (holders(Right::Impersonation), holders(Right::Update));
+        for (idx, key) in self.keys.iter().enumerate().filter(|(_, k)| k.derived.valid) {
+            let key_link = format!("#{}", idx);
+            let method =
+                match W3cVerificationMethod::new(&self.did, &key_link, &key.state.authentication) {
+                    Some(method) => method,
+                    None => {
+                        if updaters.contains(&key_link) {
+                            w3c.controller.push(key.state.authentication.to_string());
+                        }
+                        continue;
+                    }
+                };
+            let context = match key.state.authentication {
+                Authentication::KeyId(_) => W3cContext::morpheus_key_ids(),
+                _ => W3cContext::url(MULTIKEY_CONTEXT),
+            };
+            if !w3c.context.contains(&context) {
+                w3c.context.push(context);
+            }
+            if impersonators.contains(&key_link) {
+                w3c.authentication.push(method.id.to_owned());
+                w3c.assertion_method.push(method.id.to_owned());
+            }
+            if updaters.contains(&key_link) {
+                w3c.capability_invocation.push(method.id.to_owned());
+                w3c.capability_delegation.push(method.id.to_owned());
+            }
+            w3c.verification_method.push(method);
+        }
+
+        w3c.service = self
+            .services
+            .iter()
+            .map(|s| {
+                let type_ = match s.type_ {
+                    ServiceType::Messaging => "Messaging",
+                    ServiceType::Hub => "Hub",
+                };
+                W3cService {
+                    id: format!("{}#{}", self.did, s.name),
+                    type_: type_.to_owned(),
+                    service_endpoint: s.service_endpoint.to_owned(),
+                }
+            })
+            .collect();
+        w3c
+    }
+}
+
+/// Metadata about a DID document, derived from the transactions of the DID. Heights are always
+/// known, times only if the node recorded the time of those blocks.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidDocumentMetadata {
+    /// Time of the first confirmed transaction of the DID as an XML datetime.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub created: Option<String>,
+    /// Time of the last confirmed transaction of the DID at the queried height.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub updated: Option<String>,
+    pub deactivated: bool,
+    /// Id of the last confirmed transaction of the DID at the queried height. Implicit documents
+    /// without transactions have no version.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub created_at_height: Option<BlockHeight>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub updated_at_height: Option<BlockHeight>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidResolutionMetadata {
+    pub content_type: String,
+}
+
+/// A DID resolved in the format of the W3C DID Resolution specification.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidResolutionResult {
+    #[serde(rename = "@context")]
+    pub context: String,
+    pub did_document: W3cDidDocument,
+    pub did_document_metadata: DidDocumentMetadata,
+    pub did_resolution_metadata: DidResolutionMetadata,
+}
+
+impl DidResolutionResult {
+    pub fn new(doc: &DidDocument, did_document_metadata: DidDocumentMetadata) -> Self {
+        Self {
+            context: RESOLUTION_CONTEXT.to_owned(),
+            did_document: doc.to_w3c(),
+            did_document_metadata,
+            did_resolution_metadata: DidResolutionMetadata { content_type: DID_LD_JSON.to_owned() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DID: &str = "did:morpheus:ezbeWGSY2dqcUBqT8K7R14xr";
+    const CONTROLLER: &str = "did:morpheus:ez25N5WZ1Q6TQpgpyYgiu9gTX";
+
+    fn key_right(key_link: &str, valid: bool) -> serde_json::Value {
+        serde_json::json!({ "keyLink": key_link, "history": [], "valid": valid })
+    }
+
+    fn sample_doc(tombstoned: bool) -> Result<DidDocument> {
+        let doc = serde_json::json!({
+            "did": DID,
+            "keys": [
+                { "auth": "iezbeWGSY2dqcUBqT8K7R14xr", "valid": true },
+                { "auth": "pezDj6ea4tVfNRUTMyssVDepAAzPW67Fe3yHtuHL6ZNtcfJ", "valid": true },
+                { "auth": "iez25N5WZ1Q6TQpgpyYgiu9gTX", "valid": false, "validUntilHeight": 5 },
+                { "auth": CONTROLLER, "valid": true },
+            ],
+            "rights": {
+                "impersonate": [
+                    key_right("#0", true), key_right("#1", true),
+                    key_right("#2", true), key_right("#3", false),
+                ],
+                "update": [
+                    key_right("#0", true), key_right("#1", false),
+                    key_right("#2", false), key_right("#3", true),
+                ],
+                "sign-contract": [key_right("#1", true)],
+            },
+            "services": [
+                { "type": "hub", "name": "storage", "serviceEndpoint": "https://hub.example.com" },
+            ],
+            "tombstonedAtHeight": if tombstoned { Some(8) } else { None },
+            "tombstoned": tombstoned,
+            "queriedAtHeight": 8,
+        });
+        Ok(serde_json::from_value(doc)?)
+    }
+
+    #[test]
+    fn rights_become_verification_relationships() -> Result<()> {
+        let w3c = sample_doc(false)?.to_w3c();
+        let key = |idx: usize| format!("{}#{}", DID, idx);
+
+        assert_eq!(w3c.id, DID);
+        let contexts = [
+            W3cContext::url(DID_CONTEXT),
+            W3cContext::morpheus_key_ids(),
+            W3cContext::url(MULTIKEY_CONTEXT),
+        ];
+        assert_eq!(w3c.context, contexts);
+        assert_eq!(w3c.controller, vec![CONTROLLER]);
+        let method_ids: Vec<_> = w3c.verification_method.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(method_ids, vec![key(0), key(1)]);
+        assert_eq!(w3c.verification_method[0].type_, "MulticipherKeyId");
+        assert_eq!(
+            w3c.verification_method[0].multicipher_key_id.as_deref(),
+            Some("iezbeWGSY2dqcUBqT8K7R14xr")
+        );
+        assert_eq!(w3c.verification_method[1].type_, MULTIKEY);
+        let multikey = w3c.verification_method[1].public_key_multibase.as_deref().unwrap();
+        assert!(multikey.starts_with("z6Mk"));
+
+        assert_eq!(w3c.authentication, vec![key(0), key(1)]);
+        assert_eq!(w3c.assertion_method, vec![key(0), key(1)]);
+        assert_eq!(w3c.capability_invocation, vec![key(0)]);
+        assert_eq!(w3c.capability_delegation, vec![key(0)]);
+        assert_eq!(w3c.service.len(), 1);
+        assert_eq!(w3c.service[0].id, format!("{}#storage", DID));
+        assert_eq!(w3c.service[0].type_, "Hub");
+
+        let json = serde_json::to_value(&w3c)?;
+        assert_eq!(json["@context"][0], DID_CONTEXT);
+        let term = format!("{}{}", MORPHEUS_VOCABULARY, MULTICIPHER_KEY_ID);
+        assert_eq!(json["@context"][1][MULTICIPHER_KEY_ID], term);
+        assert_eq!(json["verificationMethod"][1]["publicKeyMultibase"], multikey);
+        assert_eq!(json["service"][0]["serviceEndpoint"], "https://hub.example.com");
+        Ok(())
+    }
+
+    #[test]
+    fn documents_only_use_did_core_properties_or_defined_terms() -> Result<()> {
+        const DOCUMENT_PROPERTIES: &[&str] = &[
+            "@context",
+            "id",
+            "alsoKnownAs",
+            "controller",
+            "verificationMethod",
+            "authentication",
+            "assertionMethod",
+            "keyAgreement",
+            "capabilityInvocation",
+            "capabilityDelegation",
+            "service",
+        ];
+        const METHOD_PROPERTIES: &[&str] = &["id", "type", "controller"];
+        const SERVICE_PROPERTIES: &[&str] = &["id", "type", "serviceEndpoint"];
+
+        let json = serde_json::to_value(sample_doc(false)?.to_w3c())?;
+        let keys = |value: &serde_json::Value| -> Vec<String> {
+            value.as_object().unwrap().keys().cloned().collect()
+        };
+        let contexts = json["@context"].as_array().unwrap();
+        let is_defined = |term: &str| contexts.iter().any(|c| c.get(term).is_some());
+        let is_published = |url: &str| contexts.iter().any(|c| c == url);
+
+        assert!(keys(&json).iter().all(|p| DOCUMENT_PROPERTIES.contains(&p.as_str())));
+        for method in json["verificationMethod"].as_array().unwrap() {
+            let extra: Vec<_> = keys(method)
+                .into_iter()
+                .filter(|p| !METHOD_PROPERTIES.contains(&p.as_str()))
+                .collect();
+            match method["type"].as_str().unwrap() {
+                // Multikey verification methods only have a publicKeyMultibase
+                MULTIKEY => {
+                    assert!(is_published(MULTIKEY_CONTEXT));
+                    assert_eq!(extra, vec!["publicKeyMultibase"]);
+                }
+                type_ => {
+                    assert!(is_defined(type_));
+                    assert!(extra.iter().all(|p| is_defined(p)));
+                }
+            }
+        }
+        for service in json["service"].as_array().unwrap() {
+            assert!(keys(service).iter().all(|p| SERVICE_PROPERTIES.contains(&p.as_str())));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tombstoned_documents_are_empty() -> Result<()> {
+        let doc = sample_doc(true)?;
+        let metadata = DidDocumentMetadata { deactivated: true, ..Default::default() };
+        let result = DidResolutionResult::new(&doc, metadata);
+        assert_eq!(result.did_document.id, DID);
+        assert!(result.did_document.verification_method.is_empty());
+        assert!(result.did_document.controller.is_empty());
+        assert!(result.did_document.service.is_empty());
+
+        let json = serde_json::to_value(&result)?;
+        assert_eq!(json["didDocumentMetadata"]["deactivated"], true);
+        assert_eq!(json["didResolutionMetadata"]["contentType"], DID_LD_JSON);
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ pub mod txtype;
 use anyhow::{anyhow, bail, ensure, Result};
 use serde::{Deserialize, Serialize};
 
-use iop_journal_proto::{serializer, BlockHeight};
+use iop_journal_proto::{serializer, BlockCount, BlockHeight, Timestamp};
 use iop_keyvault::{
     multicipher::{MPublicKey, MSignature},
     PublicKey,
@@ -5,6 +5,6 @@ pub use asset::*;
 pub use ops::*;
 
 use crypto::sign::SyncMorpheusSigner;
-use data::{Authentication, Did};
+use data::{Authentication, Did, RecoveryPolicy, Service, UpdatePolicy};
 
 use super::*;
@@ -7,7 +7,13 @@ pub enum OperationAttempt {
         #[serde(rename = "contentId")]
         content_id: String,
     },
+    /// Registers the Merkle root of a `BeforeProofBatch`, so all content ids in the batch are
+    /// timestamped for the fee of a single operation.
+    RegisterBeforeProofBatch {
+        root: String,
+    },
     Signed(SignedOperation),
+    MultiSigned(MultiSignedOperation),
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -42,6 +48,13 @@ impl SignableOperation {
             signature: signature.to_string(),
         })
     }
+
+    /// Signs the operations with all signers, e.g. to satisfy a threshold update policy.
+    pub fn multi_sign(self, signers: &[&dyn SyncMorpheusSigner]) -> Result<MultiSignedOperation> {
+        signers
+            .iter()
+            .try_fold(MultiSignedOperation::new(self.signables), |op, signer| op.sign(*signer))
+    }
 }
 
 // TDDO consider using strict types for public key and signature
@@ -69,6 +82,68 @@ impl SignedOperation {
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationSignature {
+    pub signer_public_key: String,
+    pub signature: String,
+}
+
+/// Operations signed by several keys. Signatures can be collected one by one, because all of them
+/// sign the same signable operations.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiSignedOperation {
+    signables: Vec<SignableOperationAttempt>,
+    pub signatures: Vec<OperationSignature>,
+}
+
+impl MultiSignedOperation {
+    pub fn new(signables: Vec<SignableOperationAttempt>) -> Self {
+        Self { signables, signatures: vec![] }
+    }
+
+    pub fn sign(mut self, signer: &dyn SyncMorpheusSigner) -> Result<Self> {
+        let (signed_with_pubkey, signature) =
+            signer.sign(&SignableOperation::to_signable_bytes(&self.signables)?)?;
+        self.signatures.push(OperationSignature {
+            signer_public_key: signed_with_pubkey.to_string(),
+            signature: signature.to_string(),
+        });
+        Ok(self)
+    }
+
+    pub fn attempts_unsafe_without_signature_checking(
+        &self,
+    ) -> std::slice::Iter<'_, SignableOperationAttempt> {
+        self.signables.iter()
+    }
+
+    /// Checks all signatures and returns the distinct keys that signed the operations.
+    pub fn signers(&self) -> Result<Vec<MPublicKey>> {
+        ensure!(!self.signatures.is_empty(), "Operations are not signed");
+        let bytes = SignableOperation::to_signable_bytes(&self.signables)?;
+        let mut signers: Vec<MPublicKey> = vec![];
+        for signed in &self.signatures {
+            let signer: MPublicKey = signed.signer_public_key.parse()?;
+            let signature: MSignature = signed.signature.parse()?;
+            ensure!(signer.verify(&bytes, &signature), "Invalid signature of {}", signer);
+            ensure!(
+                !signers.contains(&signer),
+                "Operations were signed multiple times by {}",
+                signer
+            );
+            signers.push(signer);
+        }
+        Ok(signers)
+    }
+
+    pub fn attempts(&self) -> Result<std::slice::Iter<'_, SignableOperationAttempt>> {
+        self.signers()?;
+        Ok(self.attempts_unsafe_without_signature_checking())
+    }
+}
+
 // TODO Did probably should be strongly typed, but that complicates serialization as well.
 //      Also consider using some stronger type for last_tx_id
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -92,6 +167,19 @@ pub enum SignableOperationDetails {
     RevokeKey {
         auth: Authentication,
     },
+    /// Extends or shortens the expiry of a valid key, or removes it when missing.
+    SetKeyExpiry {
+        auth: Authentication,
+        #[serde(rename = "expiresAtHeight", skip_serializing_if = "Option::is_none")]
+        expires_at_height: Option<u32>,
+    },
+    /// Makes a valid key temporarily invalid, e.g. while a device is lost.
+    SuspendKey {
+        auth: Authentication,
+    },
+    ResumeKey {
+        auth: Authentication,
+    },
     AddRight {
         auth: Authentication,
         right: String,
@@ -101,4 +189,28 @@ pub enum SignableOperationDetails {
         right: String,
     },
     TombstoneDid {},
+    AddService {
+        service: Service,
+    },
+    RemoveService {
+        name: String,
+    },
+    SetUpdatePolicy {
+        policy: UpdatePolicy,
+    },
+    SetRecoveryPolicy {
+        policy: RecoveryPolicy,
+    },
+    StartRecovery {
+        auth: Authentication,
+    },
+    CancelRecovery {},
+    CompleteRecovery {
+        auth: Authentication,
+    },
+    /// Revokes a statement issued by the DID, identified by the content id of the signed statement.
+    RevokeCredential {
+        #[serde(rename = "contentId")]
+        content_id: String,
+    },
 }
@@ -0,0 +1,153 @@
+use super::*;
+
+/// A call that changed the Morpheus state of a node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum MorpheusEvent {
+    BlockApplying {
+        height: BlockHeight,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        timestamp: Option<Timestamp>,
+    },
+    ApplyTransaction {
+        txid: String,
+        asset: MorpheusAsset,
+    },
+    /// Happens before the transactions of the block are reverted.
+    BlockReverting {
+        height: BlockHeight,
+    },
+    RevertTransaction {
+        txid: String,
+        asset: MorpheusAsset,
+    },
+    Reorganize {
+        #[serde(rename = "forkHeight")]
+        fork_height: BlockHeight,
+        #[serde(rename = "revertedBlocks")]
+        reverted_blocks: Vec<Block>,
+        #[serde(rename = "appliedBlocks")]
+        applied_blocks: Vec<Block>,
+    },
+    PruneBelow {
+        height: BlockHeight,
+    },
+    SetFinalityDepth {
+        depth: BlockCount,
+    },
+    /// Restores the latest checkpoint of a corrupted state.
+    Recover,
+}
+
+/// A call that changed the Coeus state of a node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum CoeusEvent {
+    BlockApplying {
+        height: BlockHeight,
+    },
+    ApplyTransaction {
+        txid: String,
+        asset: CoeusAsset,
+    },
+    RevertTransaction {
+        txid: String,
+        asset: CoeusAsset,
+    },
+    /// Happens after the transactions of the block were reverted.
+    BlockReverted {
+        height: BlockHeight,
+    },
+    /// Restores the latest checkpoint of a corrupted state.
+    Recover,
+}
+
+/// A line of a journal, which records the calls a node made on its states in order, so they can
+/// be replayed offline. Failed calls are recorded too, because they might have changed the state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "layer")]
+pub enum JournalEntry {
+    Morpheus(MorpheusEvent),
+    Coeus(CoeusEvent),
+}
+
+impl JournalEntry {
+    /// The height of the block the entry starts applying, if any.
+    pub fn applied_block_height(&self) -> Option<BlockHeight> {
+        match self {
+            Self::Morpheus(MorpheusEvent::BlockApplying { height, .. }) => Some(*height),
+            Self::Morpheus(MorpheusEvent::Reorganize { applied_blocks, .. }) => {
+                applied_blocks.iter().map(|b| b.height).max()
+            }
+            Self::Coeus(CoeusEvent::BlockApplying { height }) => Some(*height),
+            _ => None,
+        }
+    }
+
+    pub fn to_line(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+/// Reads a journal in JSON Lines format. Empty lines are skipped, errors tell the line number.
+pub fn read_journal(reader: impl BufRead) -> impl Iterator<Item = Result<JournalEntry>> {
+    reader.lines().enumerate().filter_map(|(idx, line_res)| {
+        let entry_res = line_res.map_err(anyhow::Error::from).and_then(|line| {
+            if line.trim().is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(serde_json::from_str(&line)?))
+        });
+        entry_res.with_context(|| format!("Invalid journal line {}", idx + 1)).transpose()
+    })
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Morpheus(event) => match event {
+                MorpheusEvent::BlockApplying { height, .. } => {
+                    write!(f, "morpheus blockApplying {}", height)
+                }
+                MorpheusEvent::ApplyTransaction { txid, .. } => {
+                    write!(f, "morpheus applyTransaction {}", txid)
+                }
+                MorpheusEvent::BlockReverting { height } => {
+                    write!(f, "morpheus blockReverting {}", height)
+                }
+                MorpheusEvent::RevertTransaction { txid, .. } => {
+                    write!(f, "morpheus revertTransaction {}", txid)
+                }
+                MorpheusEvent::Reorganize { fork_height, .. } => {
+                    write!(f, "morpheus reorganize {}", fork_height)
+                }
+                MorpheusEvent::PruneBelow { height } => write!(f, "morpheus pruneBelow {}", height),
+                MorpheusEvent::SetFinalityDepth { depth } => {
+                    write!(f, "morpheus setFinalityDepth {}", depth)
+                }
+                MorpheusEvent::Recover => write!(f, "morpheus recover"),
+            },
+            Self::Coeus(event) => match event {
+                CoeusEvent::BlockApplying { height } => write!(f, "coeus blockApplying {}", height),
+                CoeusEvent::ApplyTransaction { txid, .. } => {
+                    write!(f, "coeus applyTransaction {}", txid)
+                }
+                CoeusEvent::RevertTransaction { txid, .. } => {
+                    write!(f, "coeus revertTransaction {}", txid)
+                }
+                CoeusEvent::BlockReverted { height } => write!(f, "coeus blockReverted {}", height),
+                CoeusEvent::Recover => write!(f, "coeus recover"),
+            },
+        }
+    }
+}
+
+/// Writes entries in the JSON Lines format read by `read_journal`.
+pub fn journal_lines(entries: &[JournalEntry]) -> Result<String> {
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&entry.to_line()?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
@@ -0,0 +1,26 @@
+mod journal;
+mod replayer;
+#[cfg(test)]
+mod test;
+
+pub use journal::*;
+pub use replayer::*;
+
+// imports from standard library
+
+use std::fmt;
+use std::io::BufRead;
+
+// imports from 3rd party crates
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// imports from own crates
+
+use iop_coeus_node::State as CoeusState;
+use iop_coeus_proto::CoeusAsset;
+use iop_journal_proto::{BlockCount, BlockHeight, Timestamp};
+use iop_morpheus_node::{Block, StateHolder as MorpheusState};
+use iop_morpheus_proto::txtype::MorpheusAsset;
+use json_digest::digest_data;
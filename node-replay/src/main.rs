@@ -0,0 +1,104 @@
+//! Replays a journal recorded by a node on empty Morpheus and Coeus states, so bugs of the node can
+//! be reproduced offline.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+use anyhow::{bail, Context, Result};
+
+use iop_coeus_proto::DomainName;
+use iop_journal_proto::BlockHeight;
+use iop_node_replay::{read_journal, Replayer};
+
+const USAGE: &str = "Usage: iop-node-replay JOURNAL [--until-height HEIGHT] [--each-block] \
+                     [--did DID]... [--domain NAME]...";
+
+#[derive(Debug, Default)]
+struct Options {
+    journal: String,
+    /// Stop before applying any block above this height.
+    until_height: Option<BlockHeight>,
+    /// Print fingerprints before each block, not only at the end.
+    each_block: bool,
+    dids: Vec<String>,
+    domains: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self::default();
+        let mut journal = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().with_context(|| format!("Missing value of {}", arg));
+            match arg.as_str() {
+                "--until-height" => options.until_height = Some(value()?.parse()?),
+                "--each-block" => options.each_block = true,
+                "--did" => options.dids.push(value()?),
+                "--domain" => options.domains.push(value()?),
+                _ if arg.starts_with("--") || journal.is_some() => {
+                    bail!("Unexpected argument {}\n{}", arg, USAGE)
+                }
+                _ => journal = Some(arg),
+            }
+        }
+        options.journal = journal.with_context(|| USAGE.to_owned())?;
+        Ok(options)
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:#}", e);
+        process::exit(1);
+    }
+}
+
+fn print_fingerprint(replayer: &Replayer) -> Result<()> {
+    match replayer.fingerprint() {
+        Ok(fingerprint) => println!("{}", serde_json::to_string(&fingerprint)?),
+        Err(e) => {
+            let morpheus = replayer.morpheus().corruption_diagnostic();
+            let coeus = replayer.coeus().corruption_diagnostic();
+            eprintln!("Morpheus corruption: {}", serde_json::to_string(&morpheus)?);
+            eprintln!("Coeus corruption: {}", serde_json::to_string(&coeus)?);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let options = Options::parse(std::env::args().skip(1))?;
+    let file = File::open(&options.journal)
+        .with_context(|| format!("Cannot open journal {}", options.journal))?;
+
+    let mut replayer = Replayer::new();
+    for entry_res in read_journal(BufReader::new(file)) {
+        let entry = entry_res?;
+        if let Some(height) = entry.applied_block_height() {
+            if matches!(options.until_height, Some(until) if height > until) {
+                break;
+            }
+            if options.each_block {
+                print_fingerprint(&replayer)?;
+            }
+        }
+        // The node went on after failed calls, e.g. rejected transactions, so we do the same
+        if let Err(e) = replayer.replay(&entry) {
+            eprintln!("{} failed: {:#}", entry, e);
+        }
+    }
+    print_fingerprint(&replayer)?;
+
+    for did in &options.dids {
+        let doc = replayer.morpheus().state()?.get_doc_at(did, None)?;
+        println!("{}", serde_json::to_string_pretty(&doc)?);
+    }
+    for domain in &options.domains {
+        let name: DomainName = domain.parse()?;
+        let data = replayer.coeus().resolve_data(&name)?;
+        println!("{}", serde_json::to_string_pretty(data)?);
+    }
+    Ok(())
+}
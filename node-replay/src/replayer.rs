@@ -0,0 +1,104 @@
+use super::*;
+
+/// Fingerprints of the states, which can be compared to the ones of the node that recorded the
+/// journal at the same point.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateFingerprint {
+    pub morpheus_height: BlockHeight,
+    pub morpheus_state_root: String,
+    pub coeus_height: BlockHeight,
+    pub coeus_version: u64,
+    /// Content id of the whole Coeus domain tree.
+    pub coeus_root_digest: String,
+}
+
+/// Drives the Morpheus and Coeus states of a node with the calls of a journal. Journals are
+/// recorded from empty states, so the replay starts from empty states too.
+pub struct Replayer {
+    morpheus: MorpheusState,
+    coeus: CoeusState,
+}
+
+impl Default for Replayer {
+    fn default() -> Self {
+        Self { morpheus: MorpheusState::new(), coeus: CoeusState::new() }
+    }
+}
+
+impl Replayer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn morpheus(&self) -> &MorpheusState {
+        &self.morpheus
+    }
+
+    pub fn coeus(&self) -> &CoeusState {
+        &self.coeus
+    }
+
+    /// Makes the call recorded in the entry. Its error is returned the same way the node got it,
+    /// so the replay can go on like the node did, e.g. after a rejected transaction.
+    pub fn replay(&mut self, entry: &JournalEntry) -> Result<()> {
+        match entry {
+            JournalEntry::Morpheus(event) => self.replay_morpheus(event),
+            JournalEntry::Coeus(event) => self.replay_coeus(event),
+        }
+    }
+
+    fn replay_morpheus(&mut self, event: &MorpheusEvent) -> Result<()> {
+        let state = &mut self.morpheus;
+        match event {
+            MorpheusEvent::BlockApplying { height, timestamp: None } => {
+                state.block_applying(*height)
+            }
+            MorpheusEvent::BlockApplying { height, timestamp: Some(timestamp) } => {
+                state.block_applying_at(*height, *timestamp)
+            }
+            MorpheusEvent::ApplyTransaction { txid, asset } => state.apply_transaction(txid, asset),
+            MorpheusEvent::BlockReverting { height } => state.block_reverting(*height),
+            MorpheusEvent::RevertTransaction { txid, asset } => {
+                state.revert_transaction(txid, asset)
+            }
+            MorpheusEvent::Reorganize { fork_height, reverted_blocks, applied_blocks } => {
+                state.reorganize(*fork_height, reverted_blocks, applied_blocks)
+            }
+            MorpheusEvent::PruneBelow { height } => state.prune_below(*height),
+            MorpheusEvent::SetFinalityDepth { depth } => {
+                state.set_finality_depth(*depth);
+                Ok(())
+            }
+            MorpheusEvent::Recover => state.recover().map(|_recovery| ()),
+        }
+    }
+
+    fn replay_coeus(&mut self, event: &CoeusEvent) -> Result<()> {
+        let state = &mut self.coeus;
+        match event {
+            CoeusEvent::BlockApplying { height } => state.block_applying(*height),
+            CoeusEvent::ApplyTransaction { txid, asset } => {
+                state.apply_transaction(txid, asset.to_owned())
+            }
+            CoeusEvent::RevertTransaction { txid, asset } => {
+                state.revert_transaction(txid, asset.to_owned())
+            }
+            CoeusEvent::BlockReverted { height } => state.block_reverted(*height),
+            CoeusEvent::Recover => state.recover().map(|_recovery| ()),
+        }
+    }
+
+    /// Fails if any of the states is corrupted.
+    pub fn fingerprint(&self) -> Result<StateFingerprint> {
+        let morpheus = self.morpheus.state()?;
+        self.coeus.ensure_not_corrupted()?;
+        Ok(StateFingerprint {
+            morpheus_height: morpheus.last_seen_height(),
+            morpheus_state_root: morpheus.state_root_at(None)?,
+            coeus_height: self.coeus.last_seen_height(),
+            coeus_version: self.coeus.version(),
+            coeus_root_digest: digest_data(self.coeus.root())?,
+        })
+    }
+}
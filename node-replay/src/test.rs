@@ -0,0 +1,95 @@
+use super::*;
+
+use iop_morpheus_proto::txtype::OperationAttempt;
+
+fn before_proof(content_id: &str) -> MorpheusAsset {
+    let content_id = content_id.to_owned();
+    MorpheusAsset::new(vec![OperationAttempt::RegisterBeforeProof { content_id }])
+}
+
+fn sample_journal() -> Vec<JournalEntry> {
+    use JournalEntry::*;
+    vec![
+        Morpheus(MorpheusEvent::BlockApplying { height: 2, timestamp: Some(1000) }),
+        Morpheus(MorpheusEvent::ApplyTransaction {
+            txid: "tx1".to_owned(),
+            asset: before_proof("cjuContent1"),
+        }),
+        Coeus(CoeusEvent::BlockApplying { height: 2 }),
+        Morpheus(MorpheusEvent::BlockApplying { height: 3, timestamp: None }),
+        Morpheus(MorpheusEvent::ApplyTransaction {
+            txid: "tx2".to_owned(),
+            asset: before_proof("cjuContent2"),
+        }),
+        Coeus(CoeusEvent::BlockApplying { height: 3 }),
+        Morpheus(MorpheusEvent::BlockReverting { height: 3 }),
+        Morpheus(MorpheusEvent::RevertTransaction {
+            txid: "tx2".to_owned(),
+            asset: before_proof("cjuContent2"),
+        }),
+        Coeus(CoeusEvent::BlockReverted { height: 3 }),
+    ]
+}
+
+#[test]
+fn journal_lines_roundtrip() -> Result<()> {
+    let entry =
+        JournalEntry::Morpheus(MorpheusEvent::BlockApplying { height: 2, timestamp: Some(1000) });
+    assert_eq!(
+        entry.to_line()?,
+        r#"{"layer":"morpheus","event":"blockApplying","height":2,"timestamp":1000}"#
+    );
+    assert_eq!(entry.to_string(), "morpheus blockApplying 2");
+
+    let mut text = String::new();
+    for entry in sample_journal() {
+        text.push_str(&entry.to_line()?);
+        text.push_str("\n\n");
+    }
+    let read = read_journal(text.as_bytes()).collect::<Result<Vec<_>>>()?;
+    assert_eq!(read, sample_journal());
+
+    let err = read_journal("\n{\"layer\":\"hydra\"}".as_bytes()).next().unwrap().unwrap_err();
+    assert!(format!("{:#}", err).contains("line 2"));
+    Ok(())
+}
+
+#[test]
+fn replay_reproduces_the_states() -> Result<()> {
+    let journal = sample_journal();
+
+    let mut until_2 = Replayer::new();
+    for entry in
+        journal.iter().take_while(|e| !matches!(e.applied_block_height(), Some(h) if h > 2))
+    {
+        until_2.replay(entry)?;
+    }
+    let mut replayer = Replayer::new();
+    for entry in &journal {
+        replayer.replay(entry)?;
+    }
+    // Morpheus stays at the height of the reverted block until the next one is applied
+    let (fingerprint, expected) = (replayer.fingerprint()?, until_2.fingerprint()?);
+    assert_eq!(fingerprint.morpheus_height, 3);
+    assert_eq!(fingerprint.morpheus_state_root, expected.morpheus_state_root);
+    assert_eq!(fingerprint.coeus_height, 2);
+    assert_eq!(fingerprint.coeus_root_digest, expected.coeus_root_digest);
+
+    let state = replayer.morpheus().state()?;
+    assert!(state.before_proof_exists_at("cjuContent1", None).unwrap());
+    assert!(!state.before_proof_exists_at("cjuContent2", None).unwrap());
+
+    let revert_again = JournalEntry::Morpheus(MorpheusEvent::RevertTransaction {
+        txid: "tx2".to_owned(),
+        asset: before_proof("cjuContent2"),
+    });
+    assert!(replayer.replay(&revert_again).is_err());
+    assert!(replayer.morpheus().is_corrupted());
+
+    let recover = JournalEntry::Morpheus(MorpheusEvent::Recover);
+    assert_eq!(recover.to_line()?, r#"{"layer":"morpheus","event":"recover"}"#);
+    replayer.replay(&recover)?;
+    assert!(!replayer.morpheus().is_corrupted());
+    assert!(replayer.replay(&recover).is_err());
+    Ok(())
+}
@@ -3,6 +3,7 @@ use super::*;
 #[wasm_bindgen(js_name = CoeusState)]
 pub struct JsCoeusState {
     inner: CoeusState,
+    journal: Option<Vec<JournalEntry>>,
 }
 
 #[wasm_bindgen(js_class = CoeusState)]
@@ -10,7 +11,7 @@ impl JsCoeusState {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Result<JsCoeusState, JsValue> {
         let inner = CoeusState::new();
-        Ok(Self { inner })
+        Ok(Self::from(inner))
     }
 
     #[wasm_bindgen(js_name = resolveData)]
@@ -55,21 +56,31 @@ impl JsCoeusState {
 
     #[wasm_bindgen(js_name = applyTransaction)]
     pub fn apply_transaction(&mut self, txid: &str, asset: &JsCoeusAsset) -> Result<(), JsValue> {
+        self.record(|| CoeusEvent::ApplyTransaction {
+            txid: txid.to_owned(),
+            asset: asset.inner().to_owned(),
+        });
         self.inner.apply_transaction(txid, asset.inner().to_owned()).map_err_to_js()
     }
 
     #[wasm_bindgen(js_name = revertTransaction)]
     pub fn revert_transaction(&mut self, txid: &str, asset: &JsCoeusAsset) -> Result<(), JsValue> {
+        self.record(|| CoeusEvent::RevertTransaction {
+            txid: txid.to_owned(),
+            asset: asset.inner().to_owned(),
+        });
         self.inner.revert_transaction(txid, asset.inner().to_owned()).map_err_to_js()
     }
 
     #[wasm_bindgen(js_name = blockApplying)]
     pub fn block_applying(&mut self, height: BlockHeight) -> Result<(), JsValue> {
+        self.record(|| CoeusEvent::BlockApplying { height });
         self.inner.block_applying(height).map_err_to_js()
     }
 
     #[wasm_bindgen(js_name = blockReverted)]
     pub fn block_reverted(&mut self, height: BlockHeight) -> Result<(), JsValue> {
+        self.record(|| CoeusEvent::BlockReverted { height });
         self.inner.block_reverted(height).map_err_to_js()
     }
 
@@ -78,6 +89,23 @@ impl JsCoeusState {
         self.inner.is_corrupted()
     }
 
+    #[wasm_bindgen(js_name = corruptionDiagnostic)]
+    pub fn corruption_diagnostic(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.inner.corruption_diagnostic()).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = checkpointHeights)]
+    pub fn checkpoint_heights(&self) -> Vec<BlockHeight> {
+        self.inner.checkpoint_heights()
+    }
+
+    #[wasm_bindgen]
+    pub fn recover(&mut self) -> Result<JsValue, JsValue> {
+        self.record(|| CoeusEvent::Recover);
+        let recovery = self.inner.recover().map_err_to_js()?;
+        JsValue::from_serde(&recovery).map_err_to_js()
+    }
+
     #[wasm_bindgen(getter)]
     pub fn version(&self) -> Version {
         self.inner.version()
@@ -94,6 +122,36 @@ impl JsCoeusState {
         Ok(status.success)
     }
 
+    /// Starts recording all later calls changing the state, which can be fetched with
+    /// `takeJournal` to be replayed offline. Journals are replayed from an empty state, so recording
+    /// fails if the state already processed calls.
+    #[wasm_bindgen(js_name = recordJournal)]
+    pub fn record_journal(&mut self) -> Result<(), JsValue> {
+        if self.journal.is_none() {
+            if self.inner != CoeusState::new() {
+                return Err(JsValue::from("Journal can only be recorded from an empty state"));
+            }
+            self.journal = Some(Vec::new());
+        }
+        Ok(())
+    }
+
+    /// The calls recorded since the last call in JSON Lines format.
+    #[wasm_bindgen(js_name = takeJournal)]
+    pub fn take_journal(&mut self) -> Result<String, JsValue> {
+        let entries = match &mut self.journal {
+            Some(entries) => std::mem::take(entries),
+            None => return Err(JsValue::from("Journal is not recorded, call recordJournal first")),
+        };
+        journal_lines(&entries).map_err_to_js()
+    }
+
+    fn record(&mut self, event: impl FnOnce() -> CoeusEvent) {
+        if let Some(entries) = &mut self.journal {
+            entries.push(JournalEntry::Coeus(event()));
+        }
+    }
+
     // #[wasm_bindgen(js_name = toString)]
     // pub fn stringify(&self) -> String {
     //     self.inner.to_string()
@@ -102,7 +160,7 @@ impl JsCoeusState {
 
 impl From<CoeusState> for JsCoeusState {
     fn from(inner: CoeusState) -> Self {
-        Self { inner }
+        Self { inner, journal: None }
     }
 }
 
@@ -10,12 +10,21 @@ pub use iop_keyvault_wasm::*;
 pub use iop_proto_wasm::*;
 pub use json_digest_wasm::*;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use serde::Serialize;
 use wasm_bindgen::prelude::*;
 
 use iop_coeus_node::{State as CoeusState, Version};
 use iop_coeus_proto::*;
 use iop_journal_proto::*;
-use iop_morpheus_node::{StateHolder as MorpheusState, TransactionIdWithHeight};
-use iop_morpheus_proto::{data::DidDocument, txtype::MorpheusAsset};
+use iop_morpheus_node::{
+    Block, DidFilter, StateChange, StateHolder as MorpheusState, TransactionIdWithHeight,
+};
+use iop_morpheus_proto::{
+    data::{Authentication, BeforeProofInclusionProof, DidDocument},
+    txtype::MorpheusAsset,
+};
+use iop_node_replay::{journal_lines, CoeusEvent, JournalEntry, MorpheusEvent};
 use json_digest_wasm::MapJsError;
@@ -3,6 +3,8 @@ use super::*;
 #[wasm_bindgen(js_name = MorpheusState)]
 pub struct JsMorpheusState {
     inner: MorpheusState,
+    changes: Option<Rc<RefCell<Vec<StateChange>>>>,
+    journal: Option<Vec<JournalEntry>>,
 }
 
 #[wasm_bindgen(js_class = MorpheusState)]
@@ -10,7 +12,18 @@ impl JsMorpheusState {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Result<JsMorpheusState, JsValue> {
         let inner = MorpheusState::new();
-        Ok(Self { inner })
+        Ok(Self::from(inner))
+    }
+
+    #[wasm_bindgen(js_name = loadSnapshot)]
+    pub fn load_snapshot(snapshot: &[u8]) -> Result<JsMorpheusState, JsValue> {
+        let inner = MorpheusState::load_snapshot(snapshot).map_err_to_js()?;
+        Ok(Self::from(inner))
+    }
+
+    #[wasm_bindgen(js_name = saveSnapshot)]
+    pub fn save_snapshot(&self) -> Result<Vec<u8>, JsValue> {
+        self.inner.save_snapshot().map_err_to_js()
     }
 
     #[wasm_bindgen(getter = corrupted)]
@@ -18,6 +31,23 @@ impl JsMorpheusState {
         self.inner.is_corrupted()
     }
 
+    #[wasm_bindgen(js_name = corruptionDiagnostic)]
+    pub fn corruption_diagnostic(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.inner.corruption_diagnostic()).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = checkpointHeights)]
+    pub fn checkpoint_heights(&self) -> Vec<BlockHeight> {
+        self.inner.checkpoint_heights()
+    }
+
+    #[wasm_bindgen]
+    pub fn recover(&mut self) -> Result<JsValue, JsValue> {
+        self.record(|| MorpheusEvent::Recover);
+        let recovery = self.inner.recover().map_err_to_js()?;
+        JsValue::from_serde(&recovery).map_err_to_js()
+    }
+
     #[wasm_bindgen(js_name = lastBlockHeight)]
     pub fn last_seen_height(&self) -> Result<BlockHeight, JsValue> {
         let state = self.inner.state().map_err_to_js()?;
@@ -38,17 +68,54 @@ impl JsMorpheusState {
             Self::check_height(height)?;
         }
         let state = self.inner.state().map_err_to_js()?;
-        Ok(state.before_proof_exists_at(content_id, height_opt))
+        state.before_proof_exists_at(content_id, height_opt).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = isCredentialRevokedAt)]
+    pub fn is_credential_revoked_at(
+        &self, did: &str, content_id: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<bool, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        state.is_revoked_at(did, content_id, height_opt).map_err_to_js()
     }
 
     #[wasm_bindgen(js_name = beforeProofHistory)]
-    pub fn before_proof_history(&self, content_id: &str) -> Result<JsValue, JsValue> {
+    pub fn before_proof_history(
+        &self, content_id: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
         let state = self.inner.state().map_err_to_js()?;
-        let history = state.before_proof_history(content_id);
+        let history = state.before_proof_history(content_id, height_opt).map_err_to_js()?;
         let js_history = JsValue::from_serde(&history).map_err_to_js()?;
         Ok(js_history)
     }
 
+    /// The proof is a `BeforeProofInclusionProof` of the content id in a registered batch.
+    #[wasm_bindgen(js_name = beforeProofExistsInBatchAt)]
+    pub fn before_proof_exists_in_batch_at(
+        &self, proof: &JsValue, height_opt: Option<BlockHeight>,
+    ) -> Result<bool, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let proof: BeforeProofInclusionProof = proof.into_serde().map_err_to_js()?;
+        let state = self.inner.state().map_err_to_js()?;
+        state.before_proof_exists_in_batch_at(&proof, height_opt).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = beforeProofHistoryInBatch)]
+    pub fn before_proof_history_in_batch(&self, proof: &JsValue) -> Result<JsValue, JsValue> {
+        let proof: BeforeProofInclusionProof = proof.into_serde().map_err_to_js()?;
+        let state = self.inner.state().map_err_to_js()?;
+        let history = state.before_proof_history_in_batch(&proof).map_err_to_js()?;
+        JsValue::from_serde(&history).map_err_to_js()
+    }
+
     #[wasm_bindgen(js_name = getTransactionHistory)]
     pub fn get_tx_ids(
         &self, did: &str, include_attempts: bool, from_height_inc: BlockHeight,
@@ -60,11 +127,44 @@ impl JsMorpheusState {
         let state = self.inner.state().map_err_to_js()?;
         let js_vec_opt = state
             .get_tx_ids(did, include_attempts, from_height_inc, until_height_inc)
+            .map_err_to_js()?
             .map(|a| JsValue::from_serde(&a.collect::<Vec<_>>()))
             .unwrap_or_else(|| JsValue::from_serde(&([] as [TransactionIdWithHeight; 0])));
         js_vec_opt.map_err_to_js()
     }
 
+    #[wasm_bindgen(js_name = getTransactionHistoryPage)]
+    pub fn get_tx_ids_page(
+        &self, did: &str, include_attempts: bool, from_height_inc: BlockHeight,
+        until_height_inc: Option<BlockHeight>, cursor: Option<String>, limit: usize,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = until_height_inc {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        let page = state
+            .get_tx_ids_page(
+                did,
+                include_attempts,
+                from_height_inc,
+                until_height_inc,
+                cursor.as_deref(),
+                limit,
+            )
+            .map_err_to_js()?;
+        JsValue::from_serde(&page).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = listDids)]
+    pub fn list_dids(
+        &self, filter: &JsValue, cursor: Option<String>, limit: usize,
+    ) -> Result<JsValue, JsValue> {
+        let filter: DidFilter = filter.into_serde().map_err_to_js()?;
+        let state = self.inner.state().map_err_to_js()?;
+        let page = state.list_dids(&filter, cursor.as_deref(), limit).map_err_to_js()?;
+        JsValue::from_serde(&page).map_err_to_js()
+    }
+
     #[wasm_bindgen(js_name = lastTxId)]
     pub fn last_tx_id(&self, did: &str) -> Result<Option<String>, JsValue> {
         let state = self.inner.state().map_err_to_js()?;
@@ -85,6 +185,103 @@ impl JsMorpheusState {
         Ok(js_doc)
     }
 
+    #[wasm_bindgen(js_name = resolveDid)]
+    pub fn resolve_w3c(
+        &self, did_data: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        let resolved = state.resolve_w3c(did_data, height_opt).map_err_to_js()?;
+        JsValue::from_serde(&resolved).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = getDidDocumentDiff)]
+    pub fn get_doc_diff(
+        &self, did_data: &str, from_height: BlockHeight, until_height: BlockHeight,
+    ) -> Result<JsValue, JsValue> {
+        Self::check_height(from_height)?;
+        Self::check_height(until_height)?;
+        let state = self.inner.state().map_err_to_js()?;
+        let diff = state.get_doc_diff(did_data, from_height, until_height).map_err_to_js()?;
+        JsValue::from_serde(&diff).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = heightAtTime)]
+    pub fn height_at_time(&self, timestamp: f64) -> Result<Option<BlockHeight>, JsValue> {
+        let timestamp = Self::to_timestamp(timestamp)?;
+        let state = self.inner.state().map_err_to_js()?;
+        Ok(state.height_at_time(timestamp))
+    }
+
+    #[wasm_bindgen(js_name = getDidDocumentAtTime)]
+    pub fn get_doc_at_time(&self, did_data: &str, timestamp: f64) -> Result<JsValue, JsValue> {
+        let timestamp = Self::to_timestamp(timestamp)?;
+        let state = self.inner.state().map_err_to_js()?;
+        let doc = state.get_doc_at_time(did_data, timestamp).map_err_to_js()?;
+        JsValue::from_serde(&doc).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = getKeyUsagesAt)]
+    pub fn get_key_usages_at(
+        &self, auth: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let auth: Authentication = auth.parse().map_err_to_js()?;
+        let state = self.inner.state().map_err_to_js()?;
+        let usages = state.get_key_usages_at(&auth, height_opt).map_err_to_js()?;
+        let js_usages = JsValue::from_serde(&usages).map_err_to_js()?;
+        Ok(js_usages)
+    }
+
+    #[wasm_bindgen(js_name = getStateRoot)]
+    pub fn get_state_root(&self, height_opt: Option<BlockHeight>) -> Result<String, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        state.state_root_at(height_opt).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = getDidStateProof)]
+    pub fn get_did_state_proof(
+        &self, did_data: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        let proof = state.did_state_proof(did_data, height_opt).map_err_to_js()?;
+        JsValue::from_serde(&proof).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = getBeforeProofStateProof)]
+    pub fn get_before_proof_state_proof(
+        &self, content_id: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        let proof = state.before_proof_state_proof(content_id, height_opt).map_err_to_js()?;
+        JsValue::from_serde(&proof).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = getBeforeProofBatchStateProof)]
+    pub fn get_before_proof_batch_state_proof(
+        &self, root: &str, height_opt: Option<BlockHeight>,
+    ) -> Result<JsValue, JsValue> {
+        if let Some(height) = height_opt {
+            Self::check_height(height)?;
+        }
+        let state = self.inner.state().map_err_to_js()?;
+        let proof = state.before_proof_batch_state_proof(root, height_opt).map_err_to_js()?;
+        JsValue::from_serde(&proof).map_err_to_js()
+    }
+
     #[wasm_bindgen(js_name = dryRun)]
     pub fn dry_run(&self, asset: &JsValue) -> Result<Vec<JsValue>, JsValue> {
         let asset: MorpheusAsset = asset.into_serde().map_err_to_js()?;
@@ -102,6 +299,106 @@ impl JsMorpheusState {
         Ok(js_errs)
     }
 
+    #[wasm_bindgen(js_name = addPendingTransaction)]
+    pub fn add_pending_transaction(
+        &mut self, txid: &str, asset: &JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let asset: MorpheusAsset = asset.into_serde().map_err_to_js()?;
+        let reports = self.inner.add_pending_transaction(txid, &asset).map_err_to_js()?;
+        JsValue::from_serde(reports).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = removePendingTransaction)]
+    pub fn remove_pending_transaction(&mut self, txid: &str) -> Result<JsValue, JsValue> {
+        let reports = self.inner.remove_pending_transaction(txid).map_err_to_js()?;
+        JsValue::from_serde(reports).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = pendingTransactions)]
+    pub fn pending_transactions(&self) -> Result<JsValue, JsValue> {
+        let reports = self.inner.pending_transactions().map_err_to_js()?;
+        JsValue::from_serde(reports).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = getProjectedDidDocument)]
+    pub fn get_projected_doc(&self, did_data: &str) -> Result<JsValue, JsValue> {
+        let doc = self.inner.get_projected_doc(did_data).map_err_to_js()?;
+        JsValue::from_serde(&doc).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = projectedLastTxId)]
+    pub fn projected_last_tx_id(&self, did: &str) -> Result<Option<String>, JsValue> {
+        let last_tx_id = self.inner.projected_last_tx_id(did).map_err_to_js()?;
+        Ok(last_tx_id.map(|t| t.transaction_id.clone()))
+    }
+
+    /// Starts collecting the changes of all later state transitions, which can be fetched with
+    /// `takeChanges`.
+    #[wasm_bindgen(js_name = recordChanges)]
+    pub fn record_changes(&mut self) {
+        if self.changes.is_none() {
+            let changes = Rc::new(RefCell::new(Vec::new()));
+            let recorded = Rc::clone(&changes);
+            self.inner.subscribe(Box::new(move |change: &StateChange| {
+                recorded.borrow_mut().push(change.to_owned())
+            }));
+            self.changes = Some(changes);
+        }
+    }
+
+    #[wasm_bindgen(js_name = takeChanges)]
+    pub fn take_changes(&mut self) -> Result<JsValue, JsValue> {
+        let changes = match &self.changes {
+            Some(changes) => changes.replace(Vec::new()),
+            None => {
+                return Err(JsValue::from("Changes are not recorded, call recordChanges first"))
+            }
+        };
+        JsValue::from_serde(&changes).map_err_to_js()
+    }
+
+    /// Starts recording all later calls changing the state, which can be fetched with
+    /// `takeJournal` to be replayed offline. Journals are replayed from an empty state, so recording
+    /// fails if the state already processed calls or was loaded from a snapshot.
+    #[wasm_bindgen(js_name = recordJournal)]
+    pub fn record_journal(&mut self) -> Result<(), JsValue> {
+        if self.journal.is_none() {
+            let empty = MorpheusState::new();
+            let is_empty = matches!(
+                (self.inner.state(), empty.state()),
+                (Ok(state), Ok(empty_state)) if state == empty_state
+            ) && self.inner.finality_depth() == empty.finality_depth();
+            if !is_empty {
+                return Err(JsValue::from("Journal can only be recorded from an empty state"));
+            }
+            self.journal = Some(Vec::new());
+        }
+        Ok(())
+    }
+
+    /// The calls recorded since the last call in JSON Lines format.
+    #[wasm_bindgen(js_name = takeJournal)]
+    pub fn take_journal(&mut self) -> Result<String, JsValue> {
+        let entries = match &mut self.journal {
+            Some(entries) => std::mem::take(entries),
+            None => return Err(JsValue::from("Journal is not recorded, call recordJournal first")),
+        };
+        journal_lines(&entries).map_err_to_js()
+    }
+
+    fn record(&mut self, event: impl FnOnce() -> MorpheusEvent) {
+        if let Some(entries) = &mut self.journal {
+            entries.push(JournalEntry::Morpheus(event()));
+        }
+    }
+
+    fn to_timestamp(timestamp: f64) -> Result<Timestamp, JsValue> {
+        if !timestamp.is_finite() || timestamp.fract() != 0.0 {
+            return Err(JsValue::from(format!("Timestamp must be whole seconds: {}", timestamp)));
+        }
+        Ok(timestamp as Timestamp)
+    }
+
     fn check_height(height: BlockHeight) -> Result<(), JsValue> {
         if height > i32::MAX as u32 {
             return Err(JsValue::from(format!("Blockheight cannot be negative: {}", height)));
@@ -109,29 +406,99 @@ impl JsMorpheusState {
         Ok(())
     }
 
+    /// The optional timestamp is the time the block was forged at in seconds since the Unix epoch.
     #[wasm_bindgen(js_name = blockApplying)]
-    pub fn block_applying(&mut self, height: BlockHeight) -> Result<(), JsValue> {
+    pub fn block_applying(
+        &mut self, height: BlockHeight, timestamp: Option<f64>,
+    ) -> Result<(), JsValue> {
         Self::check_height(height)?;
-        self.inner.block_applying(height).map_err_to_js()
+        let timestamp = timestamp.map(Self::to_timestamp).transpose()?;
+        self.record(|| MorpheusEvent::BlockApplying { height, timestamp });
+        match timestamp {
+            Some(timestamp) => self.inner.block_applying_at(height, timestamp).map_err_to_js(),
+            None => self.inner.block_applying(height).map_err_to_js(),
+        }
     }
 
     #[wasm_bindgen(js_name = applyTransaction)]
     pub fn apply_transaction(&mut self, txid: &str, asset: &JsValue) -> Result<(), JsValue> {
         let asset: MorpheusAsset = asset.into_serde().map_err_to_js()?;
+        self.record(|| MorpheusEvent::ApplyTransaction {
+            txid: txid.to_owned(),
+            asset: asset.clone(),
+        });
         self.inner.apply_transaction(txid, &asset).map_err_to_js()
     }
 
     #[wasm_bindgen(js_name = blockReverting)]
     pub fn block_reverting(&mut self, height: BlockHeight) -> Result<(), JsValue> {
         Self::check_height(height)?;
+        self.record(|| MorpheusEvent::BlockReverting { height });
         self.inner.block_reverting(height).map_err_to_js()
     }
 
     #[wasm_bindgen(js_name = revertTransaction)]
     pub fn revert_transaction(&mut self, txid: &str, asset: &JsValue) -> Result<(), JsValue> {
         let asset: MorpheusAsset = asset.into_serde().map_err_to_js()?;
+        self.record(|| MorpheusEvent::RevertTransaction {
+            txid: txid.to_owned(),
+            asset: asset.clone(),
+        });
         self.inner.revert_transaction(txid, &asset).map_err_to_js()
     }
+
+    #[wasm_bindgen(js_name = revertToHeight)]
+    pub fn revert_to_height(
+        &mut self, height: BlockHeight, reverted_blocks: &JsValue,
+    ) -> Result<(), JsValue> {
+        Self::check_height(height)?;
+        let reverted_blocks: Vec<Block> = reverted_blocks.into_serde().map_err_to_js()?;
+        self.record(|| MorpheusEvent::Reorganize {
+            fork_height: height,
+            reverted_blocks: reverted_blocks.clone(),
+            applied_blocks: vec![],
+        });
+        self.inner.revert_to_height(height, &reverted_blocks).map_err_to_js()
+    }
+
+    #[wasm_bindgen]
+    pub fn reorganize(
+        &mut self, fork_height: BlockHeight, reverted_blocks: &JsValue, applied_blocks: &JsValue,
+    ) -> Result<(), JsValue> {
+        Self::check_height(fork_height)?;
+        let reverted_blocks: Vec<Block> = reverted_blocks.into_serde().map_err_to_js()?;
+        let applied_blocks: Vec<Block> = applied_blocks.into_serde().map_err_to_js()?;
+        self.record(|| MorpheusEvent::Reorganize {
+            fork_height,
+            reverted_blocks: reverted_blocks.clone(),
+            applied_blocks: applied_blocks.clone(),
+        });
+        self.inner.reorganize(fork_height, &reverted_blocks, &applied_blocks).map_err_to_js()
+    }
+
+    #[wasm_bindgen(getter = finalityDepth)]
+    pub fn finality_depth(&self) -> BlockCount {
+        self.inner.finality_depth()
+    }
+
+    #[wasm_bindgen(setter = finalityDepth)]
+    pub fn set_finality_depth(&mut self, depth: BlockCount) {
+        self.record(|| MorpheusEvent::SetFinalityDepth { depth });
+        self.inner.set_finality_depth(depth)
+    }
+
+    #[wasm_bindgen(js_name = pruneBelow)]
+    pub fn prune_below(&mut self, height: BlockHeight) -> Result<(), JsValue> {
+        Self::check_height(height)?;
+        self.record(|| MorpheusEvent::PruneBelow { height });
+        self.inner.prune_below(height).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = prunedBelowHeight)]
+    pub fn pruned_below_height(&self) -> Result<BlockHeight, JsValue> {
+        let state = self.inner.state().map_err_to_js()?;
+        Ok(state.pruned_below_height())
+    }
 }
 
 impl Wraps<MorpheusState> for JsMorpheusState {
@@ -142,6 +509,6 @@ impl Wraps<MorpheusState> for JsMorpheusState {
 
 impl From<MorpheusState> for JsMorpheusState {
     fn from(inner: MorpheusState) -> Self {
-        Self { inner }
+        Self { inner, changes: None, journal: None }
     }
 }
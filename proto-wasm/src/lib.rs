@@ -2,11 +2,13 @@ mod coeus;
 mod did;
 mod jwt;
 mod sign;
+mod state_proof;
 
 pub use coeus::*;
 pub use did::*;
 pub use jwt::*;
 pub use sign::*;
+pub use state_proof::*;
 
 // imports from standard library
 
@@ -26,7 +28,9 @@ use iop_morpheus_proto::{
         jwt::{JwtBuilder, JwtParser},
         sign::{Signable, Signed},
     },
-    data::{Did, ValidationIssue, ValidationResult},
+    data::{
+        BeforeProofBatch, Did, DidDocument, StateInclusionProof, ValidationIssue, ValidationResult,
+    },
 };
 
 pub use iop_keyvault_wasm::*;
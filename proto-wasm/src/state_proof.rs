@@ -0,0 +1,94 @@
+use super::*;
+
+#[wasm_bindgen(js_name = StateInclusionProof)]
+pub struct JsStateInclusionProof {
+    inner: StateInclusionProof,
+}
+
+#[wasm_bindgen(js_class = StateInclusionProof)]
+impl JsStateInclusionProof {
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: &JsValue) -> Result<JsStateInclusionProof, JsValue> {
+        let inner: StateInclusionProof = data.into_serde().map_err_to_js()?;
+        Ok(inner.into())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> BlockHeight {
+        self.inner.height
+    }
+
+    pub fn root(&self) -> Result<String, JsValue> {
+        self.inner.root().map_err_to_js()
+    }
+
+    /// Takes the DID document as JSON, queried at the height of the proof.
+    #[wasm_bindgen(js_name = verifyDid)]
+    pub fn verify_did(
+        &self, did_doc: &JsValue, last_tx_id: &str, trusted_root: &str,
+    ) -> Result<(), JsValue> {
+        let did_doc: DidDocument = did_doc.into_serde().map_err_to_js()?;
+        self.inner.verify_did(&did_doc, last_tx_id, trusted_root).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = verifyBeforeProof)]
+    pub fn verify_before_proof(&self, content_id: &str, trusted_root: &str) -> Result<(), JsValue> {
+        self.inner.verify_before_proof(content_id, trusted_root).map_err_to_js()
+    }
+
+    #[wasm_bindgen(js_name = toJson)]
+    pub fn to_json(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.inner).map_err_to_js()
+    }
+}
+
+impl From<StateInclusionProof> for JsStateInclusionProof {
+    fn from(inner: StateInclusionProof) -> Self {
+        Self { inner }
+    }
+}
+
+impl Wraps<StateInclusionProof> for JsStateInclusionProof {
+    fn inner(&self) -> &StateInclusionProof {
+        &self.inner
+    }
+}
+
+#[wasm_bindgen(js_name = BeforeProofBatch)]
+pub struct JsBeforeProofBatch {
+    inner: BeforeProofBatch,
+}
+
+#[wasm_bindgen(js_class = BeforeProofBatch)]
+impl JsBeforeProofBatch {
+    /// Takes an array of content ids.
+    #[wasm_bindgen(constructor)]
+    pub fn new(content_ids: &JsValue) -> Result<JsBeforeProofBatch, JsValue> {
+        let content_ids: Vec<String> = content_ids.into_serde().map_err_to_js()?;
+        let inner = BeforeProofBatch::new(content_ids).map_err_to_js()?;
+        Ok(inner.into())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn root(&self) -> String {
+        self.inner.root()
+    }
+
+    /// Returns a `BeforeProofInclusionProof` of the content id as JSON.
+    pub fn proof(&self, content_id: &str) -> Result<JsValue, JsValue> {
+        let proof = self.inner.proof(content_id).map_err_to_js()?;
+        JsValue::from_serde(&proof).map_err_to_js()
+    }
+}
+
+impl From<BeforeProofBatch> for JsBeforeProofBatch {
+    fn from(inner: BeforeProofBatch) -> Self {
+        Self { inner }
+    }
+}
+
+impl Wraps<BeforeProofBatch> for JsBeforeProofBatch {
+    fn inner(&self) -> &BeforeProofBatch {
+        &self.inner
+    }
+}
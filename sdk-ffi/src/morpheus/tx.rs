@@ -69,6 +69,64 @@ pub extern "C" fn MorpheusOperationBuilder_revoke_key(
     cresult(fun())
 }
 
+#[no_mangle]
+pub extern "C" fn MorpheusOperationBuilder_set_key_expiry(
+    builder: *mut MorpheusOperationBuilder, authentication: *const raw::c_char,
+    expires_at_height: BlockHeight,
+) -> CPtrResult<SignableOperationAttempt> {
+    let fun = || {
+        let builder = unsafe { convert::borrow_in(builder) };
+        let auth = unsafe { convert::str_in(authentication) }?.parse()?;
+        let expires_at_height = if expires_at_height == 0 { None } else { Some(expires_at_height) };
+        let operation = SignableOperationDetails::SetKeyExpiry { auth, expires_at_height };
+        let attempt = builder.op_to_attempt(operation);
+        Ok(convert::move_out(attempt))
+    };
+    cresult(fun())
+}
+
+#[no_mangle]
+pub extern "C" fn MorpheusOperationBuilder_suspend_key(
+    builder: *mut MorpheusOperationBuilder, authentication: *const raw::c_char,
+) -> CPtrResult<SignableOperationAttempt> {
+    let fun = || {
+        let builder = unsafe { convert::borrow_in(builder) };
+        let auth = unsafe { convert::str_in(authentication) }?.parse()?;
+        let operation = SignableOperationDetails::SuspendKey { auth };
+        let attempt = builder.op_to_attempt(operation);
+        Ok(convert::move_out(attempt))
+    };
+    cresult(fun())
+}
+
+#[no_mangle]
+pub extern "C" fn MorpheusOperationBuilder_resume_key(
+    builder: *mut MorpheusOperationBuilder, authentication: *const raw::c_char,
+) -> CPtrResult<SignableOperationAttempt> {
+    let fun = || {
+        let builder = unsafe { convert::borrow_in(builder) };
+        let auth = unsafe { convert::str_in(authentication) }?.parse()?;
+        let operation = SignableOperationDetails::ResumeKey { auth };
+        let attempt = builder.op_to_attempt(operation);
+        Ok(convert::move_out(attempt))
+    };
+    cresult(fun())
+}
+
+#[no_mangle]
+pub extern "C" fn MorpheusOperationBuilder_revoke_credential(
+    builder: *mut MorpheusOperationBuilder, content_id: *const raw::c_char,
+) -> CPtrResult<SignableOperationAttempt> {
+    let fun = || {
+        let builder = unsafe { convert::borrow_in(builder) };
+        let content_id = unsafe { convert::str_in(content_id) }?.to_owned();
+        let operation = SignableOperationDetails::RevokeCredential { content_id };
+        let attempt = builder.op_to_attempt(operation);
+        Ok(convert::move_out(attempt))
+    };
+    cresult(fun())
+}
+
 #[no_mangle]
 pub extern "C" fn MorpheusOperationBuilder_add_right(
     builder: *mut MorpheusOperationBuilder, authentication: *const raw::c_char,
@@ -210,6 +268,20 @@ pub extern "C" fn MorpheusAssetBuilder_add_register_before_proof(
     cresult_void(fun())
 }
 
+#[no_mangle]
+pub extern "C" fn MorpheusAssetBuilder_add_register_before_proof_batch(
+    builder: *mut MorpheusAssetBuilder, root: *const raw::c_char,
+) -> CPtrResult<raw::c_void> {
+    let fun = || {
+        let builder = unsafe { convert::borrow_mut_in(builder) };
+        let root = unsafe { convert::str_in(root) }?;
+        let batch = OperationAttempt::RegisterBeforeProofBatch { root: root.to_owned() };
+        builder.op_attempts.push(batch);
+        Ok(())
+    };
+    cresult_void(fun())
+}
+
 #[no_mangle]
 pub extern "C" fn MorpheusAssetBuilder_build(
     builder: *mut MorpheusAssetBuilder,
@@ -45,6 +45,12 @@ impl JsMorpheusAssetBuilder {
         self.op_attempts.push(before_proof);
     }
 
+    #[wasm_bindgen(js_name = addRegisterBeforeProofBatch)]
+    pub fn add_register_before_proof_batch(&mut self, root: &str) {
+        let batch = OperationAttempt::RegisterBeforeProofBatch { root: root.to_owned() };
+        self.op_attempts.push(batch);
+    }
+
     #[wasm_bindgen(js_name = addSigned)]
     pub fn add_signed(&mut self, signed_operation: &JsMorpheusSignedOperation) {
         self.op_attempts.push(OperationAttempt::Signed(signed_operation.inner.to_owned()));
@@ -112,6 +118,41 @@ impl JsMorpheusOperationBuilder {
         self.to_attempt(operation)
     }
 
+    #[wasm_bindgen(js_name = setKeyExpiry)]
+    pub fn set_key_expiry(
+        &self, authentication: &str, expires_at_height: JsValue,
+    ) -> Result<JsMorpheusSignableOperation, JsValue> {
+        let auth = Authentication::from_str(authentication).map_err_to_js()?;
+        let expires_at_height = expires_at_height.into_serde().map_err_to_js()?;
+        let operation = SignableOperationDetails::SetKeyExpiry { auth, expires_at_height };
+        self.to_attempt(operation)
+    }
+
+    #[wasm_bindgen(js_name = suspendKey)]
+    pub fn suspend_key(
+        &self, authentication: &str,
+    ) -> Result<JsMorpheusSignableOperation, JsValue> {
+        let auth = Authentication::from_str(authentication).map_err_to_js()?;
+        let operation = SignableOperationDetails::SuspendKey { auth };
+        self.to_attempt(operation)
+    }
+
+    #[wasm_bindgen(js_name = resumeKey)]
+    pub fn resume_key(&self, authentication: &str) -> Result<JsMorpheusSignableOperation, JsValue> {
+        let auth = Authentication::from_str(authentication).map_err_to_js()?;
+        let operation = SignableOperationDetails::ResumeKey { auth };
+        self.to_attempt(operation)
+    }
+
+    #[wasm_bindgen(js_name = revokeCredential)]
+    pub fn revoke_credential(
+        &self, content_id: &str,
+    ) -> Result<JsMorpheusSignableOperation, JsValue> {
+        let operation =
+            SignableOperationDetails::RevokeCredential { content_id: content_id.to_owned() };
+        self.to_attempt(operation)
+    }
+
     #[wasm_bindgen(js_name = addRight)]
     pub fn add_right(
         &self, authentication: &str, right: &str,